@@ -0,0 +1,35 @@
+#[cfg(feature = "header")]
+extern crate cbindgen;
+
+
+// `cargo build --features header` generates parallel_image_crop.h from the
+// #[no_mangle] exports into OUT_DIR, where config's test_header_is_current
+// compares it with the checked in header. UPDATE_HEADER=1 also overwrites the
+// checked in one. plain builds leave both alone
+
+#[cfg(feature = "header")]
+fn generate_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("could not read cbindgen.toml");
+    let header = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("could not generate the C header");
+
+    header.write_to_file(format!("{}/parallel_image_crop.h", out_dir));
+    if std::env::var("UPDATE_HEADER").map(|v| v == "1").unwrap_or(false) {
+        header.write_to_file(format!("{}/parallel_image_crop.h", crate_dir));
+    }
+}
+
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=UPDATE_HEADER");
+    #[cfg(feature = "header")]
+    generate_header();
+}
@@ -0,0 +1,517 @@
+#ifndef PARALLEL_IMAGE_CROP_H
+#define PARALLEL_IMAGE_CROP_H
+
+/* generated by cbindgen from the #[no_mangle] exports, regenerate with `cargo build --features header` */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+#ifndef DLPACK_VERSION
+/* the DLPack v0.8 types, include dlpack/dlpack.h before this header to use its own */
+typedef struct DLDevice {
+  int32_t device_type;
+  int32_t device_id;
+} DLDevice;
+
+typedef struct DLDataType {
+  uint8_t code;
+  uint8_t bits;
+  uint16_t lanes;
+} DLDataType;
+
+typedef struct DLTensor {
+  void *data;
+  DLDevice device;
+  int32_t ndim;
+  DLDataType dtype;
+  int64_t *shape;
+  int64_t *strides;
+  uint64_t byte_offset;
+} DLTensor;
+
+typedef struct DLManagedTensor {
+  DLTensor dl_tensor;
+  void *manager_ctx;
+  void (*deleter)(struct DLManagedTensor *self);
+} DLManagedTensor;
+#endif
+
+
+#define PARALLEL_IMAGE_CROP_API_VERSION 6
+
+#define CROP_BATCH_PENDING 0
+
+#define CROP_BATCH_RUNNING 1
+
+#define CROP_BATCH_DONE 2
+
+#define CROP_BATCH_CANCELLED 3
+
+#define CROP_BATCH_FAILED 4
+
+#define CROP_BATCH_UNKNOWN -1
+
+typedef struct CropConfig {
+  size_t size;
+  uint64_t num_threads;
+  bool use_vips;
+  uint32_t window_size;
+  uint32_t chans;
+  float max_img_percent;
+  uint32_t filter;
+  uint32_t dtype;
+  bool exif_orientation;
+} CropConfig;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+void *initialize(uint64_t num_threads, bool use_vips);
+
+void destroy(void *crop_manager_ptr);
+
+uint32_t api_version(void);
+
+void crop_config_init(struct CropConfig *config_ptr);
+
+void *initialize_with_config(const struct CropConfig *config_ptr);
+
+void set_exif_orientation(bool enabled);
+
+size_t probe_images(const void *crop_manager_ptr,
+                    const char *const *image_paths_ptr,
+                    uint32_t *shapes_ptr,
+                    uint8_t *ok_ptr,
+                    size_t length);
+
+void parallel_crop_and_resize(const void *crop_manager_ptr,
+                              const char *const *image_paths_ptr,
+                              uint8_t *return_ptr,
+                              const float *scale_ptr,
+                              const float *x_ptr,
+                              const float *y_ptr,
+                              uint32_t window_size,
+                              uint32_t chans,
+                              float max_img_percent,
+                              size_t length);
+
+void parallel_crop_and_resize_subpixel(const void *crop_manager_ptr,
+                                       const char *const *image_paths_ptr,
+                                       uint8_t *return_ptr,
+                                       const float *scale_ptr,
+                                       const float *x_ptr,
+                                       const float *y_ptr,
+                                       uint32_t window_size,
+                                       uint32_t chans,
+                                       float max_img_percent,
+                                       size_t length);
+
+void parallel_crop_and_resize_augment(const void *crop_manager_ptr,
+                                      const char *const *image_paths_ptr,
+                                      uint8_t *return_ptr,
+                                      const float *scale_ptr,
+                                      const float *x_ptr,
+                                      const float *y_ptr,
+                                      uint32_t window_size,
+                                      uint32_t chans,
+                                      float max_img_percent,
+                                      const uint8_t *flags_ptr,
+                                      const float *params_ptr,
+                                      const uint32_t *rects_ptr,
+                                      uint32_t fill_mode,
+                                      uint8_t fill_value,
+                                      uint64_t seed,
+                                      size_t length);
+
+void parallel_crop_and_resize_geometric(const void *crop_manager_ptr,
+                                        const char *const *image_paths_ptr,
+                                        uint8_t *return_ptr,
+                                        const float *scale_ptr,
+                                        const float *x_ptr,
+                                        const float *y_ptr,
+                                        uint32_t window_size,
+                                        uint32_t chans,
+                                        float max_img_percent,
+                                        uint8_t *flags_ptr,
+                                        bool sample,
+                                        float hflip_prob,
+                                        float vflip_prob,
+                                        float rot90_prob,
+                                        uint64_t seed,
+                                        size_t length);
+
+void parallel_crop_and_resize_photometric(const void *crop_manager_ptr,
+                                          const char *const *image_paths_ptr,
+                                          uint8_t *return_ptr,
+                                          const float *scale_ptr,
+                                          const float *x_ptr,
+                                          const float *y_ptr,
+                                          uint32_t window_size,
+                                          uint32_t chans,
+                                          float max_img_percent,
+                                          float *params_ptr,
+                                          bool sample,
+                                          float brightness,
+                                          float contrast,
+                                          float saturation,
+                                          float hue,
+                                          float grayscale_prob,
+                                          float blur_prob,
+                                          float blur_sigma_min,
+                                          float blur_sigma_max,
+                                          uint64_t seed,
+                                          size_t length);
+
+void parallel_crop_and_resize_erasing(const void *crop_manager_ptr,
+                                      const char *const *image_paths_ptr,
+                                      uint8_t *return_ptr,
+                                      const float *scale_ptr,
+                                      const float *x_ptr,
+                                      const float *y_ptr,
+                                      uint32_t window_size,
+                                      uint32_t chans,
+                                      float max_img_percent,
+                                      uint32_t *rects_ptr,
+                                      float prob,
+                                      float scale_min,
+                                      float scale_max,
+                                      float ratio_min,
+                                      float ratio_max,
+                                      uint32_t fill_mode,
+                                      uint8_t fill_value,
+                                      uint64_t seed,
+                                      size_t length);
+
+void parallel_crop_and_resize_paired(const void *crop_manager_ptr,
+                                     const char *const *const *stream_paths_ptr,
+                                     void *const *stream_returns_ptr,
+                                     const uint32_t *stream_chans_ptr,
+                                     const uint32_t *stream_filters_ptr,
+                                     const uint32_t *stream_dtypes_ptr,
+                                     uint32_t num_streams,
+                                     const float *scale_ptr,
+                                     const float *x_ptr,
+                                     const float *y_ptr,
+                                     uint32_t window_size,
+                                     float max_img_percent,
+                                     size_t length);
+
+void parallel_crop_and_resize_with_config(const void *crop_manager_ptr,
+                                          const struct CropConfig *config_ptr,
+                                          const char *const *image_paths_ptr,
+                                          void *return_ptr,
+                                          const float *scale_ptr,
+                                          const float *x_ptr,
+                                          const float *y_ptr,
+                                          size_t length);
+
+DLManagedTensor *parallel_crop_and_resize_dlpack(const void *crop_manager_ptr,
+                                                 const struct CropConfig *config_ptr,
+                                                 const char *const *image_paths_ptr,
+                                                 const float *scale_ptr,
+                                                 const float *x_ptr,
+                                                 const float *y_ptr,
+                                                 uint32_t layout,
+                                                 size_t length);
+
+uint64_t submit_crop_batch(const void *crop_manager_ptr,
+                           const struct CropConfig *config_ptr,
+                           const char *const *image_paths_ptr,
+                           void *return_ptr,
+                           const float *scale_ptr,
+                           const float *x_ptr,
+                           const float *y_ptr,
+                           size_t length);
+
+int32_t poll_crop_batch(const void *crop_manager_ptr, uint64_t job_id);
+
+int32_t wait_crop_batch(const void *crop_manager_ptr, uint64_t job_id, int64_t timeout_ms);
+
+bool cancel_crop_batch(const void *crop_manager_ptr, uint64_t job_id);
+
+void parallel_crop_and_resize_u16(const void *crop_manager_ptr,
+                                  const char *const *image_paths_ptr,
+                                  uint16_t *return_ptr,
+                                  const float *scale_ptr,
+                                  const float *x_ptr,
+                                  const float *y_ptr,
+                                  uint32_t window_size,
+                                  uint32_t chans,
+                                  float max_img_percent,
+                                  size_t length);
+
+void parallel_crop_and_resize_f32(const void *crop_manager_ptr,
+                                  const char *const *image_paths_ptr,
+                                  float *return_ptr,
+                                  const float *scale_ptr,
+                                  const float *x_ptr,
+                                  const float *y_ptr,
+                                  uint32_t window_size,
+                                  uint32_t chans,
+                                  float max_img_percent,
+                                  size_t length);
+
+void parallel_crop_and_resize_windowed(const void *crop_manager_ptr,
+                                       const char *const *image_paths_ptr,
+                                       uint8_t *return_ptr,
+                                       const float *scale_ptr,
+                                       const float *x_ptr,
+                                       const float *y_ptr,
+                                       uint32_t window_size,
+                                       uint32_t chans,
+                                       float max_img_percent,
+                                       uint32_t window_mode,
+                                       float low,
+                                       float high,
+                                       size_t length);
+
+void parallel_crop_and_resize_frames(const void *crop_manager_ptr,
+                                     const char *const *image_paths_ptr,
+                                     uint8_t *return_ptr,
+                                     const float *scale_ptr,
+                                     const float *x_ptr,
+                                     const float *y_ptr,
+                                     uint32_t window_size,
+                                     uint32_t chans,
+                                     float max_img_percent,
+                                     const uint32_t *frames_ptr,
+                                     size_t length);
+
+void parallel_crop_and_resize_tiled(const void *crop_manager_ptr,
+                                    const char *const *image_paths_ptr,
+                                    uint8_t *return_ptr,
+                                    const float *scale_ptr,
+                                    const float *x_ptr,
+                                    const float *y_ptr,
+                                    uint32_t window_size,
+                                    uint32_t chans,
+                                    float max_img_percent,
+                                    size_t length);
+
+void parallel_crop_boxes_and_resize(const void *crop_manager_ptr,
+                                    const char *const *image_paths_ptr,
+                                    uint8_t *return_ptr,
+                                    const float *boxes_ptr,
+                                    bool normalized,
+                                    float padding,
+                                    bool letterbox,
+                                    uint32_t window_size,
+                                    uint32_t chans,
+                                    size_t length);
+
+void parallel_affine_crop(const void *crop_manager_ptr,
+                          const char *const *image_paths_ptr,
+                          uint8_t *return_ptr,
+                          const float *matrices_ptr,
+                          uint32_t border,
+                          float fill,
+                          uint32_t window_size,
+                          uint32_t chans,
+                          size_t length);
+
+void parallel_rotated_crop(const void *crop_manager_ptr,
+                           const char *const *image_paths_ptr,
+                           uint8_t *return_ptr,
+                           const float *rboxes_ptr,
+                           uint32_t border,
+                           float fill,
+                           uint32_t window_size,
+                           uint32_t chans,
+                           size_t length);
+
+void parallel_perspective_crop(const void *crop_manager_ptr,
+                               const char *const *image_paths_ptr,
+                               uint8_t *return_ptr,
+                               const float *corners_ptr,
+                               uint32_t border,
+                               float fill,
+                               uint32_t window_size,
+                               uint32_t chans,
+                               size_t length);
+
+void parallel_random_crop_and_resize(const void *crop_manager_ptr,
+                                     const char *const *image_paths_ptr,
+                                     uint8_t *return_ptr,
+                                     float *params_ptr,
+                                     uint32_t mode,
+                                     float scale_min,
+                                     float scale_max,
+                                     float ratio_min,
+                                     float ratio_max,
+                                     uint64_t seed,
+                                     uint32_t window_size,
+                                     uint32_t chans,
+                                     size_t length);
+
+void assemble_patches_u8(const void *crop_manager_ptr,
+                         const uint8_t *patches_ptr,
+                         const int32_t *x_ptr,
+                         const int32_t *y_ptr,
+                         uint32_t patch_width,
+                         uint32_t patch_height,
+                         uint32_t chans,
+                         uint32_t image_width,
+                         uint32_t image_height,
+                         bool feather,
+                         uint8_t *return_ptr,
+                         size_t length);
+
+void assemble_patches_f32(const void *crop_manager_ptr,
+                          const float *patches_ptr,
+                          const int32_t *x_ptr,
+                          const int32_t *y_ptr,
+                          uint32_t patch_width,
+                          uint32_t patch_height,
+                          uint32_t chans,
+                          uint32_t image_width,
+                          uint32_t image_height,
+                          bool feather,
+                          float *return_ptr,
+                          size_t length);
+
+void mix_batch_u8(const void *crop_manager_ptr,
+                  uint8_t *batch_ptr,
+                  uint32_t *perm_ptr,
+                  float *lambda_ptr,
+                  uint32_t mode,
+                  bool sample,
+                  float alpha,
+                  uint64_t seed,
+                  uint32_t window_size,
+                  uint32_t chans,
+                  size_t length);
+
+void mix_batch_f32(const void *crop_manager_ptr,
+                   float *batch_ptr,
+                   uint32_t *perm_ptr,
+                   float *lambda_ptr,
+                   uint32_t mode,
+                   bool sample,
+                   float alpha,
+                   uint64_t seed,
+                   uint32_t window_size,
+                   uint32_t chans,
+                   size_t length);
+
+void transform_annotations(const void *crop_manager_ptr,
+                           const char *const *image_paths_ptr,
+                           const float *scale_ptr,
+                           const float *x_ptr,
+                           const float *y_ptr,
+                           uint32_t window_size,
+                           float max_img_percent,
+                           const uint8_t *flags_ptr,
+                           const uint32_t *num_boxes_ptr,
+                           float *boxes_ptr,
+                           uint8_t *box_valid_ptr,
+                           const uint32_t *num_points_ptr,
+                           float *points_ptr,
+                           uint8_t *point_visible_ptr,
+                           size_t length);
+
+void transform_annotations_affine(const void *crop_manager_ptr,
+                                  const float *matrices_ptr,
+                                  uint32_t window_size,
+                                  const uint32_t *num_boxes_ptr,
+                                  float *boxes_ptr,
+                                  uint8_t *box_valid_ptr,
+                                  const uint32_t *num_points_ptr,
+                                  float *points_ptr,
+                                  uint8_t *point_visible_ptr,
+                                  size_t length);
+
+void transform_annotations_boxes(const void *crop_manager_ptr,
+                                 const char *const *image_paths_ptr,
+                                 const float *crop_boxes_ptr,
+                                 bool normalized,
+                                 float padding,
+                                 bool letterbox,
+                                 uint32_t window_size,
+                                 const uint32_t *num_boxes_ptr,
+                                 float *boxes_ptr,
+                                 uint8_t *box_valid_ptr,
+                                 const uint32_t *num_points_ptr,
+                                 float *points_ptr,
+                                 uint8_t *point_visible_ptr,
+                                 size_t length);
+
+void transform_annotations_random(const void *crop_manager_ptr,
+                                  const char *const *image_paths_ptr,
+                                  const float *params_ptr,
+                                  uint32_t window_size,
+                                  const uint32_t *num_boxes_ptr,
+                                  float *boxes_ptr,
+                                  uint8_t *box_valid_ptr,
+                                  const uint32_t *num_points_ptr,
+                                  float *points_ptr,
+                                  uint8_t *point_visible_ptr,
+                                  size_t length);
+
+size_t parallel_crop_and_save(const void *crop_manager_ptr,
+                              const char *const *image_paths_ptr,
+                              const char *output_template_ptr,
+                              uint32_t format,
+                              uint32_t quality,
+                              uint8_t *written_ptr,
+                              const float *scale_ptr,
+                              const float *x_ptr,
+                              const float *y_ptr,
+                              uint32_t window_size,
+                              float max_img_percent,
+                              size_t length);
+
+size_t parallel_crop_and_save_with_config(const void *crop_manager_ptr,
+                                          const struct CropConfig *config_ptr,
+                                          const char *const *image_paths_ptr,
+                                          const char *output_template_ptr,
+                                          uint32_t format,
+                                          uint32_t quality,
+                                          uint8_t *written_ptr,
+                                          const float *scale_ptr,
+                                          const float *x_ptr,
+                                          const float *y_ptr,
+                                          size_t length);
+
+bool write_npy(const char *path_ptr,
+               const void *data_ptr,
+               uint32_t dtype,
+               uint32_t layout,
+               size_t n,
+               uint32_t h,
+               uint32_t w,
+               uint32_t c);
+
+bool write_npz(const char *path_ptr,
+               uint32_t num_arrays,
+               const char *const *names_ptr,
+               const void *const *data_ptrs,
+               const uint32_t *dtypes_ptr,
+               const uint32_t *shapes_ptr,
+               uint32_t layout);
+
+bool create_npy_memmap(const char *path_ptr,
+                       uint32_t dtype,
+                       uint32_t layout,
+                       size_t n,
+                       uint32_t h,
+                       uint32_t w,
+                       uint32_t c);
+
+bool write_npy_rows(const char *path_ptr,
+                    const void *data_ptr,
+                    const uint64_t *indices_ptr,
+                    uint32_t dtype,
+                    uint32_t layout,
+                    size_t length,
+                    uint32_t h,
+                    uint32_t w,
+                    uint32_t c);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* PARALLEL_IMAGE_CROP_H */
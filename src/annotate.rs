@@ -0,0 +1,243 @@
+use std::slice;
+use rayon::ThreadPool;
+use rayon::prelude::*;
+use libc::size_t;
+use warp::Affine;
+use augment::GeometricAug;
+use boxes;
+use boxes::{CropBox, BoxUnits, ResizeMode};
+
+
+// maps per-item annotations (boxes and points, polygons being lists of points)
+// through the same geometry the backends apply to the pixels.
+//
+// unlike warp.rs the transforms here are *forward* maps from source to
+// window co-ordinates, using the same continuous convention where pixel i
+// spans [i, i + 1)
+
+
+pub fn map_point(forward: &Affine, x: f32, y: f32) -> (f32, f32) {
+    (forward[0] * x + forward[1] * y + forward[2],
+     forward[3] * x + forward[4] * y + forward[5])
+}
+
+
+pub fn compose(first: &Affine, then: &Affine) -> Affine {
+    // the transform that applies first and then then
+    [then[0] * first[0] + then[1] * first[3],
+     then[0] * first[1] + then[1] * first[4],
+     then[0] * first[2] + then[1] * first[5] + then[2],
+     then[3] * first[0] + then[4] * first[3],
+     then[3] * first[1] + then[4] * first[4],
+     then[3] * first[2] + then[4] * first[5] + then[5]]
+}
+
+
+pub fn crop_transform(img_size: (u32, u32), scale: f32, x_crop: f32, y_crop: f32,
+                      max_img_percent: f32, window_size: (u32, u32)) -> Affine
+{
+    // the crop region of parallel_crop_and_resize stretched onto the window
+    let (x, y, width, height) = super::crop_region(img_size, scale, x_crop, y_crop, max_img_percent);
+    let (sx, sy) = (window_size.0 as f32 / width as f32, window_size.1 as f32 / height as f32);
+    [sx, 0.0, -sx * x as f32,
+     0.0, sy, -sy * y as f32]
+}
+
+
+pub fn geometric_transform(aug: &GeometricAug, window_size: (u32, u32)) -> Affine {
+    // flips first, then the clockwise quarter turns, as in the backends
+    let (w, h) = (window_size.0 as f32, window_size.1 as f32);
+    let mut forward = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+    if aug.hflip {
+        forward = compose(&forward, &[-1.0, 0.0, w, 0.0, 1.0, 0.0]);
+    }
+    if aug.vflip {
+        forward = compose(&forward, &[1.0, 0.0, 0.0, 0.0, -1.0, h]);
+    }
+
+    // a clockwise turn of a (w, h) window sends (x, y) to (h - y, x)
+    let mut size = (w, h);
+    for _ in 0..aug.rot90 {
+        forward = compose(&forward, &[0.0, -1.0, size.1, 1.0, 0.0, 0.0]);
+        size = (size.1, size.0);
+    }
+
+    forward
+}
+
+
+pub fn box_transform(img_size: (u32, u32), bbox: &CropBox, units: BoxUnits, padding: f32,
+                     mode: ResizeMode, window_size: (u32, u32)) -> Affine
+{
+    // the box region of parallel_crop_boxes_and_resize onto the window, or onto
+    // the centered letterbox inside it
+    let (x, y, width, height) = boxes::box_to_region(bbox, units, padding, img_size);
+    let (size, offset) = match mode {
+        ResizeMode::Stretch => (window_size, (0, 0)),
+        ResizeMode::Letterbox => {
+            let size = boxes::letterbox_size((width, height), window_size);
+            (size, ((window_size.0 - size.0) / 2, (window_size.1 - size.1) / 2))
+        }
+    };
+    let (sx, sy) = (size.0 as f32 / width as f32, size.1 as f32 / height as f32);
+    [sx, 0.0, offset.0 as f32 - sx * x as f32,
+     0.0, sy, offset.1 as f32 - sy * y as f32]
+}
+
+
+// the annotations of a single item, all updated in place
+pub struct Annotations<'a> {
+    pub boxes: &'a mut [f32],       // [n, 4] of (x0, y0, x1, y1)
+    pub box_valid: &'a mut [u8],    // [n], 1 if the box keeps a non-empty area
+    pub points: &'a mut [f32],      // [m, 2] of (x, y)
+    pub point_visible: &'a mut [u8] // [m], 1 if the point lands inside the window
+}
+
+
+pub fn transform_annotations(annotations: &mut Annotations, forward: &Affine, window_size: (u32, u32)) {
+    let (w, h) = (window_size.0 as f32, window_size.1 as f32);
+
+    // boxes become the bounds of their mapped corners, clipped to the window
+    for (bbox, valid) in annotations.boxes.chunks_mut(4).zip(annotations.box_valid.iter_mut()) {
+        let corners = [map_point(forward, bbox[0], bbox[1]), map_point(forward, bbox[2], bbox[1]),
+                       map_point(forward, bbox[2], bbox[3]), map_point(forward, bbox[0], bbox[3])];
+        let x0 = corners.iter().map(|c| c.0).fold(::std::f32::INFINITY, f32::min).max(0.0).min(w);
+        let y0 = corners.iter().map(|c| c.1).fold(::std::f32::INFINITY, f32::min).max(0.0).min(h);
+        let x1 = corners.iter().map(|c| c.0).fold(::std::f32::NEG_INFINITY, f32::max).max(0.0).min(w);
+        let y1 = corners.iter().map(|c| c.1).fold(::std::f32::NEG_INFINITY, f32::max).max(0.0).min(h);
+        bbox.copy_from_slice(&[x0, y0, x1, y1]);
+        *valid = (x1 > x0 && y1 > y0) as u8;
+    }
+
+    // points keep their mapped position even when they fall outside
+    for (point, visible) in annotations.points.chunks_mut(2).zip(annotations.point_visible.iter_mut()) {
+        let (x, y) = map_point(forward, point[0], point[1]);
+        point.copy_from_slice(&[x, y]);
+        *visible = (x >= 0.0 && x < w && y >= 0.0 && y < h) as u8;
+    }
+}
+
+
+pub fn parallel_transform_annotations(threadpool: &ThreadPool,
+                                      transforms: &[Option<Affine>],
+                                      window_size: u32,
+                                      num_boxes_ptr: *const u32,
+                                      boxes_ptr: *mut f32,
+                                      box_valid_ptr: *mut u8,
+                                      num_points_ptr: *const u32,
+                                      points_ptr: *mut f32,
+                                      point_visible_ptr: *mut u8,
+                                      length: size_t)
+{
+    // accepts the per-item counts of boxes and points and the flat [sum(num_boxes), 4]
+    // and [sum(num_points), 2] arrays, which are mapped in place by transforms. the
+    // boxes and points of an item without a transform are flagged invalid / hidden
+    assert!(!num_boxes_ptr.is_null(), "can't operate over null box count vector");
+    assert!(!boxes_ptr.is_null(), "can't operate over null box vector");
+    assert!(!box_valid_ptr.is_null(), "can't operate over null box validity vector");
+    assert!(!num_points_ptr.is_null(), "can't operate over null point count vector");
+    assert!(!points_ptr.is_null(), "can't operate over null point vector");
+    assert!(!point_visible_ptr.is_null(), "can't operate over null point visibility vector");
+    assert!(transforms.len() == length as usize, "transforms [{:?}] != length [{:?}]",
+            transforms.len(), length);
+
+    let num_boxes = unsafe { slice::from_raw_parts(num_boxes_ptr, length as usize) };
+    let num_points = unsafe { slice::from_raw_parts(num_points_ptr, length as usize) };
+    let total_boxes = num_boxes.iter().map(|&n| n as usize).sum::<usize>();
+    let total_points = num_points.iter().map(|&n| n as usize).sum::<usize>();
+    let mut boxes = unsafe { slice::from_raw_parts_mut(boxes_ptr, 4 * total_boxes) };
+    let mut box_valid = unsafe { slice::from_raw_parts_mut(box_valid_ptr, total_boxes) };
+    let mut points = unsafe { slice::from_raw_parts_mut(points_ptr, 2 * total_points) };
+    let mut point_visible = unsafe { slice::from_raw_parts_mut(point_visible_ptr, total_points) };
+
+    // split the flat arrays into the annotations of every item
+    let mut items = Vec::with_capacity(length as usize);
+    for (&nb, &np) in num_boxes.iter().zip(num_points) {
+        let (item_boxes, rest) = { boxes }.split_at_mut(4 * nb as usize);
+        boxes = rest;
+        let (item_valid, rest) = { box_valid }.split_at_mut(nb as usize);
+        box_valid = rest;
+        let (item_points, rest) = { points }.split_at_mut(2 * np as usize);
+        points = rest;
+        let (item_visible, rest) = { point_visible }.split_at_mut(np as usize);
+        point_visible = rest;
+        items.push(Annotations { boxes: item_boxes, box_valid: item_valid,
+                                 points: item_points, point_visible: item_visible });
+    }
+
+    threadpool.install(|| {
+        items.into_par_iter().zip(transforms).for_each(|(mut annotations, forward)| match *forward {
+            Some(ref forward) => transform_annotations(&mut annotations, forward, (window_size, window_size)),
+            None => {
+                for flag in annotations.box_valid.iter_mut().chain(annotations.point_visible.iter_mut()) {
+                    *flag = 0;
+                }
+            }
+        });
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crop_and_geometric_transforms() {
+        // the region (25, 25, 50, 50) of a 100x100 image onto a 10x10 window
+        let crop = crop_transform((100, 100), 0.5, 0.25, 0.25, 1.0, (10, 10));
+        assert!(map_point(&crop, 25.0, 25.0) == (0.0, 0.0));
+        assert!(map_point(&crop, 50.0, 75.0) == (5.0, 10.0));
+
+        // a clockwise quarter turn sends the top-left corner to the top-right
+        let rot = geometric_transform(&GeometricAug { hflip: false, vflip: false, rot90: 1 }, (10, 10));
+        assert!(map_point(&rot, 0.0, 0.0) == (10.0, 0.0));
+        let flip_rot = geometric_transform(&GeometricAug { hflip: true, vflip: false, rot90: 1 }, (10, 10));
+        assert!(map_point(&flip_rot, 0.0, 0.0) == (10.0, 10.0));
+    }
+
+    #[test]
+    fn test_box_transforms() {
+        // a 40x20 box onto a 10x10 window, stretched or letterboxed into 10x5 at y = 2
+        let bbox = CropBox::new(10.0, 10.0, 50.0, 30.0);
+        let stretch = box_transform((100, 100), &bbox, BoxUnits::Pixel, 0.0, ResizeMode::Stretch, (10, 10));
+        assert!(map_point(&stretch, 10.0, 10.0) == (0.0, 0.0) && map_point(&stretch, 50.0, 30.0) == (10.0, 10.0));
+        let letterbox = box_transform((100, 100), &bbox, BoxUnits::Pixel, 0.0, ResizeMode::Letterbox, (10, 10));
+        assert!(map_point(&letterbox, 10.0, 10.0) == (0.0, 2.0) && map_point(&letterbox, 50.0, 30.0) == (10.0, 7.0));
+
+        // the same box normalized and padded by half its size on a 200x100 image
+        let normalized = CropBox::new(0.1, 0.2, 0.3, 0.4);
+        let padded = box_transform((200, 100), &normalized, BoxUnits::Normalized, 1.0, ResizeMode::Stretch, (10, 10));
+        assert!(map_point(&padded, 0.0, 10.0) == (0.0, 0.0) && map_point(&padded, 80.0, 50.0) == (10.0, 10.0));
+    }
+
+    #[test]
+    fn test_boxes_clip_and_points_hide() {
+        let forward = crop_transform((100, 100), 0.5, 0.25, 0.25, 1.0, (10, 10));
+        let mut boxes = [20.0, 30.0, 35.0, 45.0, 0.0, 0.0, 10.0, 10.0];
+        let mut valid = [0u8; 2];
+        let mut points = [30.0, 30.0, 90.0, 30.0];
+        let mut visible = [0u8; 2];
+        transform_annotations(&mut Annotations { boxes: &mut boxes, box_valid: &mut valid,
+                                                 points: &mut points, point_visible: &mut visible },
+                              &forward, (10, 10));
+        assert!(boxes[..4] == [0.0, 1.0, 2.0, 4.0], "boxes were {:?}", boxes);
+        assert!(valid == [1, 0]);
+        assert!(points == [1.0, 1.0, 13.0, 1.0] && visible == [1, 0], "points were {:?}", points);
+    }
+    #[test]
+    fn test_items_without_transform() {
+        // the second item's image couldn't be read, its annotations are all dropped
+        let pool = ::rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let (num_boxes, num_points) = ([1u32, 1], [1u32, 1]);
+        let mut boxes = [2.0, 2.0, 6.0, 6.0, 2.0, 2.0, 6.0, 6.0];
+        let mut valid = [0u8, 1];
+        let mut points = [4.0, 4.0, 4.0, 4.0];
+        let mut visible = [0u8, 1];
+        let identity = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        parallel_transform_annotations(&pool, &[Some(identity), None], 10,
+                                       num_boxes.as_ptr(), boxes.as_mut_ptr(), valid.as_mut_ptr(),
+                                       num_points.as_ptr(), points.as_mut_ptr(), visible.as_mut_ptr(), 2);
+        assert!(valid == [1, 0] && visible == [1, 0]);
+    }
+}
@@ -0,0 +1,480 @@
+use rng::Rng;
+
+
+// per-item augmentations applied by the backends to the resized window, so
+// the batch written to return_ptr is already augmented
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GeometricAug {
+    pub hflip: bool,
+    pub vflip: bool,
+    pub rot90: u8     // clockwise quarter turns, applied after the flips
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GeometricProbs {
+    pub hflip: f32,
+    pub vflip: f32,
+    pub rot90: f32    // probability of rotating by a uniform 90, 180 or 270 degrees
+}
+
+
+impl GeometricAug {
+    pub fn from_bits(bits: u8) -> GeometricAug {
+        // bit 0: horizontal flip, bit 1: vertical flip, bits 2-3: quarter turns
+        GeometricAug {
+            hflip: bits & 1 != 0,
+            vflip: bits & 2 != 0,
+            rot90: (bits >> 2) & 3
+        }
+    }
+
+    pub fn to_bits(&self) -> u8 {
+        (self.hflip as u8) | (self.vflip as u8) << 1 | (self.rot90 & 3) << 2
+    }
+
+    pub fn sample(probs: &GeometricProbs, rng: &mut Rng) -> GeometricAug {
+        let hflip = rng.chance(probs.hflip);
+        let vflip = rng.chance(probs.vflip);
+        let rot90 = if rng.chance(probs.rot90) { 1 + rng.below(3) as u8 } else { 0 };
+        GeometricAug { hflip: hflip, vflip: vflip, rot90: rot90 }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhotometricAug {
+    pub brightness: f32,  // multiplicative factor, 1 is the identity
+    pub contrast: f32,    // blend factor against the mean gray level
+    pub saturation: f32,  // blend factor against the per-pixel gray level
+    pub hue: f32,         // shift in [-0.5, 0.5] of the hue circle
+    pub grayscale: bool,
+    pub blur_sigma: f32   // gaussian blur sigma in pixels, 0 disables it
+}
+
+
+// ColorJitter style ranges: factors are drawn from [max(0, 1 - j), 1 + j]
+// and the hue shift from [-hue, hue]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PhotometricRanges {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    pub hue: f32,
+    pub grayscale_prob: f32,
+    pub blur_prob: f32,
+    pub blur_sigma: (f32, f32)
+}
+
+
+impl Default for PhotometricAug {
+    fn default() -> PhotometricAug {
+        PhotometricAug { brightness: 1.0, contrast: 1.0, saturation: 1.0, hue: 0.0,
+                         grayscale: false, blur_sigma: 0.0 }
+    }
+}
+
+
+impl PhotometricAug {
+    pub fn from_slice(vals: &[f32]) -> PhotometricAug {
+        // (brightness, contrast, saturation, hue, grayscale, blur_sigma)
+        assert!(vals.len() == 6, "photometric params need 6 values, got {:?}", vals.len());
+        PhotometricAug { brightness: vals[0], contrast: vals[1], saturation: vals[2], hue: vals[3],
+                         grayscale: vals[4] != 0.0, blur_sigma: vals[5] }
+    }
+
+    pub fn to_array(&self) -> [f32; 6] {
+        [self.brightness, self.contrast, self.saturation, self.hue,
+         self.grayscale as u8 as f32, self.blur_sigma]
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == PhotometricAug::default()
+    }
+
+    pub fn sample(ranges: &PhotometricRanges, rng: &mut Rng) -> PhotometricAug {
+        let mut factor = |jitter: f32| rng.uniform((1.0 - jitter).max(0.0), 1.0 + jitter);
+        let brightness = factor(ranges.brightness);
+        let contrast = factor(ranges.contrast);
+        let saturation = factor(ranges.saturation);
+        let hue = rng.uniform(-ranges.hue, ranges.hue);
+        let grayscale = rng.chance(ranges.grayscale_prob);
+        let blur_sigma = if rng.chance(ranges.blur_prob) {
+            rng.uniform(ranges.blur_sigma.0, ranges.blur_sigma.1)
+        } else { 0.0 };
+
+        PhotometricAug { brightness: brightness, contrast: contrast, saturation: saturation,
+                         hue: hue, grayscale: grayscale, blur_sigma: blur_sigma }
+    }
+}
+
+
+fn luma(px: &[f32]) -> f32 {
+    0.299 * px[0] + 0.587 * px[1] + 0.114 * px[2]
+}
+
+
+fn shift_hue(px: &mut [f32], shift: f32) {
+    // rgb -> hsv, rotate the hue and back, all in [0, 255]
+    let (r, g, b) = (px[0], px[1], px[2]);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    if delta <= 0.0 {
+        return;
+    }
+
+    let hue = if max == r { ((g - b) / delta).rem_euclid(6.0) }
+              else if max == g { (b - r) / delta + 2.0 }
+              else { (r - g) / delta + 4.0 };
+    let hue = (hue + shift * 6.0).rem_euclid(6.0);
+    let sector = hue.floor();
+    let frac = hue - sector;
+    let (p, q, t) = (min, max - delta * frac, min + delta * frac);
+    let (r, g, b) = match sector as u32 {
+        0 => (max, t, p),
+        1 => (q, max, p),
+        2 => (p, max, t),
+        3 => (p, q, max),
+        4 => (t, p, max),
+        _ => (max, p, q)
+    };
+    px[0] = r;
+    px[1] = g;
+    px[2] = b;
+}
+
+
+fn gaussian_blur(vals: &mut [f32], size: (u32, u32), chans: usize, sigma: f32) {
+    // separable blur with edge replication
+    let radius = (3.0 * sigma).ceil() as i64;
+    let kernel: Vec<f32> = (-radius..radius + 1).map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+    let norm: f32 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.iter().map(|k| k / norm).collect();
+    let (w, h) = (size.0 as i64, size.1 as i64);
+
+    // one pass along x and one along y
+    for &(step, len, lines, line_step) in [(chans as i64, w, h, w * chans as i64),
+                                           (w * chans as i64, h, w, chans as i64)].iter() {
+        let mut line = vec![0f32; len as usize];
+        for l in 0..lines {
+            for c in 0..chans as i64 {
+                let base = l * line_step + c;
+                for i in 0..len {
+                    line[i as usize] = vals[(base + i * step) as usize];
+                }
+                for i in 0..len {
+                    let acc: f32 = kernel.iter().enumerate().map(|(k, weight)| {
+                        let j = (i + k as i64 - radius).max(0).min(len - 1);
+                        weight * line[j as usize]
+                    }).sum();
+                    vals[(base + i * step) as usize] = acc;
+                }
+            }
+        }
+    }
+}
+
+
+pub fn apply_photometric(buf: &mut [u8], size: (u32, u32), chans: u32, aug: &PhotometricAug) {
+    // applies brightness, contrast, saturation, hue, grayscale and blur in
+    // that order to an [h, w, chans] buffer; color ops need 3 or 4 channels
+    // and alpha is left untouched
+    if aug.is_identity() {
+        return;
+    }
+
+    let chans = chans as usize;
+    let color = chans >= 3;
+    let gray_chans = if color { 3 } else { 1 };
+    let mut vals: Vec<f32> = buf.iter().map(|&v| v as f32).collect();
+    let clamp = |v: f32| v.max(0.0).min(255.0);
+
+    for px in vals.chunks_mut(chans) {
+        for v in px[..gray_chans].iter_mut() {
+            *v = clamp(*v * aug.brightness);
+        }
+    }
+
+    if aug.contrast != 1.0 {
+        let num_px = (vals.len() / chans).max(1) as f32;
+        let mean = vals.chunks(chans).map(|px| if color { luma(px) } else { px[0] }).sum::<f32>() / num_px;
+        for px in vals.chunks_mut(chans) {
+            for v in px[..gray_chans].iter_mut() {
+                *v = clamp((*v - mean) * aug.contrast + mean);
+            }
+        }
+    }
+
+    if color {
+        for px in vals.chunks_mut(chans) {
+            if aug.saturation != 1.0 {
+                let gray = luma(px);
+                for v in px[..3].iter_mut() {
+                    *v = clamp((*v - gray) * aug.saturation + gray);
+                }
+            }
+            if aug.hue != 0.0 {
+                shift_hue(px, aug.hue);
+            }
+            if aug.grayscale {
+                let gray = luma(px);
+                for v in px[..3].iter_mut() {
+                    *v = gray;
+                }
+            }
+        }
+    }
+
+    if aug.blur_sigma > 0.0 {
+        gaussian_blur(&mut vals, size, chans, aug.blur_sigma);
+    }
+
+    for (dst, v) in buf.iter_mut().zip(vals) {
+        *dst = v.round().max(0.0).min(255.0) as u8;
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EraseFill {
+    Constant(u8),
+    Noise,  // uniform noise in [0, 255]
+    Mean    // per-channel mean of the window
+}
+
+
+impl EraseFill {
+    pub fn from_code(mode: u32, value: u8) -> EraseFill {
+        match mode {
+            0 => EraseFill::Constant(value),
+            1 => EraseFill::Noise,
+            2 => EraseFill::Mean,
+            _ => panic!("unknown erase fill mode {:?}", mode)
+        }
+    }
+}
+
+
+impl Default for EraseFill {
+    fn default() -> EraseFill {
+        EraseFill::Constant(0)
+    }
+}
+
+
+// RandomErasing style ranges: area fraction of the window and aspect ratio (h / w)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EraseRanges {
+    pub prob: f32,
+    pub scale: (f32, f32),
+    pub ratio: (f32, f32),
+    pub fill: EraseFill
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EraseAug {
+    pub rect: Option<(u32, u32, u32, u32)>,  // (x, y, width, height) in the window
+    pub fill: EraseFill,
+    pub noise_seed: u64
+}
+
+
+impl EraseAug {
+    pub fn sample(ranges: &EraseRanges, window_size: (u32, u32), rng: &mut Rng) -> EraseAug {
+        let mut aug = EraseAug { rect: None, fill: ranges.fill, noise_seed: rng.next_u64() };
+        if !rng.chance(ranges.prob) {
+            return aug;
+        }
+
+        let (width, height) = (window_size.0 as f32, window_size.1 as f32);
+        let log_ratio = (ranges.ratio.0.ln(), ranges.ratio.1.ln());
+        for _ in 0..10 {
+            let area = width * height * rng.uniform(ranges.scale.0, ranges.scale.1);
+            let aspect = rng.uniform(log_ratio.0, log_ratio.1).exp();
+            let h = (area * aspect).sqrt().round() as u32;
+            let w = (area / aspect).sqrt().round() as u32;
+            if w > 0 && h > 0 && w < window_size.0 && h < window_size.1 {
+                let x = rng.below(window_size.0 - w + 1);
+                let y = rng.below(window_size.1 - h + 1);
+                aug.rect = Some((x, y, w, h));
+                break;
+            }
+        }
+
+        aug
+    }
+
+    pub fn from_slice(rect: &[u32], fill: EraseFill, noise_seed: u64) -> EraseAug {
+        // the inverse of rect_array, an empty rectangle erases nothing
+        let rect = match (rect[2], rect[3]) {
+            (0, _) | (_, 0) => None,
+            _ => Some((rect[0], rect[1], rect[2], rect[3]))
+        };
+        EraseAug { rect: rect, fill: fill, noise_seed: noise_seed }
+    }
+
+    pub fn rect_array(&self) -> [u32; 4] {
+        match self.rect {
+            Some((x, y, w, h)) => [x, y, w, h],
+            None => [0, 0, 0, 0]
+        }
+    }
+}
+
+
+pub fn apply_erase(buf: &mut [u8], size: (u32, u32), chans: u32, aug: &EraseAug) {
+    // fills the erased rectangle of an [h, w, chans] buffer
+    let (x, y, w, h) = match aug.rect {
+        Some(rect) => rect,
+        None => return
+    };
+
+    let chans = chans as usize;
+    let mean: Vec<u8> = match aug.fill {
+        EraseFill::Mean => {
+            let num_px = (buf.len() / chans).max(1) as f32;
+            (0..chans).map(|c| {
+                let sum: f32 = buf.chunks(chans).map(|px| px[c] as f32).sum();
+                (sum / num_px).round() as u8
+            }).collect()
+        },
+        _ => vec![]
+    };
+
+    let mut rng = Rng::new(aug.noise_seed);
+    for row in y..(y + h).min(size.1) {
+        for col in x..(x + w).min(size.0) {
+            let begin = (row * size.0 + col) as usize * chans;
+            for c in 0..chans {
+                buf[begin + c] = match aug.fill {
+                    EraseFill::Constant(val) => val,
+                    EraseFill::Noise => rng.below(256) as u8,
+                    EraseFill::Mean => mean[c]
+                };
+            }
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Augment {
+    pub geometric: GeometricAug,
+    pub photometric: PhotometricAug,
+    pub erase: EraseAug
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geometric_bits() {
+        for bits in 0..16u8 {
+            assert!(GeometricAug::from_bits(bits).to_bits() == bits);
+        }
+
+        let aug = GeometricAug { hflip: true, vflip: false, rot90: 3 };
+        assert!(GeometricAug::from_bits(aug.to_bits()) == aug);
+    }
+
+    #[test]
+    fn test_geometric_sampling() {
+        let never = GeometricProbs { hflip: 0.0, vflip: 0.0, rot90: 0.0 };
+        let always = GeometricProbs { hflip: 1.0, vflip: 1.0, rot90: 1.0 };
+        let mut rng = Rng::new(3);
+        for _ in 0..100 {
+            assert!(GeometricAug::sample(&never, &mut rng) == GeometricAug::default());
+            let aug = GeometricAug::sample(&always, &mut rng);
+            assert!(aug.hflip && aug.vflip && aug.rot90 >= 1 && aug.rot90 <= 3);
+        }
+    }
+
+    #[test]
+    fn test_photometric_ops() {
+        // identity leaves the buffer alone, brightness scales it
+        let mut buf = vec![10u8, 20, 30, 40, 50, 60];
+        apply_photometric(&mut buf, (2, 1), 3, &PhotometricAug::default());
+        assert!(buf == vec![10, 20, 30, 40, 50, 60]);
+        apply_photometric(&mut buf, (2, 1), 3, &PhotometricAug { brightness: 2.0, ..Default::default() });
+        assert!(buf == vec![20, 40, 60, 80, 100, 120]);
+
+        // grayscale collapses the channels and a full hue turn is a no-op
+        let mut gray = vec![255u8, 0, 0];
+        apply_photometric(&mut gray, (1, 1), 3, &PhotometricAug { grayscale: true, ..Default::default() });
+        assert!(gray == vec![76, 76, 76], "gray was {:?}", gray);
+        let mut red = vec![200u8, 30, 10];
+        apply_photometric(&mut red, (1, 1), 3, &PhotometricAug { hue: 1.0 / 3.0, ..Default::default() });
+        assert!(red == vec![10, 200, 30], "hue shifted was {:?}", red);
+
+        // blurring a constant image keeps it constant
+        let mut flat = vec![90u8; 5 * 4];
+        apply_photometric(&mut flat, (5, 4), 1, &PhotometricAug { blur_sigma: 1.5, ..Default::default() });
+        assert!(flat.iter().all(|&v| v == 90));
+    }
+
+    #[test]
+    fn test_photometric_sampling() {
+        let ranges = PhotometricRanges { brightness: 0.4, contrast: 0.4, saturation: 0.4, hue: 0.1,
+                                         grayscale_prob: 0.0, blur_prob: 1.0, blur_sigma: (0.1, 2.0) };
+        let mut rng = Rng::new(11);
+        for _ in 0..100 {
+            let aug = PhotometricAug::sample(&ranges, &mut rng);
+            assert!(aug.brightness >= 0.6 && aug.brightness <= 1.4);
+            assert!(aug.hue >= -0.1 && aug.hue <= 0.1);
+            assert!(!aug.grayscale && aug.blur_sigma >= 0.1);
+            assert!(PhotometricAug::from_slice(&aug.to_array()) == aug);
+        }
+        assert!(PhotometricAug::sample(&PhotometricRanges::default(), &mut rng).is_identity());
+    }
+
+    #[test]
+    fn test_samplers_are_independent() {
+        // with one seed for the batch a shared stream flips exactly the items
+        // whose brightness lands low, the salted ones agree about half the time
+        let probs = GeometricProbs { hflip: 0.5, vflip: 0.0, rot90: 0.0 };
+        let ranges = PhotometricRanges { brightness: 0.5, ..Default::default() };
+        let agree = |geometric: u64, photometric: u64| (0..256).filter(|&idx| {
+            let flip = GeometricAug::sample(&probs, &mut Rng::for_op(geometric, 7, idx)).hflip;
+            let dark = PhotometricAug::sample(&ranges, &mut Rng::for_op(photometric, 7, idx)).brightness < 1.0;
+            flip == dark
+        }).count();
+        assert!(agree(0, 0) == 256);
+        let salted = agree(::rng::GEOMETRIC, ::rng::PHOTOMETRIC);
+        assert!(salted > 96 && salted < 160, "{} of 256 decisions agree", salted);
+    }
+
+    #[test]
+    fn test_random_erasing() {
+        let ranges = EraseRanges { prob: 1.0, scale: (0.02, 0.33), ratio: (0.3, 3.3), fill: EraseFill::Mean };
+        let mut rng = Rng::new(5);
+        for _ in 0..100 {
+            let aug = EraseAug::sample(&ranges, (32, 32), &mut rng);
+            let (x, y, w, h) = aug.rect.expect("prob 1 always erases a rectangle");
+            assert!(x + w <= 32 && y + h <= 32);
+        }
+
+        // constant and mean fills only touch the rectangle
+        let mut buf: Vec<u8> = (0..16).map(|v| v * 10).collect();
+        apply_erase(&mut buf, (4, 4), 1, &EraseAug { rect: Some((1, 1, 2, 2)), fill: EraseFill::Constant(7),
+                                                     noise_seed: 0 });
+        assert!(buf[5] == 7 && buf[6] == 7 && buf[9] == 7 && buf[10] == 7);
+        assert!(buf[4] == 40 && buf[11] == 110);
+
+        let mut buf = vec![10u8, 20, 30, 40];
+        apply_erase(&mut buf, (2, 2), 1, &EraseAug { rect: Some((0, 0, 1, 1)), fill: EraseFill::Mean,
+                                                     noise_seed: 0 });
+        assert!(buf == vec![25, 20, 30, 40]);
+        assert!(EraseAug::sample(&EraseRanges { prob: 0.0, ..ranges }, (32, 32), &mut rng).rect.is_none());
+
+        // rectangles read back from rect_array erase the same pixels
+        let aug = EraseAug::sample(&ranges, (32, 32), &mut rng);
+        assert!(EraseAug::from_slice(&aug.rect_array(), aug.fill, aug.noise_seed) == aug);
+        assert!(EraseAug::from_slice(&[3, 4, 0, 5], EraseFill::Noise, 0).rect.is_none());
+    }
+}
@@ -0,0 +1,68 @@
+use std::{ptr, slice, str};
+use std::ffi::CStr;
+use rayon::prelude::*;
+use libc::{size_t, c_char};
+
+
+// the plumbing shared by the parallel_* crops of both backends: read the paths
+// (and the usual scale / x / y vectors) out of the caller's arrays, crop every
+// item on the pool and copy the windows into the caller's return array
+
+
+pub fn paths<'a>(image_paths_ptr: *const *const c_char, length: size_t) -> Vec<&'a str> {
+    assert!(!image_paths_ptr.is_null(), "can't operate over null list of image paths");
+    unsafe { slice::from_raw_parts(image_paths_ptr, length) }.iter()
+        .map(|&p| unsafe { CStr::from_ptr(p) })
+        .map(|cs| str::from_utf8(cs.to_bytes()).unwrap())
+        .collect()
+}
+
+
+pub fn coords<'a>(scale_ptr: *const f32, x_ptr: *const f32, y_ptr: *const f32,
+                  length: size_t) -> (&'a [f32], &'a [f32], &'a [f32])
+{
+    // the [scale, x, y] of every item
+    assert!(!scale_ptr.is_null(), "can't operate over null scale vector");
+    assert!(!x_ptr.is_null(), "can't operate over null x vector");
+    assert!(!y_ptr.is_null(), "can't operate over null y vector");
+    unsafe {
+        (slice::from_raw_parts(scale_ptr, length),
+         slice::from_raw_parts(x_ptr, length),
+         slice::from_raw_parts(y_ptr, length))
+    }
+}
+
+
+pub fn run_with<T, E, F>(image_paths_ptr: *const *const c_char, return_ptr: *mut T, win_size: usize,
+                         length: size_t, crop_one: F) -> Vec<E>
+    where T: Copy + Send, E: Send, F: Fn(usize, &str) -> (Vec<T>, E) + Sync
+{
+    // crops item i with crop_one(i, path), which returns its window of win_size
+    // values and anything else the caller needs back, eg: the sampled box
+    assert!(!return_ptr.is_null(), "can't operate over null result vector");
+    let image_paths_vec = paths(image_paths_ptr, length);
+
+    let mut resultant_vec = vec![];
+    image_paths_vec.into_par_iter().enumerate()
+        .map(|(idx, path)| crop_one(idx, path))
+        .collect_into_vec(&mut resultant_vec);
+
+    // copy the buffer into the return array
+    let mut extras = Vec::with_capacity(resultant_vec.len());
+    for (begin, (rvec, extra)) in izip!((0..length*win_size).step_by(win_size), resultant_vec)
+    {
+        assert!(rvec.len() == win_size, "rvec [{:?}] != window_size [{:?}]",
+                rvec.len(), win_size);
+        unsafe { ptr::copy(rvec.as_ptr(), return_ptr.add(begin), win_size) };
+        extras.push(extra);
+    }
+    extras
+}
+
+
+pub fn run<T, F>(image_paths_ptr: *const *const c_char, return_ptr: *mut T, win_size: usize,
+                 length: size_t, crop_one: F)
+    where T: Copy + Send, F: Fn(usize, &str) -> Vec<T> + Sync
+{
+    run_with(image_paths_ptr, return_ptr, win_size, length, |idx, path| (crop_one(idx, path), ()));
+}
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+
+
+pub const USAGE: &'static str = "\
+usage: parallel_image_crop --manifest <crops.csv|crops.jsonl> --output <dir|crops.npy> [options]
+
+crops every image of the manifest and writes the crops as png files named
+<line>_<stem>.png into a directory, or into one [N, H, W, C] u8 .npy file with
+a row per manifest row (failed rows are left as zeros). manifest rows are either
+(path, scale, x, y) normalized crops or (path, x0, y0, x1, y1) boxes, see the
+manifest module for the csv and jsonl layouts. paths are relative to the
+working directory.
+
+options:
+  -m, --manifest <path>       csv (with a header row) or jsonl manifest
+  -o, --output <path>         output directory, or a file ending in .npy
+  -w, --window-size <n>       side of the square crops (default: 32)
+  -c, --chans <n>             channels of the crops, images with a different
+                              count are reported as failures (default: 3)
+  -t, --threads <n>           threadpool size, 0 for one per core (default: 0)
+  -b, --backend <name>        image or vips (default: image)
+  -f, --filter <name>         resize filter of the scale/x/y crops: nearest,
+                              bilinear, bicubic or lanczos3 (default: nearest)
+      --max-img-percent <f>   upper bound of the crop scale (default: 1.0)
+      --normalized-boxes      boxes are in [0, 1] instead of pixels
+      --padding <f>           relative context added around boxes (default: 0)
+      --letterbox             keep the box aspect ratio, padding the crop
+      --nchw                  store the .npy as [N, C, H, W]
+      --batch-size <n>        images handed to the threadpool at once (default: 256)
+  -q, --quiet                 no progress indicator
+  -h, --help                  print this message
+";
+
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub manifest: String,
+    pub output: String,
+    pub window_size: u32,
+    pub chans: u32,
+    pub threads: u64,
+    pub use_vips: bool,
+    pub filter: u32,
+    pub max_img_percent: f32,
+    pub normalized_boxes: bool,
+    pub padding: f32,
+    pub letterbox: bool,
+    pub nchw: bool,
+    pub batch_size: usize,
+    pub quiet: bool
+}
+
+
+fn value<T: FromStr, I: Iterator<Item = String>>(flag: &str, args: &mut I) -> Result<T, String> {
+    let raw = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+    raw.parse().map_err(|_| format!("invalid value {:?} for {}", raw, flag))
+}
+
+
+// Ok(None) when help was requested
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Options>, String> {
+    let mut opts = Options {
+        manifest: String::new(),
+        output: String::new(),
+        window_size: 32,
+        chans: 3,
+        threads: 0,
+        use_vips: false,
+        filter: 0,
+        max_img_percent: 1.0,
+        normalized_boxes: false,
+        padding: 0.0,
+        letterbox: false,
+        nchw: false,
+        batch_size: 256,
+        quiet: false
+    };
+
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        match &flag[..] {
+            "-m" | "--manifest" => opts.manifest = value(&flag, &mut args)?,
+            "-o" | "--output" => opts.output = value(&flag, &mut args)?,
+            "-w" | "--window-size" => opts.window_size = value(&flag, &mut args)?,
+            "-c" | "--chans" => opts.chans = value(&flag, &mut args)?,
+            "-t" | "--threads" => opts.threads = value(&flag, &mut args)?,
+            "-b" | "--backend" => opts.use_vips = match &value::<String, _>(&flag, &mut args)?[..] {
+                "image" => false,
+                "vips" => true,
+                other => return Err(format!("unknown backend {:?}, expected image or vips", other))
+            },
+            "-f" | "--filter" => opts.filter = match &value::<String, _>(&flag, &mut args)?[..] {
+                "nearest" => 0,
+                "bilinear" => 1,
+                "bicubic" => 2,
+                "lanczos3" => 3,
+                other => return Err(format!("unknown filter {:?}", other))
+            },
+            "--max-img-percent" => opts.max_img_percent = value(&flag, &mut args)?,
+            "--normalized-boxes" => opts.normalized_boxes = true,
+            "--padding" => opts.padding = value(&flag, &mut args)?,
+            "--letterbox" => opts.letterbox = true,
+            "--nchw" => opts.nchw = true,
+            "--batch-size" => opts.batch_size = value(&flag, &mut args)?,
+            "-q" | "--quiet" => opts.quiet = true,
+            "-h" | "--help" => return Ok(None),
+            other => return Err(format!("unknown argument {:?}", other))
+        }
+    }
+
+    if opts.manifest.is_empty() || opts.output.is_empty() {
+        return Err("--manifest and --output are required".to_string());
+    }
+    if opts.window_size == 0 || opts.chans == 0 || opts.chans > 4 || opts.batch_size == 0 {
+        return Err("window size and batch size must be positive and chans in [1, 4]".to_string());
+    }
+    Ok(Some(opts))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let opts = parse(args("-m a.csv -o out.npy -w 64 --backend vips -f bicubic -q")).unwrap().unwrap();
+        assert!(opts.window_size == 64 && opts.use_vips && opts.filter == 2 && opts.quiet);
+        assert!(opts.chans == 3 && opts.batch_size == 256);
+        assert!(parse(args("--help")).unwrap().is_none());
+        assert!(parse(args("-m a.csv")).is_err());
+        assert!(parse(args("-m a.csv -o out -w big")).is_err());
+        assert!(parse(args("-m a.csv -o out -f sinc")).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+extern crate parallel_image_crop;
+extern crate image;
+extern crate rayon;
+extern crate serde_json;
+
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::io::Write;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::process;
+use rayon::prelude::*;
+
+use parallel_image_crop as crop;
+use parallel_image_crop::CropConfig;
+
+mod args;
+mod manifest;
+
+use args::Options;
+use manifest::{Crop, Entry};
+
+
+// offline batch cropping: reads a manifest, crops it through the same exports
+// the python bindings use and writes png files or a single .npy
+
+
+// where the directory sink stages the files of parallel_crop_and_save
+const STAGING: &'static str = ".staging";
+
+
+enum Sink {
+    Directory(String),
+    // a preallocated .npy with one row per manifest row
+    Npy(CString)
+}
+
+
+fn color_type(chans: u32) -> image::ColorType {
+    match chans {
+        1 => image::ColorType::Gray(8),
+        2 => image::ColorType::GrayA(8),
+        3 => image::ColorType::RGB(8),
+        _ => image::ColorType::RGBA(8)
+    }
+}
+
+
+fn png_path(dir: &str, entry: &Entry) -> PathBuf {
+    let stem = Path::new(&entry.path).file_stem().map_or("".to_string(), |s| s.to_string_lossy().into_owned());
+    Path::new(dir).join(format!("{:06}_{}.png", entry.line, stem))
+}
+
+
+fn save_scaled(cm: *const c_void, opts: &Options, dir: &str, entries: &[&Entry], path_ptrs: &[*const c_char],
+               scale: &[f32], x: &[f32], y: &[f32]) -> Vec<Result<Option<Vec<u8>>, String>>
+{
+    // parallel_crop_and_save names the files by their position in the call, so
+    // they're written to a staging directory and renamed to <line>_<stem>.png
+    let staging = Path::new(dir).join(STAGING);
+    let template = CString::new(staging.join("{index}.png").to_string_lossy().into_owned()).unwrap();
+    let config = CropConfig { window_size: opts.window_size, max_img_percent: opts.max_img_percent,
+                              filter: opts.filter, ..CropConfig::default() };
+    let mut written = vec![0u8; entries.len()];
+    crop::parallel_crop_and_save_with_config(cm, &config, path_ptrs.as_ptr(), template.as_ptr(), 0, 100,
+                                             written.as_mut_ptr(), scale.as_ptr(), x.as_ptr(), y.as_ptr(),
+                                             entries.len());
+    entries.iter().zip(written).enumerate().map(|(index, (entry, ok))| match ok {
+        0 => Err("could not be written".to_string()),
+        _ => fs::rename(staging.join(format!("{}.png", index)), png_path(dir, entry))
+            .map(|_| None)
+            .map_err(|err| err.to_string())
+    }).collect()
+}
+
+
+fn crop_batch(cm: *const c_void, sink: &Sink, opts: &Options,
+              batch: &[Entry]) -> Vec<Result<Option<Vec<u8>>, String>>
+{
+    // the crop of every entry of the batch (None once it's already written to the
+    // sink), or why it failed
+    let paths: Vec<CString> = batch.iter()
+        .map(|e| CString::new(e.path.clone()).unwrap_or_default())
+        .collect();
+    let path_ptrs: Vec<*const c_char> = paths.iter().map(|p| p.as_ptr()).collect();
+
+    // unreadable images would abort the whole batch, weed them out first
+    let mut ok = vec![0u8; batch.len()];
+    let mut shapes = vec![0u32; 3 * batch.len()];
+    crop::probe_images(cm, path_ptrs.as_ptr(), shapes.as_mut_ptr(), ok.as_mut_ptr(), batch.len());
+    let mut results: Vec<Result<Option<Vec<u8>>, String>> = batch.iter().enumerate().map(|(i, _)| {
+        match (ok[i], shapes[3 * i + 2]) {
+            (0, _) => Err("could not be decoded".to_string()),
+            (_, chans) if chans != opts.chans => Err(format!("has {} channels, expected {}", chans, opts.chans)),
+            _ => Ok(None)
+        }
+    }).collect();
+
+    // the scale / x / y crops and the box crops go through their own exports
+    let win_size = (opts.window_size * opts.window_size * opts.chans) as usize;
+    let readable: Vec<usize> = (0..batch.len()).filter(|&i| results[i].is_ok()).collect();
+    let (scaled, boxed): (Vec<usize>, Vec<usize>) = readable.into_iter().partition(|&i| match batch[i].crop {
+        Crop::Scaled { .. } => true,
+        Crop::Box(_) => false
+    });
+
+    if !scaled.is_empty() {
+        let ptrs: Vec<*const c_char> = scaled.iter().map(|&i| path_ptrs[i]).collect();
+        let (mut scale, mut x, mut y) = (vec![], vec![], vec![]);
+        for &i in &scaled {
+            if let Crop::Scaled { scale: s, x: xc, y: yc } = batch[i].crop {
+                scale.push(s);
+                x.push(xc);
+                y.push(yc);
+            }
+        }
+        match *sink {
+            Sink::Directory(ref dir) => {
+                let entries: Vec<&Entry> = scaled.iter().map(|&i| &batch[i]).collect();
+                let saved = save_scaled(cm, opts, dir, &entries, &ptrs, &scale, &x, &y);
+                for (&i, result) in scaled.iter().zip(saved) {
+                    results[i] = result;
+                }
+            },
+            Sink::Npy(_) => {
+                let mut crops = vec![0u8; win_size * scaled.len()];
+                let stream_paths = [ptrs.as_ptr()];
+                let stream_returns = [crops.as_mut_ptr() as *mut c_void];
+                crop::parallel_crop_and_resize_paired(cm, stream_paths.as_ptr(), stream_returns.as_ptr(),
+                                                      [opts.chans].as_ptr(), [opts.filter].as_ptr(), [0u32].as_ptr(), 1,
+                                                      scale.as_ptr(), x.as_ptr(), y.as_ptr(),
+                                                      opts.window_size, opts.max_img_percent, scaled.len());
+                for (&i, window) in scaled.iter().zip(crops.chunks(win_size)) {
+                    results[i] = Ok(Some(window.to_vec()));
+                }
+            }
+        }
+    }
+
+    if !boxed.is_empty() {
+        let ptrs: Vec<*const c_char> = boxed.iter().map(|&i| path_ptrs[i]).collect();
+        let boxes: Vec<f32> = boxed.iter().flat_map(|&i| match batch[i].crop {
+            Crop::Box(b) => b.to_vec(),
+            Crop::Scaled { .. } => unreachable!()
+        }).collect();
+        let mut crops = vec![0u8; win_size * boxed.len()];
+        crop::parallel_crop_boxes_and_resize(cm, ptrs.as_ptr(), crops.as_mut_ptr(), boxes.as_ptr(),
+                                             opts.normalized_boxes, opts.padding, opts.letterbox,
+                                             opts.window_size, opts.chans, boxed.len());
+        for (&i, window) in boxed.iter().zip(crops.chunks(win_size)) {
+            results[i] = Ok(Some(window.to_vec()));
+        }
+    }
+    results
+}
+
+
+fn write_batch(sink: &Sink, opts: &Options, batch: &[Entry],
+               crops: Vec<Result<Option<Vec<u8>>, String>>) -> Vec<(usize, String)>
+{
+    // writes the crops that succeeded and aren't written yet, returns the
+    // (line, reason) failures
+    let mut failures: Vec<(usize, String)> = batch.iter().zip(&crops).filter_map(|(e, c)| match *c {
+        Err(ref reason) => Some((e.line, format!("{}: {}", e.path, reason))),
+        Ok(_) => None
+    }).collect();
+
+    match *sink {
+        Sink::Npy(ref path) => {
+            let (rows, data): (Vec<u64>, Vec<u8>) = batch.iter().zip(crops).filter_map(|(entry, crop)| {
+                crop.ok().and_then(|crop| crop).map(|crop| (entry.row as u64, crop))
+            }).fold((vec![], vec![]), |(mut rows, mut data), (row, crop)| {
+                rows.push(row);
+                data.extend(crop);
+                (rows, data)
+            });
+            let size = opts.window_size;
+            if !rows.is_empty() && !crop::write_npy_rows(path.as_ptr(), data.as_ptr() as *const c_void, rows.as_ptr(),
+                                                         0, opts.nchw as u32, rows.len(), size, size, opts.chans) {
+                failures.extend(batch.iter().filter(|e| rows.contains(&(e.row as u64)))
+                                .map(|e| (e.line, format!("{}: could not be written", e.path))));
+            }
+        },
+        Sink::Directory(ref dir) => {
+            // the box crops, there's no export saving them
+            let size = opts.window_size;
+            let written: Vec<(usize, String)> = batch.par_iter().zip(crops.into_par_iter()).filter_map(|(entry, crop)| {
+                let crop = crop.ok().and_then(|crop| crop)?;
+                image::save_buffer(&png_path(dir, entry), &crop, size, size, color_type(opts.chans)).err()
+                    .map(|err| (entry.line, format!("{}: {}", entry.path, err)))
+            }).collect();
+            failures.extend(written);
+        }
+    }
+    failures
+}
+
+
+fn run(opts: &Options) -> Result<(usize, Vec<(usize, String)>), String> {
+    let manifest = manifest::read(&opts.manifest)
+        .map_err(|err| format!("could not read {}: {}", opts.manifest, err))?;
+    let mut failures = manifest.errors.clone();
+    let total = manifest.entries.len() + manifest.errors.len();
+
+    let sink = match opts.output.to_ascii_lowercase().ends_with(".npy") {
+        true  => {
+            let path = CString::new(opts.output.clone()).map_err(|err| err.to_string())?;
+            let _ = fs::remove_file(&opts.output);
+            if !crop::create_npy_memmap(path.as_ptr(), 0, opts.nchw as u32, total,
+                                        opts.window_size, opts.window_size, opts.chans) {
+                return Err(format!("could not create {}", opts.output));
+            }
+            Sink::Npy(path)
+        },
+        false => {
+            fs::create_dir_all(&opts.output)
+                .map_err(|err| format!("could not create {}: {}", opts.output, err))?;
+            Sink::Directory(opts.output.clone())
+        }
+    };
+
+    let cm = crop::initialize(opts.threads, opts.use_vips);
+    let mut done = manifest.errors.len();
+    for batch in manifest.entries.chunks(opts.batch_size) {
+        let crops = crop_batch(cm, &sink, opts, batch);
+        failures.extend(write_batch(&sink, opts, batch, crops));
+        done += batch.len();
+        if !opts.quiet {
+            eprint!("\r{} / {} rows ({:.1}%)", done, total, 100.0 * done as f32 / total.max(1) as f32);
+            let _ = ::std::io::stderr().flush();
+        }
+    }
+    if !opts.quiet && total > 0 {
+        eprintln!();
+    }
+    crop::destroy(cm);
+    if let Sink::Directory(ref dir) = sink {
+        let _ = fs::remove_dir(Path::new(dir).join(STAGING));
+    }
+
+    failures.sort();
+    Ok((total - failures.len(), failures))
+}
+
+
+fn main() {
+    let opts = match args::parse(env::args().skip(1)) {
+        Ok(Some(opts)) => opts,
+        Ok(None) => {
+            print!("{}", args::USAGE);
+            return;
+        },
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, args::USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&opts) {
+        Ok((written, failures)) => {
+            eprintln!("wrote {} crops to {}, {} failed", written, opts.output, failures.len());
+            for &(line, ref reason) in failures.iter().take(50) {
+                eprintln!("  line {}: {}", line, reason);
+            }
+            if failures.len() > 50 {
+                eprintln!("  ... and {} more", failures.len() - 50);
+            }
+            if !failures.is_empty() {
+                process::exit(1);
+            }
+        },
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(2);
+        }
+    }
+}
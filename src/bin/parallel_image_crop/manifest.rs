@@ -0,0 +1,178 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde_json;
+use serde_json::Value;
+
+
+// crop requests read from a csv manifest (with a header row naming the
+// columns) or a jsonl manifest (one object per line). a request is either a
+// normalized (scale, x, y) crop as in parallel_crop_and_resize or a box
+//
+//   path,scale,x,y             {"path": "a.png", "scale": 0.5, "x": 0.1, "y": 0.2}
+//   path,x0,y0,x1,y1           {"path": "a.png", "box": [10, 20, 110, 220]}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crop {
+    Scaled { scale: f32, x: f32, y: f32 },
+    Box([f32; 4])
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub line: usize,
+    // position among the data rows of the manifest
+    pub row: usize,
+    pub path: String,
+    pub crop: Crop
+}
+
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub entries: Vec<Entry>,
+    // (line, reason) of the rows that could not be parsed
+    pub errors: Vec<(usize, String)>
+}
+
+
+pub fn read(path: &str) -> io::Result<Manifest> {
+    let text = fs::read_to_string(path)?;
+    let ext = Path::new(path).extension().and_then(|s| s.to_str())
+                             .map_or("".to_string(), |s| s.to_ascii_lowercase());
+    match &ext[..] {
+        "jsonl" | "ndjson" | "json" => Ok(parse_jsonl(&text)),
+        _ => parse_csv(&text)
+    }
+}
+
+
+fn split_csv_line(line: &str) -> Vec<String> {
+    // comma separated fields, double quoted fields may hold commas and ""
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => { chars.next(); field.push('"'); },
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(field.split_off(0)),
+            (c, _) => field.push(c)
+        }
+    }
+    fields.push(field);
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+
+fn build(line: usize, row: usize, path: Option<String>, scaled: [Option<f32>; 3], bbox: [Option<f32>; 4]) -> Result<Entry, String> {
+    let path = match path {
+        Some(ref p) if !p.is_empty() => p.clone(),
+        _ => return Err("missing path".to_string())
+    };
+    let crop = match (scaled, bbox) {
+        (_, [Some(x0), Some(y0), Some(x1), Some(y1)]) => Crop::Box([x0, y0, x1, y1]),
+        ([Some(scale), Some(x), Some(y)], _) => {
+            if x < 0.0 || x > 1.0 || y < 0.0 || y > 1.0 {
+                return Err(format!("x and y must be in [0, 1], got ({}, {})", x, y));
+            }
+            Crop::Scaled { scale: scale, x: x, y: y }
+        },
+        _ => return Err("expected scale, x, y or a box".to_string())
+    };
+    Ok(Entry { line: line, row: row, path: path, crop: crop })
+}
+
+
+pub fn parse_csv(text: &str) -> io::Result<Manifest> {
+    let mut lines = text.lines().enumerate().filter(|&(_, l)| !l.trim().is_empty());
+    let header = match lines.next() {
+        Some((_, header)) => split_csv_line(header),
+        None => return Ok(Manifest::default())
+    };
+    let column = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let path_col = column("path").ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData, "csv manifest needs a header row with a path column"))?;
+    let scaled_cols = [column("scale"), column("x"), column("y")];
+    let box_cols = [column("x0"), column("y0"), column("x1"), column("y1")];
+
+    let mut manifest = Manifest::default();
+    for (row, (index, line)) in lines.enumerate() {
+        let fields = split_csv_line(line);
+        let mut bad_number = None;
+        {
+            let mut number = |col: Option<usize>| -> Option<f32> {
+                let field = match col.and_then(|c| fields.get(c)) {
+                    Some(f) if !f.is_empty() => f,
+                    _ => return None
+                };
+                match field.parse::<f32>() {
+                    Ok(v) => Some(v),
+                    Err(_) => { bad_number = Some(field.clone()); None }
+                }
+            };
+            let scaled = [number(scaled_cols[0]), number(scaled_cols[1]), number(scaled_cols[2])];
+            let bbox = [number(box_cols[0]), number(box_cols[1]), number(box_cols[2]), number(box_cols[3])];
+            let entry = build(index + 1, row, fields.get(path_col).cloned(), scaled, bbox);
+            match (entry, bad_number.take()) {
+                (_, Some(field)) => manifest.errors.push((index + 1, format!("{:?} is not a number", field))),
+                (Ok(entry), None) => manifest.entries.push(entry),
+                (Err(err), None) => manifest.errors.push((index + 1, err))
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+
+pub fn parse_jsonl(text: &str) -> Manifest {
+    let mut manifest = Manifest::default();
+    let lines = text.lines().enumerate().filter(|&(_, l)| !l.trim().is_empty());
+    for (row, (index, line)) in lines.enumerate() {
+        let entry = serde_json::from_str::<Value>(line).map_err(|err| err.to_string()).and_then(|obj| {
+            let number = |key: &str| obj.get(key).and_then(|v| v.as_f64()).map(|v| v as f32);
+            let bbox = match obj.get("box") {
+                Some(&Value::Array(ref vals)) if vals.len() == 4 => {
+                    let v: Vec<Option<f32>> = vals.iter().map(|v| v.as_f64().map(|v| v as f32)).collect();
+                    [v[0], v[1], v[2], v[3]]
+                },
+                Some(_) => return Err("box must be [x0, y0, x1, y1]".to_string()),
+                None => [None; 4]
+            };
+            let path = obj.get("path").and_then(|p| p.as_str()).map(|p| p.to_string());
+            build(index + 1, row, path, [number("scale"), number("x"), number("y")], bbox)
+        });
+        match entry {
+            Ok(entry) => manifest.entries.push(entry),
+            Err(err) => manifest.errors.push((index + 1, err))
+        }
+    }
+    manifest
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifests() {
+        let csv = parse_csv("path,scale,x,y\n\"a,b.png\",0.5,0.1,0.2\nc.png,0.5,2.0,0.1\n\nd.png,half,0,0\n").unwrap();
+        assert!(csv.entries == vec![Entry { line: 2, row: 0, path: "a,b.png".to_string(),
+                                            crop: Crop::Scaled { scale: 0.5, x: 0.1, y: 0.2 } }]);
+        assert!(csv.errors.iter().map(|e| e.0).collect::<Vec<_>>() == vec![3, 5]);
+
+        let jsonl = parse_jsonl("{\"path\": \"a\\u00e9.png\", \"box\": [1, 2, 30.5, 40]}\n{\"path\": \"b.png\"}\n");
+        assert!(jsonl.entries == vec![Entry { line: 1, row: 0, path: "a\u{e9}.png".to_string(),
+                                              crop: Crop::Box([1.0, 2.0, 30.5, 40.0]) }]);
+        assert!(jsonl.errors == vec![(2, "expected scale, x, y or a box".to_string())]);
+        assert!(parse_jsonl("{\"path\": \"a.png\",}").errors.len() == 1);
+
+        // escaped surrogate pairs decode to the character they encode
+        let jsonl = parse_jsonl("{\"path\": \"\\ud83d\\ude00.png\", \"scale\": 1, \"x\": 0, \"y\": 0}");
+        assert!(jsonl.entries[0].path == "\u{1f600}.png");
+    }
+}
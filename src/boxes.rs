@@ -0,0 +1,136 @@
+// shared geometry for the bounding-box crop path: both piston and vips
+// translate a box into a pixel region with these helpers and then only
+// differ in how they decode, crop and resize
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoxUnits {
+    Pixel,      // (x0, y0, x1, y1) in pixels of the source image
+    Normalized  // (x0, y0, x1, y1) in [0, 1] relative to the source image
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResizeMode {
+    Stretch,   // resize the region to the window ignoring the aspect ratio
+    Letterbox  // keep the aspect ratio and zero-pad the remainder of the window
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropBox {
+    pub x0: f32,
+    pub y0: f32,
+    pub x1: f32,
+    pub y1: f32
+}
+
+
+impl CropBox {
+    pub fn new(x0: f32, y0: f32, x1: f32, y1: f32) -> CropBox {
+        CropBox { x0: x0, y0: y0, x1: x1, y1: y1 }
+    }
+
+    pub fn from_slice(vals: &[f32]) -> CropBox {
+        assert!(vals.len() == 4, "box needs 4 co-ordinates, got {:?}", vals.len());
+        CropBox::new(vals[0], vals[1], vals[2], vals[3])
+    }
+}
+
+
+pub fn box_to_region(bbox: &CropBox, units: BoxUnits, padding: f32,
+                     img_size: (u32, u32)) -> (u32, u32, u32, u32)
+{
+    // converts a box into an (x, y, width, height) pixel region of the image,
+    // grown by padding * box size (split evenly between both sides) and
+    // clipped to the image bounds
+    assert!(bbox.x1 > bbox.x0 && bbox.y1 > bbox.y0, "degenerate box {:?}", bbox);
+    assert!(padding >= 0f32, "padding ratio needs to be non-negative");
+    if units == BoxUnits::Normalized {
+        assert!(bbox.x0 >= 0f32 && bbox.x1 <= 1f32, "x of box not bounded in [0, 1]");
+        assert!(bbox.y0 >= 0f32 && bbox.y1 <= 1f32, "y of box not bounded in [0, 1]");
+    }
+
+    // scale the co-ordinates to the img_size
+    let (x0, y0, x1, y1) = match units {
+        BoxUnits::Pixel => (bbox.x0, bbox.y0, bbox.x1, bbox.y1),
+        BoxUnits::Normalized => (super::scale_range(bbox.x0, 0f32, img_size.0 as f32),
+                                 super::scale_range(bbox.y0, 0f32, img_size.1 as f32),
+                                 super::scale_range(bbox.x1, 0f32, img_size.0 as f32),
+                                 super::scale_range(bbox.y1, 0f32, img_size.1 as f32))
+    };
+
+    // add the context padding
+    let pad = ((x1 - x0) * padding / 2.0, (y1 - y0) * padding / 2.0);
+    let (x0, y0, x1, y1) = (x0 - pad.0, y0 - pad.1, x1 + pad.0, y1 + pad.1);
+
+    // threshold to the image, keeping at least a single pixel; the epsilon
+    // avoids growing the region by a pixel due to float error in the scaling
+    let x = (x0 + 1e-3).floor().max(0f32).min(img_size.0 as f32 - 1.0) as u32;
+    let y = (y0 + 1e-3).floor().max(0f32).min(img_size.1 as f32 - 1.0) as u32;
+    let x_end = ((x1 - 1e-3).ceil().min(img_size.0 as f32) as u32).max(x + 1);
+    let y_end = ((y1 - 1e-3).ceil().min(img_size.1 as f32) as u32).max(y + 1);
+    (x, y, x_end - x, y_end - y)
+}
+
+
+pub fn letterbox_size(region_size: (u32, u32), window_size: (u32, u32)) -> (u32, u32)
+{
+    // largest size with the aspect ratio of the region that fits in the window
+    let ratio = (window_size.0 as f32 / region_size.0 as f32)
+        .min(window_size.1 as f32 / region_size.1 as f32);
+    (((region_size.0 as f32 * ratio).round() as u32).max(1).min(window_size.0),
+     ((region_size.1 as f32 * ratio).round() as u32).max(1).min(window_size.1))
+}
+
+
+pub fn letterbox(resized: &[u8], resized_size: (u32, u32), window_size: (u32, u32),
+                 chans: u32) -> Vec<u8>
+{
+    // centers an already resized [h, w, chans] buffer in a zeroed window
+    assert!(resized.len() == (resized_size.0 * resized_size.1 * chans) as usize,
+            "resized [{:?}] != resized_size [{:?}]", resized.len(), resized_size);
+    let offset = ((window_size.0 - resized_size.0) / 2,
+                  (window_size.1 - resized_size.1) / 2);
+    let src_row = (resized_size.0 * chans) as usize;
+    let dst_row = (window_size.0 * chans) as usize;
+
+    let mut window = vec![0u8; dst_row * window_size.1 as usize];
+    for (row, src) in resized.chunks(src_row).enumerate() {
+        let begin = (row + offset.1 as usize) * dst_row + (offset.0 * chans) as usize;
+        window[begin..begin + src_row].copy_from_slice(src);
+    }
+
+    window
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_box_to_region() {
+        // pixel boxes are passed through, normalized ones scaled
+        let bbox = CropBox::new(10.0, 20.0, 30.0, 60.0);
+        assert!(box_to_region(&bbox, BoxUnits::Pixel, 0.0, (100, 100)) == (10, 20, 20, 40));
+        let bbox = CropBox::new(0.1, 0.2, 0.3, 0.6);
+        assert!(box_to_region(&bbox, BoxUnits::Normalized, 0.0, (100, 50)) == (10, 10, 20, 20));
+
+        // padding grows the box and is clipped at the image border
+        let bbox = CropBox::new(0.0, 10.0, 20.0, 30.0);
+        assert!(box_to_region(&bbox, BoxUnits::Pixel, 1.0, (100, 100)) == (0, 0, 30, 40));
+    }
+
+    #[test]
+    fn test_letterbox() {
+        assert!(letterbox_size((200, 100), (32, 32)) == (32, 16));
+        assert!(letterbox_size((10, 40), (32, 32)) == (8, 32));
+
+        // a 2x1 rgb image in a 2x3 window lands in the middle row
+        let window = letterbox(&[1, 2, 3, 4, 5, 6], (2, 1), (2, 3), 3);
+        assert!(window == vec![0, 0, 0, 0, 0, 0,
+                               1, 2, 3, 4, 5, 6,
+                               0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,380 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use image;
+use image::{DynamicImage, ImageBuffer, ImageDecoder, ImageResult};
+
+
+// formats the image crate either lacks (avif) or only partially decodes (its
+// webp decoder is lossy-vp8 and luma only); full support links a system codec
+// behind the matching cargo feature
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NativeFormat {
+    WebP,
+    Avif
+}
+
+
+fn read_all(path: &Path) -> ImageResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+
+#[allow(dead_code)]
+fn from_raw(size: (u32, u32), alpha: bool, pixels: Vec<u8>) -> ImageResult<DynamicImage> {
+    let bad_size = || image::ImageError::DimensionError;
+    if alpha {
+        ImageBuffer::from_raw(size.0, size.1, pixels).map(DynamicImage::ImageRgba8).ok_or_else(bad_size)
+    } else {
+        ImageBuffer::from_raw(size.0, size.1, pixels).map(DynamicImage::ImageRgb8).ok_or_else(bad_size)
+    }
+}
+
+
+#[allow(dead_code)]
+pub fn webp_has_alpha(buf: &[u8]) -> bool {
+    // the first chunk after the riff header says whether alpha is stored:
+    // vp8x carries a flags byte, vp8l an alpha hint bit in its header and
+    // simple lossy vp8 never has alpha
+    if buf.len() < 30 || &buf[0..4] != b"RIFF" || &buf[8..12] != b"WEBP" {
+        return false;
+    }
+    match &buf[12..16] {
+        b"VP8X" => buf[20] & 0x10 != 0,
+        b"VP8L" => buf[24] & 0x10 != 0,
+        _ => false
+    }
+}
+
+
+#[cfg(feature = "webp")]
+mod webp {
+    use libc::{c_int, c_void, size_t};
+    use std::{ptr, slice};
+    use image;
+    use image::{DynamicImage, GenericImage, ImageResult};
+
+    #[link(name = "webp")]
+    extern "C" {
+        fn WebPGetInfo(data: *const u8, data_size: size_t,
+                       width: *mut c_int, height: *mut c_int) -> c_int;
+        fn WebPDecodeRGBA(data: *const u8, data_size: size_t,
+                          width: *mut c_int, height: *mut c_int) -> *mut u8;
+        fn WebPDecodeRGB(data: *const u8, data_size: size_t,
+                         width: *mut c_int, height: *mut c_int) -> *mut u8;
+        fn WebPEncodeRGB(rgb: *const u8, width: c_int, height: c_int, stride: c_int,
+                         quality_factor: f32, output: *mut *mut u8) -> size_t;
+        fn WebPEncodeRGBA(rgba: *const u8, width: c_int, height: c_int, stride: c_int,
+                          quality_factor: f32, output: *mut *mut u8) -> size_t;
+        fn WebPFree(ptr: *mut c_void);
+    }
+
+    fn invalid(what: &str) -> image::ImageError {
+        image::ImageError::FormatError(format!("libwebp could not {}", what))
+    }
+
+    pub fn dimensions(buf: &[u8]) -> ImageResult<(u32, u32)> {
+        let (mut width, mut height) = (0 as c_int, 0 as c_int);
+        match unsafe { WebPGetInfo(buf.as_ptr(), buf.len(), &mut width, &mut height) } {
+            0 => Err(invalid("read the webp header")),
+            _ => Ok((width as u32, height as u32))
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> ImageResult<DynamicImage> {
+        let alpha = super::webp_has_alpha(buf);
+        let (mut width, mut height) = (0 as c_int, 0 as c_int);
+        let pixels = unsafe {
+            let ptr = match alpha {
+                true  => WebPDecodeRGBA(buf.as_ptr(), buf.len(), &mut width, &mut height),
+                false => WebPDecodeRGB(buf.as_ptr(), buf.len(), &mut width, &mut height)
+            };
+            if ptr.is_null() {
+                return Err(invalid("decode the webp image"));
+            }
+
+            let chans = if alpha { 4 } else { 3 };
+            let pixels = slice::from_raw_parts(ptr, (width * height * chans) as usize).to_vec();
+            WebPFree(ptr as *mut c_void);
+            pixels
+        };
+
+        super::from_raw((width as u32, height as u32), alpha, pixels)
+    }
+
+    pub fn encode(img: &DynamicImage, quality: u8) -> ImageResult<Vec<u8>> {
+        // lossy encode, keeping alpha when the image has it
+        let (width, height) = img.dimensions();
+        let alpha = match img.color() {
+            image::ColorType::GrayA(_) | image::ColorType::RGBA(_) => true,
+            _ => false
+        };
+        let pixels = match alpha {
+            true  => img.to_rgba().into_raw(),
+            false => img.to_rgb().into_raw()
+        };
+
+        let chans = if alpha { 4 } else { 3 };
+        let mut out = ptr::null_mut();
+        unsafe {
+            let size = match alpha {
+                true  => WebPEncodeRGBA(pixels.as_ptr(), width as c_int, height as c_int,
+                                        (width * chans) as c_int, quality as f32, &mut out),
+                false => WebPEncodeRGB(pixels.as_ptr(), width as c_int, height as c_int,
+                                       (width * chans) as c_int, quality as f32, &mut out)
+            };
+            if size == 0 || out.is_null() {
+                return Err(invalid("encode the webp image"));
+            }
+
+            let buf = slice::from_raw_parts(out, size).to_vec();
+            WebPFree(out as *mut c_void);
+            Ok(buf)
+        }
+    }
+}
+
+
+#[cfg(feature = "avif")]
+mod avif {
+    use libc::{c_char, c_int, c_void, size_t};
+    use std::ffi::CStr;
+    use std::ptr;
+    use std::slice;
+    use image;
+    use image::{DynamicImage, ImageResult};
+
+    #[repr(C)]
+    struct HeifError {
+        code: c_int,
+        subcode: c_int,
+        message: *const c_char
+    }
+
+    // libheif enum values used below
+    const HEIF_COLORSPACE_RGB: c_int = 1;
+    const HEIF_CHROMA_INTERLEAVED_RGB: c_int = 10;
+    const HEIF_CHROMA_INTERLEAVED_RGBA: c_int = 11;
+    const HEIF_CHANNEL_INTERLEAVED: c_int = 10;
+
+    #[link(name = "heif")]
+    extern "C" {
+        fn heif_context_alloc() -> *mut c_void;
+        fn heif_context_free(ctx: *mut c_void);
+        fn heif_context_read_from_memory_without_copy(ctx: *mut c_void, mem: *const c_void,
+                                                      size: size_t, options: *const c_void) -> HeifError;
+        fn heif_context_get_primary_image_handle(ctx: *mut c_void, handle: *mut *mut c_void) -> HeifError;
+        fn heif_image_handle_release(handle: *mut c_void);
+        fn heif_image_handle_get_width(handle: *const c_void) -> c_int;
+        fn heif_image_handle_get_height(handle: *const c_void) -> c_int;
+        fn heif_image_handle_has_alpha_channel(handle: *const c_void) -> c_int;
+        fn heif_decode_image(handle: *const c_void, out_img: *mut *mut c_void, colorspace: c_int,
+                             chroma: c_int, options: *const c_void) -> HeifError;
+        fn heif_image_get_plane_readonly(img: *const c_void, channel: c_int,
+                                         out_stride: *mut c_int) -> *const u8;
+        fn heif_image_release(img: *mut c_void);
+    }
+
+    fn check(err: HeifError) -> ImageResult<()> {
+        match err.code {
+            0 => Ok(()),
+            _ => {
+                let msg = match err.message.is_null() {
+                    true  => "unknown error".to_string(),
+                    false => unsafe { CStr::from_ptr(err.message) }.to_string_lossy().into_owned()
+                };
+                Err(image::ImageError::FormatError(format!("libheif: {}", msg)))
+            }
+        }
+    }
+
+    // owns the context and the primary image handle so every early return frees both
+    struct Primary {
+        ctx: *mut c_void,
+        handle: *mut c_void
+    }
+
+    impl Drop for Primary {
+        fn drop(&mut self) {
+            unsafe {
+                if !self.handle.is_null() {
+                    heif_image_handle_release(self.handle);
+                }
+                heif_context_free(self.ctx);
+            }
+        }
+    }
+
+    fn primary(buf: &[u8]) -> ImageResult<Primary> {
+        let mut primary = Primary { ctx: unsafe { heif_context_alloc() }, handle: ptr::null_mut() };
+        unsafe {
+            check(heif_context_read_from_memory_without_copy(
+                primary.ctx, buf.as_ptr() as *const c_void, buf.len(), ptr::null()))?;
+            check(heif_context_get_primary_image_handle(primary.ctx, &mut primary.handle))?;
+        }
+        Ok(primary)
+    }
+
+    pub fn header(buf: &[u8]) -> ImageResult<(u32, u32, u32)> {
+        let primary = primary(buf)?;
+        unsafe {
+            let chans = if heif_image_handle_has_alpha_channel(primary.handle) != 0 { 4 } else { 3 };
+            Ok((heif_image_handle_get_width(primary.handle) as u32,
+                heif_image_handle_get_height(primary.handle) as u32, chans))
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> ImageResult<DynamicImage> {
+        let primary = primary(buf)?;
+        let (size, alpha) = unsafe {
+            ((heif_image_handle_get_width(primary.handle) as u32,
+              heif_image_handle_get_height(primary.handle) as u32),
+             heif_image_handle_has_alpha_channel(primary.handle) != 0)
+        };
+
+        let chans = if alpha { 4 } else { 3 };
+        let chroma = if alpha { HEIF_CHROMA_INTERLEAVED_RGBA } else { HEIF_CHROMA_INTERLEAVED_RGB };
+        let mut pixels = Vec::with_capacity((size.0 * size.1 * chans) as usize);
+        unsafe {
+            let mut img = ptr::null_mut();
+            check(heif_decode_image(primary.handle, &mut img, HEIF_COLORSPACE_RGB, chroma, ptr::null()))?;
+
+            // rows are padded to the plane stride
+            let mut stride = 0 as c_int;
+            let plane = heif_image_get_plane_readonly(img, HEIF_CHANNEL_INTERLEAVED, &mut stride);
+            if !plane.is_null() {
+                let row_len = (size.0 * chans) as usize;
+                for row in 0..size.1 as usize {
+                    pixels.extend_from_slice(slice::from_raw_parts(plane.add(row * stride as usize), row_len));
+                }
+            }
+            heif_image_release(img);
+            if plane.is_null() {
+                return Err(image::ImageError::FormatError("libheif returned no pixels".to_string()));
+            }
+        }
+
+        super::from_raw(size, alpha, pixels)
+    }
+}
+
+
+fn unsupported(feature: &str) -> image::ImageError {
+    image::ImageError::UnsupportedError(format!(
+        "{} support needs parallel_image_crop built with the `{}` feature", feature, feature))
+}
+
+
+#[cfg(feature = "webp")]
+fn webp_dimensions(buf: &[u8]) -> ImageResult<(u32, u32)> { webp::dimensions(buf) }
+
+#[cfg(not(feature = "webp"))]
+fn webp_dimensions(buf: &[u8]) -> ImageResult<(u32, u32)> {
+    let mut decoder = image::webp::WebpDecoder::new(buf);
+    decoder.dimensions()
+}
+
+#[cfg(feature = "webp")]
+fn webp_chans(buf: &[u8]) -> ImageResult<u32> { Ok(if webp_has_alpha(buf) { 4 } else { 3 }) }
+
+#[cfg(not(feature = "webp"))]
+fn webp_chans(buf: &[u8]) -> ImageResult<u32> {
+    // what webp_decode below can return: the luma plane of lossy files
+    match buf.len() >= 16 && &buf[12..16] == b"VP8 " {
+        true  => Ok(1),
+        false => Err(unsupported("webp"))
+    }
+}
+
+#[cfg(feature = "webp")]
+fn webp_decode(buf: &[u8]) -> ImageResult<DynamicImage> { webp::decode(buf) }
+
+#[cfg(not(feature = "webp"))]
+fn webp_decode(buf: &[u8]) -> ImageResult<DynamicImage> {
+    // the builtin decoder only handles lossy files and returns their luma plane
+    if buf.len() < 16 || &buf[12..16] != b"VP8 " {
+        return Err(unsupported("webp"));
+    }
+    image::load_from_memory_with_format(buf, image::ImageFormat::WEBP)
+}
+
+#[cfg(feature = "webp")]
+pub fn encode_webp(img: &DynamicImage, quality: u8) -> ImageResult<Vec<u8>> { webp::encode(img, quality) }
+
+#[cfg(not(feature = "webp"))]
+pub fn encode_webp(_img: &DynamicImage, _quality: u8) -> ImageResult<Vec<u8>> { Err(unsupported("webp")) }
+
+#[cfg(feature = "avif")]
+fn avif_header(buf: &[u8]) -> ImageResult<(u32, u32, u32)> { avif::header(buf) }
+
+#[cfg(not(feature = "avif"))]
+fn avif_header(_buf: &[u8]) -> ImageResult<(u32, u32, u32)> { Err(unsupported("avif")) }
+
+#[cfg(feature = "avif")]
+fn avif_decode(buf: &[u8]) -> ImageResult<DynamicImage> { avif::decode(buf) }
+
+#[cfg(not(feature = "avif"))]
+fn avif_decode(_buf: &[u8]) -> ImageResult<DynamicImage> { Err(unsupported("avif")) }
+
+
+pub fn header(path: &Path, format: NativeFormat) -> ImageResult<(u32, u32, u32)> {
+    // (width, height, chans) of what decode returns, without decoding the pixels
+    let buf = read_all(path)?;
+    match format {
+        NativeFormat::WebP => {
+            let (width, height) = webp_dimensions(&buf)?;
+            Ok((width, height, webp_chans(&buf)?))
+        },
+        NativeFormat::Avif => avif_header(&buf)
+    }
+}
+
+
+pub fn decode(path: &Path, format: NativeFormat) -> ImageResult<DynamicImage> {
+    let buf = read_all(path)?;
+    match format {
+        NativeFormat::WebP => webp_decode(&buf),
+        NativeFormat::Avif => avif_decode(&buf)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImage;
+
+    #[test]
+    fn test_native_formats() {
+        // assets/solid.webp is a 4x3 lossless file of (200, 100, 50) without alpha
+        let buf = read_all(Path::new("assets/solid.webp")).unwrap();
+        assert!(!webp_has_alpha(&buf));
+        let decoded = decode(Path::new("assets/solid.webp"), NativeFormat::WebP);
+        if cfg!(feature = "webp") {
+            let img = decoded.unwrap();
+            assert!(img.dimensions() == (4, 3));
+            assert!(img.raw_pixels().chunks(3).all(|px| px == [200, 100, 50]));
+            assert!(header(Path::new("assets/solid.webp"), NativeFormat::WebP).unwrap() == (4, 3, 3));
+        } else {
+            // lossless webp needs libwebp
+            assert!(decoded.is_err());
+            assert!(header(Path::new("assets/solid.webp"), NativeFormat::WebP).is_err());
+        }
+
+        // assets/solid.avif is the same image, encoded losslessly through yuv
+        let decoded = decode(Path::new("assets/solid.avif"), NativeFormat::Avif);
+        if cfg!(feature = "avif") {
+            let img = decoded.unwrap();
+            assert!(img.dimensions() == (4, 3));
+            assert!(img.raw_pixels().chunks(3).all(|px| {
+                px.iter().zip(&[200u8, 100, 50]).all(|(a, b)| (*a as i32 - *b as i32).abs() <= 2)
+            }));
+            assert!(header(Path::new("assets/solid.avif"), NativeFormat::Avif).unwrap() == (4, 3, 3));
+        } else {
+            assert!(decoded.is_err());
+        }
+    }
+}
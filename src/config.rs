@@ -0,0 +1,124 @@
+use std::{cmp, mem, ptr};
+use libc::size_t;
+
+
+// versioned options of initialize_with_config and parallel_crop_and_resize_with_config.
+// callers set size to the sizeof(CropConfig) of the header they were built with and
+// fields are only ever appended, so a binary built against an older header passes a
+// shorter struct and gets the defaults of the fields it doesn't know about.
+// bump API_VERSION whenever a field or an export is added
+
+
+pub const API_VERSION: u32 = 6;
+
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropConfig {
+    pub size: size_t,
+    pub num_threads: u64,
+    pub use_vips: bool,
+    pub window_size: u32,
+    pub chans: u32,
+    pub max_img_percent: f32,
+    // 0: nearest, 1: bilinear, 2: bicubic, 3: lanczos3
+    pub filter: u32,
+    // 0: u8, 1: f32
+    pub dtype: u32,
+    // bring images upright with their EXIF orientation before cropping
+    pub exif_orientation: bool
+}
+
+
+impl Default for CropConfig {
+    fn default() -> CropConfig {
+        CropConfig {
+            size: mem::size_of::<CropConfig>() as size_t,
+            num_threads: 0,
+            use_vips: false,
+            window_size: 32,
+            chans: 3,
+            max_img_percent: 1.0,
+            filter: 0,
+            dtype: 0,
+            exif_orientation: true
+        }
+    }
+}
+
+
+fn known_size(config_ptr: *const CropConfig) -> usize {
+    // the bytes of the caller's struct that this version understands
+    assert!(!config_ptr.is_null(), "can't operate over null config");
+    let size = unsafe { ptr::read_unaligned(config_ptr as *const size_t) } as usize;
+    assert!(size >= mem::size_of::<size_t>(), "config size {} is smaller than its size field", size);
+    cmp::min(size, mem::size_of::<CropConfig>())
+}
+
+
+pub fn read(config_ptr: *const CropConfig) -> CropConfig {
+    // the caller's config, defaults past its size
+    let mut config = CropConfig::default();
+    let size = known_size(config_ptr);
+    unsafe {
+        ptr::copy_nonoverlapping(config_ptr as *const u8, &mut config as *mut CropConfig as *mut u8, size);
+    }
+    config.size = mem::size_of::<CropConfig>() as size_t;
+
+    assert!(config.window_size > 0, "window size must be positive");
+    assert!(config.chans >= 1 && config.chans <= 4, "chans must be in [1, 4]");
+    assert!(config.filter <= 3, "unknown filter {}", config.filter);
+    assert!(config.dtype <= 1, "unknown dtype {}", config.dtype);
+    config
+}
+
+
+pub fn init(config_ptr: *mut CropConfig) {
+    // writes the defaults into the first size bytes of the caller's config
+    let size = known_size(config_ptr);
+    let defaults = CropConfig::default();
+    unsafe {
+        ptr::copy_nonoverlapping((&defaults as *const CropConfig as *const u8).offset(mem::size_of::<size_t>() as isize),
+                                 (config_ptr as *mut u8).offset(mem::size_of::<size_t>() as isize),
+                                 size - mem::size_of::<size_t>());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_sizes() {
+        // a caller built against a header that stopped after window_size
+        let mut config = CropConfig { size: 0, num_threads: 4, use_vips: true, window_size: 64,
+                                      chans: 1, max_img_percent: 0.5, filter: 3, dtype: 1,
+                                      exif_orientation: false };
+        config.size = (&config.chans as *const u32 as usize - &config as *const CropConfig as usize) as size_t;
+        let read_config = read(&config);
+        assert_eq!((read_config.num_threads, read_config.use_vips, read_config.window_size), (4, true, 64));
+        assert_eq!((read_config.chans, read_config.filter, read_config.dtype), (3, 0, 0));
+        assert!(read_config.exif_orientation);
+        assert_eq!(read_config.size, mem::size_of::<CropConfig>() as size_t);
+
+        // init leaves the fields past its size alone
+        init(&mut config);
+        assert_eq!((config.num_threads, config.window_size, config.chans), (0, 32, 1));
+
+        let mut config = CropConfig { size: mem::size_of::<CropConfig>() as size_t, ..config };
+        init(&mut config);
+        assert_eq!(config, CropConfig::default());
+    }
+
+    #[test]
+    #[cfg(feature = "header")]
+    fn test_header_is_current() {
+        // build.rs regenerated the header into OUT_DIR with cbindgen, the checked in
+        // one must match it byte for byte
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/parallel_image_crop.h"));
+        let checked_in = include_str!("../parallel_image_crop.h");
+        assert!(generated == checked_in,
+                "parallel_image_crop.h is stale, regenerate it with UPDATE_HEADER=1 cargo build --features header");
+    }
+}
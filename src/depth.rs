@@ -0,0 +1,239 @@
+use std::cmp;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use image;
+use image::{ImageResult, ImageDecoder, DecodingResult, ColorType, GenericImage};
+use png;
+use png::HasParameters;
+use image::tiff::TIFFDecoder;
+use image::hdr::HDRDecoder;
+use libc::{size_t, c_char, c_void};
+use lazy_load;
+use orient;
+use batch;
+
+
+// support for sources that are not 8-bit (16-bit PNG / TIFF, radiance HDR).
+//
+// samples are carried as f32 in their native range, eg: [0, 65535] for a
+// 16-bit image, which represents every u16 value exactly
+
+
+pub struct Samples {
+    pub size: (u32, u32),
+    pub chans: u32,
+    pub data: Vec<f32>  // [h, w, chans]
+}
+
+
+// how the samples are mapped onto a u8 output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Windowing {
+    MinMax { min: f32, max: f32 },       // fixed value range mapped onto [0, 255]
+    Percentile { low: f32, high: f32 }   // per-window percentiles in [0, 100]
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    U16,
+    F32,
+    U8(Windowing)
+}
+
+
+fn color_chans(color: ColorType) -> u32 {
+    match color {
+        ColorType::Gray(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) | ColorType::Palette(_) => 3,
+        ColorType::RGBA(_) => 4
+    }
+}
+
+
+fn decoded_samples<D: ImageDecoder>(mut decoder: D) -> ImageResult<Samples> {
+    let color = decoder.colortype()?;
+    let size = decoder.dimensions()?;
+    let data = match decoder.read_image()? {
+        DecodingResult::U8(buf) => buf.into_iter().map(|v| v as f32).collect(),
+        DecodingResult::U16(buf) => buf.into_iter().map(|v| v as f32).collect()
+    };
+
+    Ok(Samples { size: size, chans: color_chans(color), data: data })
+}
+
+
+fn png_samples(path: &Path) -> ImageResult<Samples> {
+    // the image crate's png decoder strips 16-bit samples, so only expand
+    // palettes and low bit depths here
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buf = vec![0u8; reader.output_buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    // EXPAND only widens depths below eight (though output_color_type reports
+    // eight for sixteen bit inputs too), so the input depth gives the sample width
+    let (color, _) = reader.output_color_type();
+    let data = match reader.info().bit_depth {
+        // sixteen bit samples are big endian byte pairs
+        png::BitDepth::Sixteen => buf.chunks(2).map(|b| ((b[0] as u16) << 8 | b[1] as u16) as f32).collect(),
+        _ => buf.into_iter().map(|v| v as f32).collect()
+    };
+
+    Ok(Samples { size: (info.width, info.height), chans: color.samples() as u32, data: data })
+}
+
+
+pub fn decode(path_str: &str) -> ImageResult<Samples> {
+    // decodes the full image without reducing its bit depth, upright
+    let samples = decode_stored(path_str)?;
+    Ok(match orient::transform(path_str) {
+        Some(aug) => {
+            let (data, size) = orient::orient_buffer(&samples.data, samples.size, samples.chans, &aug);
+            Samples { size: size, chans: samples.chans, data: data }
+        },
+        None => samples
+    })
+}
+
+
+fn decode_stored(path_str: &str) -> ImageResult<Samples> {
+    // the samples in their stored orientation
+    let path = Path::new(path_str);
+    match lazy_load::get_image_format(path) {
+        Ok(image::ImageFormat::PNG) => png_samples(path),
+        Ok(image::ImageFormat::TIFF) => decoded_samples(TIFFDecoder::new(BufReader::new(File::open(path)?))?),
+        Ok(image::ImageFormat::HDR) => {
+            let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+            let meta = decoder.metadata();
+            let pixels = decoder.read_image_hdr()?;
+            Ok(Samples {
+                size: (meta.width, meta.height),
+                chans: 3,
+                data: pixels.iter().flat_map(|px| px.data.to_vec()).collect()
+            })
+        },
+        _ => {
+            // everything else is 8-bit in the image crate or a native codec
+            let img = lazy_load::open_image(path_str)?;
+            let chans = color_chans(img.color());
+            Ok(Samples { size: img.dimensions(), chans: chans,
+                         data: img.raw_pixels().into_iter().map(|v| v as f32).collect() })
+        }
+    }
+}
+
+
+pub fn crop_and_resize(samples: &Samples, region: (u32, u32, u32, u32), out_size: (u32, u32)) -> Vec<f32> {
+    // nearest neighbour resize of the (x, y, width, height) region, as in piston
+    let (x, y, width, height) = region;
+    let chans = samples.chans as usize;
+    let mut window = Vec::with_capacity(out_size.0 as usize * out_size.1 as usize * chans);
+    for v in 0..out_size.1 {
+        let sy = y + cmp::min(((v as f32 + 0.5) * height as f32 / out_size.1 as f32) as u32, height - 1);
+        for u in 0..out_size.0 {
+            let sx = x + cmp::min(((u as f32 + 0.5) * width as f32 / out_size.0 as f32) as u32, width - 1);
+            let begin = (sy as usize * samples.size.0 as usize + sx as usize) * chans;
+            window.extend_from_slice(&samples.data[begin..begin + chans]);
+        }
+    }
+
+    window
+}
+
+
+fn percentile(sorted: &[f32], pct: f32) -> f32 {
+    let idx = (pct.max(0.0).min(100.0) / 100.0 * (sorted.len() - 1) as f32).round() as usize;
+    sorted[idx]
+}
+
+
+pub fn apply_output(window: &mut [f32], output: Output) {
+    // brings the window into the value range of the output dtype
+    let (min, max) = match output {
+        Output::F32 => return,
+        Output::U16 => (0.0, 65535.0),
+        Output::U8(Windowing::MinMax { min, max }) => (min, max),
+        Output::U8(Windowing::Percentile { low, high }) => {
+            if window.is_empty() {
+                return;
+            }
+            let mut sorted = window.to_vec();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(cmp::Ordering::Equal));
+            (percentile(&sorted, low), percentile(&sorted, high))
+        }
+    };
+
+    match output {
+        Output::U8(_) => {
+            let range = (max - min).max(::std::f32::EPSILON);
+            for val in window.iter_mut() {
+                *val = ((*val - min) / range * 255.0).round().max(0.0).min(255.0);
+            }
+        },
+        _ => for val in window.iter_mut() {
+            *val = val.round().max(min).min(max);
+        }
+    }
+}
+
+
+pub fn run<F>(image_paths_ptr: *const *const c_char, return_ptr: *mut c_void, output: Output,
+              win_size: usize, length: size_t, crop_one: F)
+    where F: Fn(usize, &str) -> Vec<f32> + Sync
+{
+    // batch::run over apply_output'd windows, cast to the dtype of output
+    match output {
+        Output::U16 => batch::run(image_paths_ptr, return_ptr as *mut u16, win_size, length, |idx, path| {
+            crop_one(idx, path).iter().map(|&v| v as u16).collect()
+        }),
+        Output::F32 => batch::run(image_paths_ptr, return_ptr as *mut f32, win_size, length, crop_one),
+        Output::U8(_) => batch::run(image_paths_ptr, return_ptr as *mut u8, win_size, length, |idx, path| {
+            crop_one(idx, path).iter().map(|&v| v as u8).collect()
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::png::PNGEncoder;
+
+    #[test]
+    fn test_sixteen_bit_png() {
+        // a 4x2 ramp of big endian 16-bit gray values
+        let values: Vec<u16> = (0..8).map(|v| v * 1000).collect();
+        let bytes: Vec<u8> = values.iter().flat_map(|v| vec![(v >> 8) as u8, *v as u8]).collect();
+        PNGEncoder::new(File::create("assets/test_gray16.png").unwrap())
+            .encode(&bytes, 4, 2, ColorType::Gray(16)).unwrap();
+
+        let samples = decode("assets/test_gray16.png").unwrap();
+        ::std::fs::remove_file("assets/test_gray16.png").unwrap();
+        assert!(samples.size == (4, 2) && samples.chans == 1);
+        assert!(samples.data == values.iter().map(|&v| v as f32).collect::<Vec<f32>>(),
+                "data was {:?}", samples.data);
+
+        // the right half upsampled keeps the native values
+        let window = crop_and_resize(&samples, (2, 0, 2, 2), (4, 4));
+        assert!(window[..4] == [2000.0, 2000.0, 3000.0, 3000.0], "window was {:?}", window);
+    }
+
+    #[test]
+    fn test_windowing() {
+        let mut fixed = vec![0.0, 500.0, 1000.0, 2000.0];
+        apply_output(&mut fixed, Output::U8(Windowing::MinMax { min: 0.0, max: 1000.0 }));
+        assert!(fixed == vec![0.0, 128.0, 255.0, 255.0], "fixed was {:?}", fixed);
+
+        let mut pct = vec![10.0, 20.0, 30.0, 1e6];
+        apply_output(&mut pct, Output::U8(Windowing::Percentile { low: 0.0, high: 66.0 }));
+        assert!(pct == vec![0.0, 128.0, 255.0, 255.0], "pct was {:?}", pct);
+
+        let mut as_u16 = vec![-1.0, 70000.0, 2.4];
+        apply_output(&mut as_u16, Output::U16);
+        assert!(as_u16 == vec![0.0, 65535.0, 2.0]);
+    }
+}
@@ -0,0 +1,135 @@
+use libc::c_void;
+
+use npy::Layout;
+
+
+// DLPack (the v0.8 ABI) tensors owning a batch of crops, so torch, jax or tensorflow
+// can take it without a copy. the consumer calls deleter once, when it is done with
+// the tensor, which frees the batch, its shape / strides and the tensor itself
+
+
+const DL_CPU: i32 = 1;
+const DL_UINT: u8 = 1;
+const DL_FLOAT: u8 = 2;
+
+
+#[repr(C)]
+pub struct DLDevice {
+    pub device_type: i32,
+    pub device_id: i32
+}
+
+#[repr(C)]
+pub struct DLDataType {
+    pub code: u8,
+    pub bits: u8,
+    pub lanes: u16
+}
+
+#[repr(C)]
+pub struct DLTensor {
+    pub data: *mut c_void,
+    pub device: DLDevice,
+    pub ndim: i32,
+    pub dtype: DLDataType,
+    pub shape: *mut i64,
+    pub strides: *mut i64,
+    pub byte_offset: u64
+}
+
+#[repr(C)]
+pub struct DLManagedTensor {
+    pub dl_tensor: DLTensor,
+    pub manager_ctx: *mut c_void,
+    pub deleter: Option<extern "C" fn(*mut DLManagedTensor)>
+}
+
+
+pub enum Buffer {
+    U8(Vec<u8>),
+    F32(Vec<f32>)
+}
+
+
+impl Buffer {
+    pub fn to_layout(self, item_shape: (usize, usize, usize), layout: Layout) -> Buffer {
+        // reorders an [N, H, W, C] batch
+        match (self, layout) {
+            (buffer, Layout::NHWC) => buffer,
+            (Buffer::U8(data), Layout::NCHW) => Buffer::U8(::npy::nchw(&data, item_shape)),
+            (Buffer::F32(data), Layout::NCHW) => Buffer::F32(::npy::nchw(&data, item_shape))
+        }
+    }
+}
+
+
+struct Context {
+    // everything the tensor points into, freed by delete
+    buffer: Buffer,
+    shape: [i64; 4],
+    strides: [i64; 4]
+}
+
+
+extern "C" fn delete(tensor: *mut DLManagedTensor) {
+    if tensor.is_null() {
+        return;
+    }
+    unsafe {
+        let tensor = Box::from_raw(tensor);
+        drop(Box::from_raw(tensor.manager_ctx as *mut Context));
+    }
+}
+
+
+pub fn managed_tensor(buffer: Buffer, shape: [usize; 4]) -> *mut DLManagedTensor {
+    // hands the buffer over to a compact row major cpu tensor of shape
+    let mut ctx = Box::new(Context {
+        buffer: buffer,
+        shape: [shape[0] as i64, shape[1] as i64, shape[2] as i64, shape[3] as i64],
+        strides: [(shape[1] * shape[2] * shape[3]) as i64, (shape[2] * shape[3]) as i64, shape[3] as i64, 1]
+    });
+    let (data, dtype) = match ctx.buffer {
+        Buffer::U8(ref mut v) => (v.as_mut_ptr() as *mut c_void, DLDataType { code: DL_UINT, bits: 8, lanes: 1 }),
+        Buffer::F32(ref mut v) => (v.as_mut_ptr() as *mut c_void, DLDataType { code: DL_FLOAT, bits: 32, lanes: 1 })
+    };
+
+    let tensor = DLTensor {
+        data: data,
+        device: DLDevice { device_type: DL_CPU, device_id: 0 },
+        ndim: 4,
+        dtype: dtype,
+        shape: ctx.shape.as_mut_ptr(),
+        strides: ctx.strides.as_mut_ptr(),
+        byte_offset: 0
+    };
+    Box::into_raw(Box::new(DLManagedTensor {
+        dl_tensor: tensor,
+        manager_ctx: Box::into_raw(ctx) as *mut c_void,
+        deleter: Some(delete)
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::slice;
+
+    #[test]
+    fn test_managed_tensor() {
+        // two 1x2 rgb items reordered to NCHW
+        let nhwc: Vec<f32> = (0..12).map(|v| v as f32).collect();
+        let buffer = Buffer::F32(nhwc).to_layout((1, 2, 3), Layout::NCHW);
+        let managed = managed_tensor(buffer, Layout::NCHW.shape(2, 1, 2, 3));
+
+        unsafe {
+            let tensor = &(*managed).dl_tensor;
+            assert_eq!((tensor.ndim, tensor.dtype.code, tensor.dtype.bits), (4, DL_FLOAT, 32));
+            assert_eq!(slice::from_raw_parts(tensor.shape, 4), &[2, 3, 1, 2]);
+            assert_eq!(slice::from_raw_parts(tensor.strides, 4), &[6, 2, 2, 1]);
+            assert_eq!(slice::from_raw_parts(tensor.data as *const f32, 6), &[0., 3., 1., 4., 2., 5.]);
+            ((*managed).deleter.unwrap())(managed);
+        }
+    }
+}
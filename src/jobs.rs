@@ -0,0 +1,243 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use rayon::ThreadPool;
+
+
+// batches submitted to the crop manager's threadpool without blocking the caller.
+// every job gets an id whose status can be polled, waited on with a timeout or
+// cancelled while it hasn't started. finished jobs (done, cancelled or failed) keep
+// reporting their status, eg: a wait after a poll, until more than RETAINED_JOBS
+// jobs are known; past that the oldest finished ones are forgotten
+
+
+// the statuses returned by poll_crop_batch and wait_crop_batch. unknown is for ids
+// that were never submitted or are already forgotten
+pub const CROP_BATCH_PENDING: i32 = 0;
+pub const CROP_BATCH_RUNNING: i32 = 1;
+pub const CROP_BATCH_DONE: i32 = 2;
+pub const CROP_BATCH_CANCELLED: i32 = 3;
+pub const CROP_BATCH_FAILED: i32 = 4;
+pub const CROP_BATCH_UNKNOWN: i32 = -1;
+
+const RETAINED_JOBS: usize = 1024;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Pending,
+    Running,
+    Done,
+    Cancelled,
+    Failed
+}
+
+
+impl Status {
+    pub fn code(&self) -> i32 {
+        match *self {
+            Status::Pending => CROP_BATCH_PENDING,
+            Status::Running => CROP_BATCH_RUNNING,
+            Status::Done => CROP_BATCH_DONE,
+            Status::Cancelled => CROP_BATCH_CANCELLED,
+            Status::Failed => CROP_BATCH_FAILED
+        }
+    }
+
+    fn finished(&self) -> bool {
+        match *self {
+            Status::Pending | Status::Running => false,
+            _ => true
+        }
+    }
+}
+
+
+struct Handle {
+    status: Mutex<Status>,
+    changed: Condvar
+}
+
+
+impl Handle {
+    fn finished(&self) -> bool {
+        self.status.lock().unwrap().finished()
+    }
+
+    fn set(&self, status: Status) {
+        *self.status.lock().unwrap() = status;
+        self.changed.notify_all();
+    }
+
+    fn start(&self) -> bool {
+        // moves a pending job to running, false when it was cancelled first
+        let mut status = self.status.lock().unwrap();
+        match *status {
+            Status::Pending => {
+                *status = Status::Running;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> Status {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut status = self.status.lock().unwrap();
+        while !status.finished() {
+            status = match deadline {
+                None => self.changed.wait(status).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    self.changed.wait_timeout(status, deadline - now).unwrap().0
+                }
+            };
+        }
+        *status
+    }
+}
+
+
+fn forget_finished(jobs: &mut HashMap<u64, Arc<Handle>>) {
+    // drops the oldest finished jobs until at most half of RETAINED_JOBS are left
+    let mut finished: Vec<u64> = jobs.iter().filter(|&(_, h)| h.finished()).map(|(&id, _)| id).collect();
+    finished.sort();
+    let excess = cmp::min(finished.len(), (jobs.len() + 1).saturating_sub(RETAINED_JOBS / 2));
+    for id in &finished[..excess] {
+        jobs.remove(id);
+    }
+}
+
+
+pub struct JobQueue {
+    next_id: AtomicUsize,
+    jobs: Mutex<HashMap<u64, Arc<Handle>>>
+}
+
+
+impl JobQueue {
+    pub fn new() -> JobQueue {
+        JobQueue {
+            next_id: AtomicUsize::new(1),
+            jobs: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn submit<F: FnOnce() + Send + 'static>(&self, pool: &ThreadPool, work: F) -> u64 {
+        // queues work on the pool and returns its id (never 0). a panic of work,
+        // eg: an unreadable image, marks the job failed
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) as u64;
+        let handle = Arc::new(Handle { status: Mutex::new(Status::Pending), changed: Condvar::new() });
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            if jobs.len() >= RETAINED_JOBS {
+                forget_finished(&mut jobs);
+            }
+            jobs.insert(id, handle.clone());
+        }
+
+        pool.spawn(move || {
+            if handle.start() {
+                handle.set(match panic::catch_unwind(AssertUnwindSafe(work)) {
+                    Ok(()) => Status::Done,
+                    Err(_) => Status::Failed
+                });
+            }
+        });
+        id
+    }
+
+    fn handle(&self, id: u64) -> Option<Arc<Handle>> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn poll(&self, id: u64) -> i32 {
+        match self.handle(id) {
+            Some(handle) => handle.status.lock().unwrap().code(),
+            None => CROP_BATCH_UNKNOWN
+        }
+    }
+
+    pub fn wait(&self, id: u64, timeout: Option<Duration>) -> i32 {
+        // the status once the job finished or the timeout (None: forever) ran out
+        match self.handle(id) {
+            Some(handle) => handle.wait(timeout).code(),
+            None => CROP_BATCH_UNKNOWN
+        }
+    }
+
+    pub fn cancel(&self, id: u64) -> bool {
+        // only jobs that haven't started can be cancelled
+        match self.handle(id) {
+            Some(handle) => {
+                let mut status = handle.status.lock().unwrap();
+                match *status {
+                    Status::Pending => {
+                        *status = Status::Cancelled;
+                        handle.changed.notify_all();
+                        true
+                    },
+                    _ => false
+                }
+            },
+            None => false
+        }
+    }
+
+    pub fn wait_all(&self) {
+        // blocks until no job is left running, used before the pool goes away
+        let handles: Vec<Arc<Handle>> = self.jobs.lock().unwrap().values().cloned().collect();
+        for handle in handles {
+            handle.wait(None);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::ThreadPoolBuilder;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_job_queue() {
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let queue = JobQueue::new();
+
+        // the first job holds the only thread until released, so the second stays pending
+        let (release, blocked) = channel::<()>();
+        let first = queue.submit(&pool, move || blocked.recv().unwrap());
+        let second = queue.submit(&pool, || ());
+        let failing = queue.submit(&pool, || panic!("unreadable image"));
+        assert_eq!(queue.wait(second, Some(Duration::from_millis(10))), Status::Pending.code());
+        assert!(queue.cancel(second));
+        assert_eq!(queue.poll(second), Status::Cancelled.code());
+        assert_eq!(queue.wait(second, None), Status::Cancelled.code());
+
+        // finished jobs keep their status, eg: polled and then waited on
+        release.send(()).unwrap();
+        assert_eq!(queue.wait(first, None), Status::Done.code());
+        assert_eq!(queue.poll(first), Status::Done.code());
+        assert_eq!(queue.wait(failing, None), Status::Failed.code());
+        assert!(!queue.cancel(first));
+        queue.wait_all();
+        assert_eq!(queue.poll(0), CROP_BATCH_UNKNOWN);
+
+        // jobs nobody polls don't pile up
+        let ids: Vec<u64> = (0..3 * RETAINED_JOBS).map(|_| {
+            let id = queue.submit(&pool, || ());
+            queue.handle(id).unwrap().wait(None);
+            id
+        }).collect();
+        assert!(queue.jobs.lock().unwrap().len() <= RETAINED_JOBS);
+        assert_eq!(queue.poll(ids[0]), CROP_BATCH_UNKNOWN);
+        assert_eq!(queue.poll(*ids.last().unwrap()), CROP_BATCH_DONE);
+    }
+}
@@ -17,34 +17,21 @@ use image::pnm;
 #[allow(unused)]
 use image::{ImageDecoder, ImageFormat, ImageResult,
             DynamicImage, FilterType};
+use image::{ImageBuffer, ColorType, DecodingResult};
+use codecs;
+use codecs::NativeFormat;
+use sniff;
+use sniff::Format;
 
 
-#[allow(dead_code)]
 pub fn get_image_format(path: &Path) -> ImageResult<ImageFormat>
 {
-    let ext = path.extension().and_then(|s| s.to_str())
-                  .map_or("".to_string(), |s| s.to_ascii_lowercase());
-
-    match &ext[..] {
-        "jpg" |
-        "jpeg" => Ok(image::ImageFormat::JPEG),
-        "png"  => Ok(image::ImageFormat::PNG),
-        "gif"  => Ok(image::ImageFormat::GIF),
-        "webp" => Ok(image::ImageFormat::WEBP),
-        "tif" |
-        "tiff" => Ok(image::ImageFormat::TIFF),
-        "tga" =>  Ok(image::ImageFormat::TGA),
-        "bmp" =>  Ok(image::ImageFormat::BMP),
-        "ico" =>  Ok(image::ImageFormat::ICO),
-        "hdr" =>  Ok(image::ImageFormat::HDR),
-        "pbm" |
-        "pam" |
-        "ppm" |
-        "pgm" =>  Ok(image::ImageFormat::PNM),
-        format => return Err(image::ImageError::UnsupportedError(format!(
-            "Image format image/{:?} is not supported.",
-            format
-        )))
+    // the image crate format of the file's content, falling back to its extension
+    match try!(sniff::detect_format(path)) {
+        Format::Image(format) => Ok(format),
+        Format::Native(NativeFormat::WebP) => Ok(image::ImageFormat::WEBP),
+        Format::Native(native) => Err(image::ImageError::UnsupportedError(format!(
+            "{:?} is decoded by a native codec, not the image crate", native)))
     }
 }
 
@@ -52,31 +39,130 @@ pub fn get_image_format(path: &Path) -> ImageResult<ImageFormat>
 #[allow(dead_code)]
 pub fn dimensions(path_str: &str) -> ImageResult<(u32, u32)>
 {
+    let (width, height, _) = try!(header(path_str));
+    Ok((width, height))
+}
+
+
+fn color_chans(color: ColorType) -> u32
+{
+    // channels of the DynamicImage the color type decodes to
+    match color {
+        ColorType::Gray(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) | ColorType::Palette(_) => 3,
+        ColorType::RGBA(_) => 4
+    }
+}
+
+
+fn decoder_header<D: ImageDecoder>(mut decoder: D) -> ImageResult<(u32, u32, u32)>
+{
+    let (width, height) = try!(decoder.dimensions());
+    Ok((width, height, color_chans(try!(decoder.colortype()))))
+}
+
+
+pub fn header(path_str: &str) -> ImageResult<(u32, u32, u32)>
+{
+    // (width, height, chans) from the file header, without decoding any pixels
     let path = Path::new(&path_str);
-    let format = get_image_format(path).unwrap();
+    let format = match try!(sniff::detect_format(path)) {
+        Format::Native(native) => return codecs::header(path, native),
+        Format::Image(format) => format
+    };
 
     // the file input reader
-    let fin = match File::open(path) {
-        Ok(f)  => f,
-        Err(err) => return Err(image::ImageError::IoError(err))
-    };
-    let fin = BufReader::new(fin);
+    let fin = BufReader::new(try!(File::open(path)));
 
     match format {
-        image::ImageFormat::PNG  => Ok(png::PNGDecoder::new(fin).dimensions().unwrap()),
-        image::ImageFormat::GIF  => Ok(gif::Decoder::new(fin).dimensions().unwrap()),
-        image::ImageFormat::JPEG => Ok(jpeg::JPEGDecoder::new(fin).dimensions().unwrap()),
-        image::ImageFormat::TIFF => Ok(try!(tiff::TIFFDecoder::new(fin)).dimensions().unwrap()),
-        image::ImageFormat::TGA => Ok(tga::TGADecoder::new(fin).dimensions().unwrap()),
-        image::ImageFormat::BMP => Ok(bmp::BMPDecoder::new(fin).dimensions().unwrap()),
-        image::ImageFormat::ICO => Ok(try!(ico::ICODecoder::new(fin)).dimensions().unwrap()),
-        image::ImageFormat::HDR => Ok(try!(hdr::HDRAdapter::new(BufReader::new(fin))).dimensions().unwrap()),
-        image::ImageFormat::PNM => Ok(try!(pnm::PNMDecoder::new(BufReader::new(fin))).dimensions().unwrap()),
+        image::ImageFormat::PNG  => decoder_header(png::PNGDecoder::new(fin)),
+        image::ImageFormat::GIF  => decoder_header(gif::Decoder::new(fin)),
+        image::ImageFormat::JPEG => decoder_header(jpeg::JPEGDecoder::new(fin)),
+        image::ImageFormat::TIFF => decoder_header(try!(tiff::TIFFDecoder::new(fin))),
+        image::ImageFormat::TGA => decoder_header(tga::TGADecoder::new(fin)),
+        image::ImageFormat::BMP => decoder_header(bmp::BMPDecoder::new(fin)),
+        image::ImageFormat::ICO => decoder_header(try!(ico::ICODecoder::new(fin))),
+        image::ImageFormat::HDR => decoder_header(try!(hdr::HDRAdapter::new(fin))),
+        image::ImageFormat::PNM => decoder_header(try!(pnm::PNMDecoder::new(fin))),
         _ => Err(image::ImageError::UnsupportedError(format!("A decoder for {:?} is not available.", format))),
     }
 }
 
 
+pub fn decoded_to_image(color: ColorType, decoded: DecodingResult, size: (u32, u32)) -> ImageResult<DynamicImage>
+{
+    // builds an 8-bit image from raw decoder output, keeping the high byte of 16-bit samples
+    let buf = match decoded {
+        DecodingResult::U8(buf) => buf,
+        DecodingResult::U16(buf) => buf.into_iter().map(|v| (v >> 8) as u8).collect()
+    };
+
+    let (w, h) = size;
+    let img = match color {
+        ColorType::Gray(_) => ImageBuffer::from_raw(w, h, buf).map(DynamicImage::ImageLuma8),
+        ColorType::GrayA(_) => ImageBuffer::from_raw(w, h, buf).map(DynamicImage::ImageLumaA8),
+        ColorType::RGB(_) => ImageBuffer::from_raw(w, h, buf).map(DynamicImage::ImageRgb8),
+        ColorType::RGBA(_) => ImageBuffer::from_raw(w, h, buf).map(DynamicImage::ImageRgba8),
+        _ => return Err(image::ImageError::UnsupportedColor(color))
+    };
+
+    match img {
+        Some(img) => Ok(img),
+        None => Err(image::ImageError::DimensionError)
+    }
+}
+
+
+pub fn open_image(path_str: &str) -> ImageResult<DynamicImage>
+{
+    // decodes by content, so mislabelled and extensionless files still load
+    let path = Path::new(&path_str);
+    match try!(sniff::detect_format(path)) {
+        Format::Native(native) => codecs::decode(path, native),
+        Format::Image(format) => image::load(BufReader::new(try!(File::open(path))), format)
+    }
+}
+
+
+pub fn frame_to_image(path_str: &str, frame: u32) -> ImageResult<DynamicImage>
+{
+    // decodes page `frame` of a multi-page tiff or frame `frame` of an animated
+    // gif (composited onto the canvas); frame 0 of any other format is the image
+    if frame == 0 {
+        return open_image(path_str);
+    }
+
+    let path = Path::new(&path_str);
+    let fin = BufReader::new(try!(File::open(path)));
+    let out_of_range = image::ImageError::UnsupportedError(
+        format!("{:?} has no frame {:?}", path_str, frame));
+    match try!(get_image_format(path)) {
+        image::ImageFormat::TIFF => {
+            let mut decoder = try!(tiff::TIFFDecoder::new(fin));
+            for _ in 0..frame {
+                if !decoder.more_images() {
+                    return Err(out_of_range);
+                }
+                decoder = try!(decoder.next_image());
+            }
+
+            let color = try!(decoder.colortype());
+            let size = try!(decoder.dimensions());
+            let decoded = try!(decoder.read_image());
+            decoded_to_image(color, decoded, size)
+        },
+        image::ImageFormat::GIF => {
+            match try!(gif::Decoder::new(fin).into_frames()).nth(frame as usize) {
+                Some(frame) => Ok(DynamicImage::ImageRgba8(frame.into_buffer())),
+                None => Err(out_of_range)
+            }
+        },
+        _ => Err(out_of_range)
+    }
+}
+
+
 #[allow(dead_code)]
 pub fn vec_to_image(v: &Vec<u8>) -> ImageResult<DynamicImage>
 {
@@ -96,7 +182,14 @@ pub fn lazy_crop_to_image(path: &str, x: u32, y: u32, width: u32, length: u32) -
 pub fn lazy_crop_to_vec(path_str: &str, x: u32, y: u32, width: u32, length: u32) -> ImageResult<Vec<u8>>
 {
     let path = Path::new(&path_str);
-    let format = get_image_format(path).unwrap();
+    let format = match try!(sniff::detect_format(path)) {
+        Format::Native(native) => {
+            // webp and avif have no region decoding, crop the full image
+            let mut img = try!(codecs::decode(path, native));
+            return Ok(img.crop(x, y, width, length).raw_pixels());
+        },
+        Format::Image(format) => format
+    };
 
     // the file input reader
     let fin = match File::open(path) {
@@ -151,3 +244,40 @@ pub fn lazy_crop_and_resize(path: &str, scale: f32, x_crop: f32, y_crop: f32,
         resize_width, resize_height, FilterType::Nearest
     )
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImage;
+
+    #[test]
+    fn test_frame_selection() {
+        // single frame formats only have frame 0
+        let first = frame_to_image("assets/lena_gray.png", 0).unwrap();
+        assert!(first.dimensions() == (512, 512));
+        assert!(frame_to_image("assets/lena_gray.png", 1).is_err());
+
+        // 16-bit tiff pages keep their high byte
+        let img = decoded_to_image(ColorType::Gray(16), DecodingResult::U16(vec![0x1234, 0xff00]), (2, 1)).unwrap();
+        assert!(img.raw_pixels() == vec![0x12, 0xff]);
+    }
+
+    #[test]
+    fn test_header_matches_decode() {
+        // the header alone gives the shape the full decode ends up with
+        for path in &["assets/lena.png", "assets/lena_gray.png"] {
+            let img = open_image(path).unwrap();
+            let chans = img.raw_pixels().len() as u32 / (img.width() * img.height());
+            assert_eq!(header(path).unwrap(), (img.width(), img.height(), chans));
+        }
+        assert!(header("assets/missing.png").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "webp")]
+    fn test_webp_crop() {
+        assert!(dimensions("assets/solid.webp").unwrap() == (4, 3));
+        assert!(lazy_crop_to_vec("assets/solid.webp", 1, 1, 2, 2).unwrap() == [200, 100, 50].repeat(4));
+    }
+}
@@ -1,10 +1,15 @@
 extern crate libc;
 extern crate image;
+extern crate png;
+extern crate lzw;
+extern crate inflate;
 extern crate rayon;
 extern crate vips_sys;
 //extern crate time;
 #[macro_use] extern crate itertools;
 #[macro_use] extern crate lazy_static;
+#[cfg(feature = "python")] extern crate pyo3;
+#[cfg(feature = "python")] extern crate numpy;
 
 use std::ptr;
 use std::fs::File;
@@ -15,19 +20,45 @@ use rayon::prelude::*;
 use libc::{size_t, c_char, c_uchar, c_void, c_double, c_longlong};
 
 
+mod config;
 mod lazy_load;
+mod codecs;
+mod sniff;
+mod save;
+mod batch;
+mod npy;
+mod dlpack;
+mod jobs;
 mod vips_ffi;
 mod vips;
 mod piston;
+mod stitch;
+mod boxes;
+mod warp;
+mod rng;
+mod sampling;
+mod augment;
+mod mixing;
+mod annotate;
+mod paired;
+mod depth;
+mod orient;
+mod tiled;
+#[cfg(feature = "python")] mod python;
 
 use vips_ffi::VipsInstance;
+pub use config::CropConfig;
+pub use dlpack::DLManagedTensor;
+pub use jobs::{CROP_BATCH_PENDING, CROP_BATCH_RUNNING, CROP_BATCH_DONE, CROP_BATCH_CANCELLED, CROP_BATCH_FAILED,
+               CROP_BATCH_UNKNOWN};
 
 
 struct CropManager{
     threadpool: rayon::ThreadPool,
     num_threads: usize,
     use_vips: bool,
-    vips_instance: Option<VipsInstance>
+    vips_instance: Option<VipsInstance>,
+    jobs: jobs::JobQueue
 }
 
 impl Drop for CropManager {
@@ -38,24 +69,61 @@ impl Drop for CropManager {
 }
 
 
+fn crop_manager<'a>(crop_manager_ptr: *const c_void) -> &'a CropManager {
+    // borrow the crop manager without taking ownership of it
+    assert!(!crop_manager_ptr.is_null(), "can't operate over null crop manager");
+    unsafe { &*(crop_manager_ptr as *const Box<CropManager>) }
+}
+
+
 pub fn scale_range(val: f32, newmin: f32, newmax: f32) -> f32 {
     // simple helper to scale a value range
     (((val) * (newmax - newmin)) / (1.0)) + newmin
 }
 
 
-#[no_mangle]
-pub extern "C" fn initialize(num_threads: u64, use_vips: bool) -> *mut c_void
+pub fn crop_region(img_size: (u32, u32), scale: f32, x_crop: f32, y_crop: f32,
+                   max_img_percent: f32) -> (u32, u32, u32, u32)
+{
+    // the (x, y, width, height) pixel region that the backends crop for a
+    // normalized (scale, x, y) triplet
+    assert!(x_crop >= 0f32 && x_crop <= 1f32, "x of crop not bounded in [0, 1]");
+    assert!(y_crop >= 0f32 && y_crop <= 1f32, "y of crop not bounded in [0, 1]");
+
+    // scale the x and y co-ordinates to the img_size
+    let mut x = scale_range(x_crop, 0f32, img_size.0 as f32) as u32;
+    let mut y = scale_range(y_crop, 0f32, img_size.1 as f32) as u32;
+
+    // calculate the scale of the true crop using the provided scale
+    // NOTE: this is different from the return size, i.e. window_size
+    let crop_scale = scale.min(max_img_percent);
+    let crop_size = ((img_size.0 as f32 * crop_scale).floor().max(2.0) as u32,
+                     (img_size.1 as f32 * crop_scale).floor().max(2.0) as u32);
+    let max_coords = (img_size.0 - crop_size.0,
+                      img_size.1 - crop_size.1);
+
+    // threshold the max x and y
+    x = x.min(max_coords.0);
+    y = y.min(max_coords.1);
+    (x, y, crop_size.0, crop_size.1)
+}
+
+
+fn new_crop_manager(num_threads: u64, use_vips: bool, exif_orientation: Option<bool>) -> *mut c_void
 {
-    // build the manager that handles the threadpool and vips [optional]
+    // build the manager that handles the threadpool and vips [optional]; the
+    // pool threads carry its EXIF setting, None follows set_exif_orientation
     let cm = Box::new(Box::new(CropManager {
-        threadpool: rayon::ThreadPoolBuilder::new().num_threads(num_threads as usize).build().unwrap(),
+        threadpool: rayon::ThreadPoolBuilder::new().num_threads(num_threads as usize)
+            .start_handler(move |_| orient::set_thread_enabled(exif_orientation))
+            .build().unwrap(),
         num_threads: num_threads as usize,
         use_vips: use_vips,
         vips_instance: match use_vips {
             true  => Some(VipsInstance::new("test", true).unwrap()),
             false => None
-        }
+        },
+        jobs: jobs::JobQueue::new()
     }));
 
     // return just a ptr, but forget to memory release it
@@ -63,11 +131,21 @@ pub extern "C" fn initialize(num_threads: u64, use_vips: bool) -> *mut c_void
 }
 
 
+#[no_mangle]
+pub extern "C" fn initialize(num_threads: u64, use_vips: bool) -> *mut c_void
+{
+    new_crop_manager(num_threads, use_vips, None)
+}
+
+
 #[no_mangle]
 pub extern "C" fn destroy(crop_manager_ptr: *mut c_void)
 {
     println!("into destroy");
 
+    // let submitted batches finish, they borrow the manager
+    crop_manager(crop_manager_ptr).jobs.wait_all();
+
     // drop the threadpool
     let cm: Box<Box<CropManager>> = unsafe { Box::from_raw(crop_manager_ptr as *mut Box<CropManager>) };
     //let use_vips = cm.use_vips;
@@ -78,6 +156,33 @@ pub extern "C" fn destroy(crop_manager_ptr: *mut c_void)
     // }
 }
 
+
+#[no_mangle]
+pub extern "C" fn api_version() -> u32
+{
+    // the version of the exports and of CropConfig, callers compare it with the
+    // PARALLEL_IMAGE_CROP_API_VERSION of the header they were built against
+    config::API_VERSION
+}
+
+
+#[no_mangle]
+pub extern "C" fn crop_config_init(config_ptr: *mut CropConfig)
+{
+    // fills a config with the defaults, its size field must already be set to
+    // sizeof(CropConfig)
+    config::init(config_ptr);
+}
+
+
+#[no_mangle]
+pub extern "C" fn initialize_with_config(config_ptr: *const CropConfig) -> *mut c_void
+{
+    // initialize with the num_threads, use_vips and exif_orientation of a config
+    let config = config::read(config_ptr);
+    new_crop_manager(config.num_threads, config.use_vips, Some(config.exif_orientation))
+}
+
 pub struct Job {
     image_paths_ptr: *const *const c_char,
     return_ptr: *mut u8,
@@ -93,6 +198,181 @@ pub struct Job {
 unsafe impl Send for Job {}
 unsafe impl Sync for Job {}
 
+pub struct BoxJob {
+    image_paths_ptr: *const *const c_char,
+    return_ptr: *mut u8,
+    boxes_ptr: *const f32,
+    units: boxes::BoxUnits,
+    padding: f32,
+    mode: boxes::ResizeMode,
+    window_size: u32,
+    chans: u32,
+    length: size_t
+}
+
+unsafe impl Send for BoxJob {}
+unsafe impl Sync for BoxJob {}
+
+pub struct AffineJob {
+    image_paths_ptr: *const *const c_char,
+    return_ptr: *mut u8,
+    matrices: Vec<warp::Affine>,
+    border: warp::BorderMode,
+    fill: f32,
+    window_size: u32,
+    chans: u32,
+    length: size_t
+}
+
+unsafe impl Send for AffineJob {}
+unsafe impl Sync for AffineJob {}
+
+pub struct PerspectiveJob {
+    image_paths_ptr: *const *const c_char,
+    return_ptr: *mut u8,
+    homographies: Vec<warp::Homography>,
+    border: warp::BorderMode,
+    fill: f32,
+    window_size: u32,
+    chans: u32,
+    length: size_t
+}
+
+unsafe impl Send for PerspectiveJob {}
+unsafe impl Sync for PerspectiveJob {}
+
+pub struct RandomJob {
+    image_paths_ptr: *const *const c_char,
+    return_ptr: *mut u8,
+    params_ptr: *mut f32,
+    sampler: sampling::CropSampler,
+    seed: u64,
+    window_size: u32,
+    chans: u32,
+    length: size_t
+}
+
+unsafe impl Send for RandomJob {}
+unsafe impl Sync for RandomJob {}
+
+pub struct AugmentJob {
+    base: Job,
+    augments: Vec<augment::Augment>
+}
+
+pub struct PairedJob {
+    streams: Vec<paired::Stream>,
+    scale_ptr: *const f32,
+    x_ptr: *const f32,
+    y_ptr: *const f32,
+    window_size: u32,
+    max_img_percent: f32,
+    length: size_t
+}
+
+unsafe impl Send for PairedJob {}
+unsafe impl Sync for PairedJob {}
+
+pub struct DepthJob {
+    image_paths_ptr: *const *const c_char,
+    return_ptr: *mut c_void,
+    scale_ptr: *const f32,
+    x_ptr: *const f32,
+    y_ptr: *const f32,
+    window_size: u32,
+    chans: u32,
+    max_img_percent: f32,
+    output: depth::Output,
+    length: size_t
+}
+
+unsafe impl Send for DepthJob {}
+unsafe impl Sync for DepthJob {}
+
+pub struct FrameJob {
+    base: Job,
+    frames: Vec<u32>
+}
+
+pub struct SaveJob {
+    image_paths_ptr: *const *const c_char,
+    scale_ptr: *const f32,
+    x_ptr: *const f32,
+    y_ptr: *const f32,
+    window_size: u32,
+    max_img_percent: f32,
+    // None resizes with the backend's default
+    filter: Option<paired::ResizeFilter>,
+    template: String,
+    format: save::SaveFormat,
+    written_ptr: *mut u8,
+    length: size_t
+}
+
+unsafe impl Send for SaveJob {}
+unsafe impl Sync for SaveJob {}
+
+#[no_mangle]
+pub extern "C" fn set_exif_orientation(enabled: bool)
+{
+    // whether images are brought upright with their EXIF orientation before
+    // cropping (the default). this is the process wide default, managers from
+    // initialize_with_config use the exif_orientation of their config instead
+    orient::set_enabled(enabled);
+}
+
+
+fn backend_probe(cm: &CropManager) -> fn(&str) -> Option<(u32, u32, u32)> {
+    // the upright (width, height, chans) of an image from its header, None when
+    // the manager's backend can't open it
+    match cm.use_vips {
+        true  => vips::vips_probe_image,
+        false => piston::probe_image
+    }
+}
+
+
+#[no_mangle]
+pub extern "C" fn probe_images(crop_manager_ptr: *const c_void,
+                               image_paths_ptr: *const *const c_char,
+                               shapes_ptr: *mut u32,
+                               ok_ptr: *mut u8,
+                               length: size_t) -> size_t
+{
+    // checks that every image opens with the selected backend before a batch is
+    // cropped (the crops themselves panic on unreadable files). only headers are
+    // read, so this is cheap next to the crop but can't catch corrupt pixel data.
+    // ok_ptr flags each readable image and shapes_ptr (may be null) receives its
+    // upright [N, 3] (width, height, chans); returns the number of readable images
+    assert!(!ok_ptr.is_null(), "can't operate over null ok vector");
+    assert!(!image_paths_ptr.is_null(), "can't operate over null list of image paths");
+    let cm = crop_manager(crop_manager_ptr);
+
+    let image_paths_vec: Vec<&str> = unsafe { slice::from_raw_parts(image_paths_ptr, length as usize) }.iter()
+        .map(|&p| unsafe { CStr::from_ptr(p) })
+        .map(|cs| str::from_utf8(cs.to_bytes()).unwrap())
+        .collect();
+    let probe = backend_probe(cm);
+    let mut shapes = vec![];
+    cm.threadpool.install(|| {
+        image_paths_vec.into_par_iter().map(probe).collect_into_vec(&mut shapes)
+    });
+
+    let oks = unsafe { slice::from_raw_parts_mut(ok_ptr, length as usize) };
+    for (ok, shape) in oks.iter_mut().zip(&shapes) {
+        *ok = shape.is_some() as u8;
+    }
+    if !shapes_ptr.is_null() {
+        let dst = unsafe { slice::from_raw_parts_mut(shapes_ptr, 3 * length as usize) };
+        for (dst, shape) in dst.chunks_mut(3).zip(&shapes) {
+            let (w, h, c) = shape.unwrap_or((0, 0, 0));
+            dst.copy_from_slice(&[w, h, c]);
+        }
+    }
+    shapes.iter().filter(|s| s.is_some()).count()
+}
+
+
 #[no_mangle]
 pub extern "C" fn parallel_crop_and_resize(crop_manager_ptr: *const c_void,
                                            image_paths_ptr: *const *const c_char,
@@ -121,18 +401,13 @@ pub extern "C" fn parallel_crop_and_resize(crop_manager_ptr: *const c_void,
         length: length
     };
 
-    match cm.use_vips {
-        true   => vips::execute_job(&job),
-        false => piston::execute_job(&job)
-    };
-
     // post to correct impl
-    // cm.threadpool.install(|| {
-    //     match cm.use_vips {
-    //         true   => vips_ffi::execute_job(&job),
-    //         false => piston::execute_job(&job)
-    //     }
-    // });
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_job(&job),
+            false => piston::execute_job(&job)
+        }
+    });
 
     // prevent the release of the crop-manager
     mem::forget(cm);
@@ -162,3 +437,1321 @@ pub extern "C" fn parallel_crop_and_resize(crop_manager_ptr: *const c_void,
 
     // }
 }
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_subpixel(crop_manager_ptr: *const c_void,
+                                                    image_paths_ptr: *const *const c_char,
+                                                    return_ptr: *mut u8,
+                                                    scale_ptr: *const f32,
+                                                    x_ptr: *const f32,
+                                                    y_ptr: *const f32,
+                                                    window_size: u32,
+                                                    chans: u32,
+                                                    max_img_percent: f32,
+                                                    length: size_t)
+{
+    // same arguments as parallel_crop_and_resize, but the window is treated as
+    // continuous co-ordinates and bilinearly sampled (grid_sample semantics)
+    let cm = crop_manager(crop_manager_ptr);
+    let job = Job {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr,
+        scale_ptr: scale_ptr,
+        x_ptr: x_ptr,
+        y_ptr: y_ptr,
+        window_size: window_size,
+        chans: chans,
+        max_img_percent: max_img_percent,
+        length: length
+    };
+
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_subpixel_job(&job),
+            false => piston::execute_subpixel_job(&job)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_augment(crop_manager_ptr: *const c_void,
+                                                   image_paths_ptr: *const *const c_char,
+                                                   return_ptr: *mut u8,
+                                                   scale_ptr: *const f32,
+                                                   x_ptr: *const f32,
+                                                   y_ptr: *const f32,
+                                                   window_size: u32,
+                                                   chans: u32,
+                                                   max_img_percent: f32,
+                                                   flags_ptr: *const u8,
+                                                   params_ptr: *const f32,
+                                                   rects_ptr: *const u32,
+                                                   fill_mode: u32,
+                                                   fill_value: u8,
+                                                   seed: u64,
+                                                   length: size_t)
+{
+    // crops and applies per-image augmentations given as the arrays of the
+    // geometric (flags_ptr), photometric (params_ptr) and erasing (rects_ptr)
+    // exports, in that order. any of them may be null to skip that step.
+    // fill_mode / fill_value fill the rects, seed drives the noise fill
+    let cm = crop_manager(crop_manager_ptr);
+    let len = length as usize;
+    let fill = augment::EraseFill::from_code(fill_mode, fill_value);
+    let augments: Vec<augment::Augment> = (0..len).map(|idx| augment::Augment {
+        geometric: match flags_ptr.is_null() {
+            true  => Default::default(),
+            false => augment::GeometricAug::from_bits(unsafe { *flags_ptr.add(idx) })
+        },
+        photometric: match params_ptr.is_null() {
+            true  => Default::default(),
+            false => augment::PhotometricAug::from_slice(unsafe { slice::from_raw_parts(params_ptr.add(6 * idx), 6) })
+        },
+        erase: match rects_ptr.is_null() {
+            true  => Default::default(),
+            // the noise seed is the first draw of the item's erase stream, as in EraseAug::sample
+            false => augment::EraseAug::from_slice(unsafe { slice::from_raw_parts(rects_ptr.add(4 * idx), 4) },
+                                                   fill, rng::Rng::for_op(rng::ERASE, seed, idx).next_u64())
+        }
+    }).collect();
+
+    let job = AugmentJob {
+        base: Job {
+            image_paths_ptr: image_paths_ptr,
+            return_ptr: return_ptr,
+            scale_ptr: scale_ptr,
+            x_ptr: x_ptr,
+            y_ptr: y_ptr,
+            window_size: window_size,
+            chans: chans,
+            max_img_percent: max_img_percent,
+            length: length
+        },
+        augments: augments
+    };
+
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_augment_job(&job),
+            false => piston::execute_augment_job(&job)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_geometric(crop_manager_ptr: *const c_void,
+                                                     image_paths_ptr: *const *const c_char,
+                                                     return_ptr: *mut u8,
+                                                     scale_ptr: *const f32,
+                                                     x_ptr: *const f32,
+                                                     y_ptr: *const f32,
+                                                     window_size: u32,
+                                                     chans: u32,
+                                                     max_img_percent: f32,
+                                                     flags_ptr: *mut u8,
+                                                     sample: bool,
+                                                     hflip_prob: f32,
+                                                     vflip_prob: f32,
+                                                     rot90_prob: f32,
+                                                     seed: u64,
+                                                     length: size_t)
+{
+    // flags_ptr holds one byte per image: bit 0 horizontal flip, bit 1 vertical
+    // flip, bits 2-3 clockwise quarter turns. When sample is set the flags are
+    // drawn from (seed, index) with the given probabilities and written back,
+    // otherwise they are read from flags_ptr
+    assert!(!flags_ptr.is_null(), "can't operate over null flags vector");
+    if sample {
+        let flags = unsafe { slice::from_raw_parts_mut(flags_ptr, length as usize) };
+        let probs = augment::GeometricProbs { hflip: hflip_prob, vflip: vflip_prob, rot90: rot90_prob };
+        for (idx, flag) in flags.iter_mut().enumerate() {
+            let mut rng = rng::Rng::for_op(rng::GEOMETRIC, seed, idx);
+            *flag = augment::GeometricAug::sample(&probs, &mut rng).to_bits();
+        }
+    }
+    parallel_crop_and_resize_augment(crop_manager_ptr, image_paths_ptr, return_ptr, scale_ptr, x_ptr, y_ptr,
+                                     window_size, chans, max_img_percent, flags_ptr, ptr::null(), ptr::null(),
+                                     0, 0, seed, length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_photometric(crop_manager_ptr: *const c_void,
+                                                       image_paths_ptr: *const *const c_char,
+                                                       return_ptr: *mut u8,
+                                                       scale_ptr: *const f32,
+                                                       x_ptr: *const f32,
+                                                       y_ptr: *const f32,
+                                                       window_size: u32,
+                                                       chans: u32,
+                                                       max_img_percent: f32,
+                                                       params_ptr: *mut f32,
+                                                       sample: bool,
+                                                       brightness: f32,
+                                                       contrast: f32,
+                                                       saturation: f32,
+                                                       hue: f32,
+                                                       grayscale_prob: f32,
+                                                       blur_prob: f32,
+                                                       blur_sigma_min: f32,
+                                                       blur_sigma_max: f32,
+                                                       seed: u64,
+                                                       length: size_t)
+{
+    // params_ptr is a [N, 6] array of (brightness, contrast, saturation, hue,
+    // grayscale, blur_sigma) per image. When sample is set they are drawn from
+    // (seed, index) using ColorJitter style ranges and written back, otherwise
+    // they are read from params_ptr
+    assert!(!params_ptr.is_null(), "can't operate over null params vector");
+    if sample {
+        let params = unsafe { slice::from_raw_parts_mut(params_ptr, 6 * length as usize) };
+        let ranges = augment::PhotometricRanges {
+            brightness: brightness,
+            contrast: contrast,
+            saturation: saturation,
+            hue: hue,
+            grayscale_prob: grayscale_prob,
+            blur_prob: blur_prob,
+            blur_sigma: (blur_sigma_min, blur_sigma_max)
+        };
+        for (idx, param) in params.chunks_mut(6).enumerate() {
+            let mut rng = rng::Rng::for_op(rng::PHOTOMETRIC, seed, idx);
+            let sampled = augment::PhotometricAug::sample(&ranges, &mut rng);
+            param.copy_from_slice(&sampled.to_array());
+        }
+    }
+    parallel_crop_and_resize_augment(crop_manager_ptr, image_paths_ptr, return_ptr, scale_ptr, x_ptr, y_ptr,
+                                     window_size, chans, max_img_percent, ptr::null(), params_ptr, ptr::null(),
+                                     0, 0, seed, length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_erasing(crop_manager_ptr: *const c_void,
+                                                   image_paths_ptr: *const *const c_char,
+                                                   return_ptr: *mut u8,
+                                                   scale_ptr: *const f32,
+                                                   x_ptr: *const f32,
+                                                   y_ptr: *const f32,
+                                                   window_size: u32,
+                                                   chans: u32,
+                                                   max_img_percent: f32,
+                                                   rects_ptr: *mut u32,
+                                                   prob: f32,
+                                                   scale_min: f32,
+                                                   scale_max: f32,
+                                                   ratio_min: f32,
+                                                   ratio_max: f32,
+                                                   fill_mode: u32,
+                                                   fill_value: u8,
+                                                   seed: u64,
+                                                   length: size_t)
+{
+    // erases a rectangle of [scale_min, scale_max] of the window area with aspect
+    // (h / w) in [ratio_min, ratio_max] from each crop with probability prob.
+    // fill_mode is 0: fill_value, 1: uniform noise, 2: per-channel mean.
+    // rects_ptr receives the [N, 4] erased (x, y, width, height), zero if untouched
+    assert!(!rects_ptr.is_null(), "can't operate over null rects vector");
+    let ranges = augment::EraseRanges {
+        prob: prob,
+        scale: (scale_min, scale_max),
+        ratio: (ratio_min, ratio_max),
+        fill: augment::EraseFill::from_code(fill_mode, fill_value)
+    };
+    let rects = unsafe { slice::from_raw_parts_mut(rects_ptr, 4 * length as usize) };
+    for (idx, rect) in rects.chunks_mut(4).enumerate() {
+        let erase = augment::EraseAug::sample(&ranges, (window_size, window_size),
+                                              &mut rng::Rng::for_op(rng::ERASE, seed, idx));
+        rect.copy_from_slice(&erase.rect_array());
+    }
+    parallel_crop_and_resize_augment(crop_manager_ptr, image_paths_ptr, return_ptr, scale_ptr, x_ptr, y_ptr,
+                                     window_size, chans, max_img_percent, ptr::null(), ptr::null(), rects_ptr,
+                                     fill_mode, fill_value, seed, length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_paired(crop_manager_ptr: *const c_void,
+                                                  stream_paths_ptr: *const *const *const c_char,
+                                                  stream_returns_ptr: *const *mut c_void,
+                                                  stream_chans_ptr: *const u32,
+                                                  stream_filters_ptr: *const u32,
+                                                  stream_dtypes_ptr: *const u32,
+                                                  num_streams: u32,
+                                                  scale_ptr: *const f32,
+                                                  x_ptr: *const f32,
+                                                  y_ptr: *const f32,
+                                                  window_size: u32,
+                                                  max_img_percent: f32,
+                                                  length: size_t)
+{
+    // crops num_streams aligned images per item (eg: image, mask, depth) with the
+    // same (scale, x, y). every stream s has its own list of N paths, return array,
+    // channel count, filter (0: nearest, 1: bilinear, 2: bicubic, 3: lanczos3)
+    // and dtype (0: u8, 1: f32). all the images of an item must share one size
+    assert!(!stream_paths_ptr.is_null(), "can't operate over null stream paths");
+    assert!(!stream_returns_ptr.is_null(), "can't operate over null stream results");
+    assert!(!stream_chans_ptr.is_null(), "can't operate over null stream chans");
+    assert!(!stream_filters_ptr.is_null(), "can't operate over null stream filters");
+    assert!(!stream_dtypes_ptr.is_null(), "can't operate over null stream dtypes");
+    let cm = crop_manager(crop_manager_ptr);
+
+    let num_streams = num_streams as usize;
+    let streams = unsafe {
+        izip!(slice::from_raw_parts(stream_paths_ptr, num_streams),
+              slice::from_raw_parts(stream_returns_ptr, num_streams),
+              slice::from_raw_parts(stream_chans_ptr, num_streams),
+              slice::from_raw_parts(stream_filters_ptr, num_streams),
+              slice::from_raw_parts(stream_dtypes_ptr, num_streams))
+    }.map(|(&paths, &ret, &chans, &filter, &dtype)| paired::Stream {
+        image_paths_ptr: paths,
+        return_ptr: ret,
+        chans: chans,
+        filter: paired::ResizeFilter::from_code(filter),
+        dtype: paired::StreamDtype::from_code(dtype)
+    }).collect();
+
+    let job = PairedJob {
+        streams: streams,
+        scale_ptr: scale_ptr,
+        x_ptr: x_ptr,
+        y_ptr: y_ptr,
+        window_size: window_size,
+        max_img_percent: max_img_percent,
+        length: length
+    };
+    run_paired_job(cm, &job);
+}
+
+
+fn run_paired_job(cm: &CropManager, job: &PairedJob) {
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_paired_job(job),
+            false => piston::execute_paired_job(job)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_with_config(crop_manager_ptr: *const c_void,
+                                                       config_ptr: *const CropConfig,
+                                                       image_paths_ptr: *const *const c_char,
+                                                       return_ptr: *mut c_void,
+                                                       scale_ptr: *const f32,
+                                                       x_ptr: *const f32,
+                                                       y_ptr: *const f32,
+                                                       length: size_t)
+{
+    // parallel_crop_and_resize with the window_size, chans, max_img_percent, filter
+    // and dtype of a config; return_ptr is u8 or f32 following dtype
+    let config = config::read(config_ptr);
+    let stream_paths = [image_paths_ptr];
+    let stream_returns = [return_ptr];
+    parallel_crop_and_resize_paired(crop_manager_ptr, stream_paths.as_ptr(), stream_returns.as_ptr(),
+                                    [config.chans].as_ptr(), [config.filter].as_ptr(), [config.dtype].as_ptr(), 1,
+                                    scale_ptr, x_ptr, y_ptr, config.window_size, config.max_img_percent, length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_dlpack(crop_manager_ptr: *const c_void,
+                                                  config_ptr: *const CropConfig,
+                                                  image_paths_ptr: *const *const c_char,
+                                                  scale_ptr: *const f32,
+                                                  x_ptr: *const f32,
+                                                  y_ptr: *const f32,
+                                                  layout: u32,
+                                                  length: size_t) -> *mut DLManagedTensor
+{
+    // parallel_crop_and_resize_with_config into a batch allocated here, returned as a
+    // DLPack tensor of the config dtype in layout (0: NHWC, 1: NCHW). whoever ends up
+    // owning the tensor (eg: torch.utils.dlpack.from_dlpack) calls its deleter
+    let config = config::read(config_ptr);
+    let layout = npy::Layout::from_code(layout);
+    let item_shape = (config.window_size as usize, config.window_size as usize, config.chans as usize);
+    let size = item_shape.0 * item_shape.1 * item_shape.2 * length;
+    let buffer = match config.dtype {
+        0 => {
+            let mut batch = vec![0u8; size];
+            parallel_crop_and_resize_with_config(crop_manager_ptr, config_ptr, image_paths_ptr,
+                                                 batch.as_mut_ptr() as *mut c_void, scale_ptr, x_ptr, y_ptr, length);
+            dlpack::Buffer::U8(batch)
+        },
+        _ => {
+            let mut batch = vec![0f32; size];
+            parallel_crop_and_resize_with_config(crop_manager_ptr, config_ptr, image_paths_ptr,
+                                                 batch.as_mut_ptr() as *mut c_void, scale_ptr, x_ptr, y_ptr, length);
+            dlpack::Buffer::F32(batch)
+        }
+    };
+
+    dlpack::managed_tensor(buffer.to_layout(item_shape, layout),
+                           layout.shape(length, item_shape.0, item_shape.1, item_shape.2))
+}
+
+
+#[no_mangle]
+pub extern "C" fn submit_crop_batch(crop_manager_ptr: *const c_void,
+                                    config_ptr: *const CropConfig,
+                                    image_paths_ptr: *const *const c_char,
+                                    return_ptr: *mut c_void,
+                                    scale_ptr: *const f32,
+                                    x_ptr: *const f32,
+                                    y_ptr: *const f32,
+                                    length: size_t) -> u64
+{
+    // parallel_crop_and_resize_with_config queued on the threadpool, returns a job id
+    // right away. the paths, scales and coordinates are copied, only return_ptr must
+    // stay alive until poll_crop_batch / wait_crop_batch report the job finished
+    assert!(!image_paths_ptr.is_null(), "can't operate over null list of image paths");
+    assert!(!scale_ptr.is_null() && !x_ptr.is_null() && !y_ptr.is_null(), "can't operate over null crops");
+    let config = config::read(config_ptr);
+    let cm = crop_manager(crop_manager_ptr);
+
+    let length = length as usize;
+    let paths: Vec<CString> = unsafe { slice::from_raw_parts(image_paths_ptr, length) }.iter()
+        .map(|&p| unsafe { CStr::from_ptr(p) }.to_owned())
+        .collect();
+    let (scale, x, y) = unsafe {
+        (slice::from_raw_parts(scale_ptr, length).to_vec(),
+         slice::from_raw_parts(x_ptr, length).to_vec(),
+         slice::from_raw_parts(y_ptr, length).to_vec())
+    };
+
+    // addresses so the work is Send, destroy waits for it before freeing the manager
+    let (cm_addr, ret_addr) = (crop_manager_ptr as usize, return_ptr as usize);
+    cm.jobs.submit(&cm.threadpool, move || {
+        let path_ptrs: Vec<*const c_char> = paths.iter().map(|p| p.as_ptr()).collect();
+        let job = PairedJob {
+            streams: vec![paired::Stream {
+                image_paths_ptr: path_ptrs.as_ptr(),
+                return_ptr: ret_addr as *mut c_void,
+                chans: config.chans,
+                filter: paired::ResizeFilter::from_code(config.filter),
+                dtype: paired::StreamDtype::from_code(config.dtype)
+            }],
+            scale_ptr: scale.as_ptr(),
+            x_ptr: x.as_ptr(),
+            y_ptr: y.as_ptr(),
+            window_size: config.window_size,
+            max_img_percent: config.max_img_percent,
+            length: length
+        };
+        run_paired_job(crop_manager(cm_addr as *const c_void), &job);
+    })
+}
+
+
+#[no_mangle]
+pub extern "C" fn poll_crop_batch(crop_manager_ptr: *const c_void, job_id: u64) -> i32
+{
+    // the CROP_BATCH_* status of a submitted batch: pending, running, done, cancelled,
+    // failed (eg: an unreadable image) or unknown. finished jobs keep their status
+    // until they are evicted by newer ones past a bound, see the jobs module
+    crop_manager(crop_manager_ptr).jobs.poll(job_id)
+}
+
+
+#[no_mangle]
+pub extern "C" fn wait_crop_batch(crop_manager_ptr: *const c_void, job_id: u64, timeout_ms: i64) -> i32
+{
+    // blocks until the batch finished or timeout_ms (negative: forever) ran out and
+    // returns its status as poll_crop_batch does
+    let timeout = match timeout_ms < 0 {
+        true  => None,
+        false => Some(std::time::Duration::from_millis(timeout_ms as u64))
+    };
+    crop_manager(crop_manager_ptr).jobs.wait(job_id, timeout)
+}
+
+
+#[no_mangle]
+pub extern "C" fn cancel_crop_batch(crop_manager_ptr: *const c_void, job_id: u64) -> bool
+{
+    // cancels a batch that hasn't started yet, false once it is running or finished
+    crop_manager(crop_manager_ptr).jobs.cancel(job_id)
+}
+
+
+fn run_depth_job(cm: &CropManager, job: &DepthJob) {
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_depth_job(job),
+            false => piston::execute_depth_job(job)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_u16(crop_manager_ptr: *const c_void,
+                                               image_paths_ptr: *const *const c_char,
+                                               return_ptr: *mut u16,
+                                               scale_ptr: *const f32,
+                                               x_ptr: *const f32,
+                                               y_ptr: *const f32,
+                                               window_size: u32,
+                                               chans: u32,
+                                               max_img_percent: f32,
+                                               length: size_t)
+{
+    // same as parallel_crop_and_resize, keeping the source bit depth (eg: 16-bit png / tiff)
+    let cm = crop_manager(crop_manager_ptr);
+    run_depth_job(cm, &DepthJob {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr as *mut c_void,
+        scale_ptr: scale_ptr,
+        x_ptr: x_ptr,
+        y_ptr: y_ptr,
+        window_size: window_size,
+        chans: chans,
+        max_img_percent: max_img_percent,
+        output: depth::Output::U16,
+        length: length
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_f32(crop_manager_ptr: *const c_void,
+                                               image_paths_ptr: *const *const c_char,
+                                               return_ptr: *mut f32,
+                                               scale_ptr: *const f32,
+                                               x_ptr: *const f32,
+                                               y_ptr: *const f32,
+                                               window_size: u32,
+                                               chans: u32,
+                                               max_img_percent: f32,
+                                               length: size_t)
+{
+    // same as parallel_crop_and_resize with the unscaled source values, eg: radiance hdr
+    let cm = crop_manager(crop_manager_ptr);
+    run_depth_job(cm, &DepthJob {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr as *mut c_void,
+        scale_ptr: scale_ptr,
+        x_ptr: x_ptr,
+        y_ptr: y_ptr,
+        window_size: window_size,
+        chans: chans,
+        max_img_percent: max_img_percent,
+        output: depth::Output::F32,
+        length: length
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_windowed(crop_manager_ptr: *const c_void,
+                                                    image_paths_ptr: *const *const c_char,
+                                                    return_ptr: *mut u8,
+                                                    scale_ptr: *const f32,
+                                                    x_ptr: *const f32,
+                                                    y_ptr: *const f32,
+                                                    window_size: u32,
+                                                    chans: u32,
+                                                    max_img_percent: f32,
+                                                    window_mode: u32,
+                                                    low: f32,
+                                                    high: f32,
+                                                    length: size_t)
+{
+    // reduces any source bit depth to u8 by mapping [low, high] onto [0, 255].
+    // window_mode 0 uses low / high as source values, 1 as per-crop percentiles
+    let cm = crop_manager(crop_manager_ptr);
+    run_depth_job(cm, &DepthJob {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr as *mut c_void,
+        scale_ptr: scale_ptr,
+        x_ptr: x_ptr,
+        y_ptr: y_ptr,
+        window_size: window_size,
+        chans: chans,
+        max_img_percent: max_img_percent,
+        output: depth::Output::U8(match window_mode {
+            0 => depth::Windowing::MinMax { min: low, max: high },
+            1 => depth::Windowing::Percentile { low: low, high: high },
+            _ => panic!("unknown window mode {:?}", window_mode)
+        }),
+        length: length
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_frames(crop_manager_ptr: *const c_void,
+                                                  image_paths_ptr: *const *const c_char,
+                                                  return_ptr: *mut u8,
+                                                  scale_ptr: *const f32,
+                                                  x_ptr: *const f32,
+                                                  y_ptr: *const f32,
+                                                  window_size: u32,
+                                                  chans: u32,
+                                                  max_img_percent: f32,
+                                                  frames_ptr: *const u32,
+                                                  length: size_t)
+{
+    // parallel_crop_and_resize over the page (multi-page tiff) or frame (animated
+    // gif) given per image in frames_ptr; frame 0 is the plain image for any format
+    assert!(!frames_ptr.is_null(), "can't operate over null frame vector");
+    let cm = crop_manager(crop_manager_ptr);
+    let job = FrameJob {
+        base: Job {
+            image_paths_ptr: image_paths_ptr,
+            return_ptr: return_ptr,
+            scale_ptr: scale_ptr,
+            x_ptr: x_ptr,
+            y_ptr: y_ptr,
+            window_size: window_size,
+            chans: chans,
+            max_img_percent: max_img_percent,
+            length: length
+        },
+        frames: unsafe { slice::from_raw_parts(frames_ptr, length as usize) }.to_vec()
+    };
+
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_frame_job(&job),
+            false => piston::execute_frame_job(&job)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_resize_tiled(crop_manager_ptr: *const c_void,
+                                                 image_paths_ptr: *const *const c_char,
+                                                 return_ptr: *mut u8,
+                                                 scale_ptr: *const f32,
+                                                 x_ptr: *const f32,
+                                                 y_ptr: *const f32,
+                                                 window_size: u32,
+                                                 chans: u32,
+                                                 max_img_percent: f32,
+                                                 length: size_t)
+{
+    // parallel_crop_and_resize for tiled pyramidal tiffs (eg: whole-slide images);
+    // (scale, x, y) are relative to the full resolution level and only the
+    // intersecting tiles of the best matching level are decoded
+    let cm = crop_manager(crop_manager_ptr);
+    let job = Job {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr,
+        scale_ptr: scale_ptr,
+        x_ptr: x_ptr,
+        y_ptr: y_ptr,
+        window_size: window_size,
+        chans: chans,
+        max_img_percent: max_img_percent,
+        length: length
+    };
+
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_tiled_job(&job),
+            false => piston::execute_tiled_job(&job)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_boxes_and_resize(crop_manager_ptr: *const c_void,
+                                                 image_paths_ptr: *const *const c_char,
+                                                 return_ptr: *mut u8,
+                                                 boxes_ptr: *const f32,
+                                                 normalized: bool,
+                                                 padding: f32,
+                                                 letterbox: bool,
+                                                 window_size: u32,
+                                                 chans: u32,
+                                                 length: size_t)
+{
+    // boxes_ptr is a [N, 4] array of (x0, y0, x1, y1) in pixels or in [0, 1]
+    let cm = crop_manager(crop_manager_ptr);
+    let job = BoxJob {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr,
+        boxes_ptr: boxes_ptr,
+        units: match normalized {
+            true  => boxes::BoxUnits::Normalized,
+            false => boxes::BoxUnits::Pixel
+        },
+        padding: padding,
+        mode: match letterbox {
+            true  => boxes::ResizeMode::Letterbox,
+            false => boxes::ResizeMode::Stretch
+        },
+        window_size: window_size,
+        chans: chans,
+        length: length
+    };
+    run_box_job(cm, &job);
+}
+
+
+fn run_box_job(cm: &CropManager, job: &BoxJob) {
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_box_job(job),
+            false => piston::execute_box_job(job)
+        }
+    });
+}
+
+
+fn run_affine_job(cm: &CropManager, job: &AffineJob) {
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::parallel_affine_crop(job.image_paths_ptr, job.return_ptr, &job.matrices,
+                                                job.border, job.fill, job.window_size,
+                                                job.chans, job.length),
+            false => piston::parallel_affine_crop(job.image_paths_ptr, job.return_ptr, &job.matrices,
+                                                  job.border, job.fill, job.window_size,
+                                                  job.chans, job.length)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_affine_crop(crop_manager_ptr: *const c_void,
+                                       image_paths_ptr: *const *const c_char,
+                                       return_ptr: *mut u8,
+                                       matrices_ptr: *const f32,
+                                       border: u32,
+                                       fill: f32,
+                                       window_size: u32,
+                                       chans: u32,
+                                       length: size_t)
+{
+    // matrices_ptr is a [N, 2, 3] array mapping output window co-ordinates to
+    // source pixel co-ordinates; border is 0: constant, 1: replicate, 2: reflect
+    assert!(!matrices_ptr.is_null(), "can't operate over null matrix vector");
+    let cm = crop_manager(crop_manager_ptr);
+    let matrices = unsafe { slice::from_raw_parts(matrices_ptr, 6 * length as usize) }
+        .chunks(6).map(|m| [m[0], m[1], m[2], m[3], m[4], m[5]]).collect();
+
+    run_affine_job(cm, &AffineJob {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr,
+        matrices: matrices,
+        border: warp::BorderMode::from_code(border),
+        fill: fill,
+        window_size: window_size,
+        chans: chans,
+        length: length
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_rotated_crop(crop_manager_ptr: *const c_void,
+                                        image_paths_ptr: *const *const c_char,
+                                        return_ptr: *mut u8,
+                                        rboxes_ptr: *const f32,
+                                        border: u32,
+                                        fill: f32,
+                                        window_size: u32,
+                                        chans: u32,
+                                        length: size_t)
+{
+    // rboxes_ptr is a [N, 5] array of (center_x, center_y, width, height, angle)
+    // in source pixels and degrees
+    assert!(!rboxes_ptr.is_null(), "can't operate over null rotated box vector");
+    let cm = crop_manager(crop_manager_ptr);
+    let matrices = unsafe { slice::from_raw_parts(rboxes_ptr, 5 * length as usize) }
+        .chunks(5).map(|b| warp::rotated_box_affine((b[0], b[1]), (b[2], b[3]), b[4],
+                                                    (window_size, window_size)))
+        .collect();
+
+    run_affine_job(cm, &AffineJob {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr,
+        matrices: matrices,
+        border: warp::BorderMode::from_code(border),
+        fill: fill,
+        window_size: window_size,
+        chans: chans,
+        length: length
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_perspective_crop(crop_manager_ptr: *const c_void,
+                                            image_paths_ptr: *const *const c_char,
+                                            return_ptr: *mut u8,
+                                            corners_ptr: *const f32,
+                                            border: u32,
+                                            fill: f32,
+                                            window_size: u32,
+                                            chans: u32,
+                                            length: size_t)
+{
+    // corners_ptr is a [N, 4, 2] array of source pixel (x, y) corners ordered
+    // top-left, top-right, bottom-right, bottom-left
+    assert!(!corners_ptr.is_null(), "can't operate over null corner vector");
+    let cm = crop_manager(crop_manager_ptr);
+    let homographies = unsafe { slice::from_raw_parts(corners_ptr, 8 * length as usize) }
+        .chunks(8).map(|c| {
+            let corners = [(c[0], c[1]), (c[2], c[3]), (c[4], c[5]), (c[6], c[7])];
+            warp::quad_homography(&corners, (window_size, window_size))
+                .expect("degenerate quadrilateral")
+        }).collect();
+
+    let job = PerspectiveJob {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr,
+        homographies: homographies,
+        border: warp::BorderMode::from_code(border),
+        fill: fill,
+        window_size: window_size,
+        chans: chans,
+        length: length
+    };
+
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::parallel_perspective_crop(job.image_paths_ptr, job.return_ptr, &job.homographies,
+                                                     job.border, job.fill, job.window_size,
+                                                     job.chans, job.length),
+            false => piston::parallel_perspective_crop(job.image_paths_ptr, job.return_ptr, &job.homographies,
+                                                       job.border, job.fill, job.window_size,
+                                                       job.chans, job.length)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_random_crop_and_resize(crop_manager_ptr: *const c_void,
+                                                  image_paths_ptr: *const *const c_char,
+                                                  return_ptr: *mut u8,
+                                                  params_ptr: *mut f32,
+                                                  mode: u32,
+                                                  scale_min: f32,
+                                                  scale_max: f32,
+                                                  ratio_min: f32,
+                                                  ratio_max: f32,
+                                                  seed: u64,
+                                                  window_size: u32,
+                                                  chans: u32,
+                                                  length: size_t)
+{
+    // mode is 0: random resized crop over [scale_min, scale_max] area and
+    // [ratio_min, ratio_max] aspect, 1: uniform position with side in
+    // [scale_min, scale_max], 2: center crop with side scale_max.
+    // params_ptr receives the sampled [N, 4] normalized (x0, y0, x1, y1) boxes
+    assert!(scale_min > 0.0 && scale_min <= scale_max && scale_max <= 1.0,
+            "scale range [{:?}, {:?}] not within (0, 1]", scale_min, scale_max);
+    let cm = crop_manager(crop_manager_ptr);
+    let sampler = match mode {
+        0 => sampling::CropSampler::RandomResized { scale: (scale_min, scale_max),
+                                                    ratio: (ratio_min, ratio_max) },
+        1 => sampling::CropSampler::RandomPosition { scale: (scale_min, scale_max) },
+        2 => sampling::CropSampler::Center { scale: scale_max },
+        _ => panic!("unknown crop sampling mode {:?}", mode)
+    };
+
+    let job = RandomJob {
+        image_paths_ptr: image_paths_ptr,
+        return_ptr: return_ptr,
+        params_ptr: params_ptr,
+        sampler: sampler,
+        seed: seed,
+        window_size: window_size,
+        chans: chans,
+        length: length
+    };
+
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::parallel_random_crop_and_resize(job.image_paths_ptr, job.return_ptr, job.params_ptr,
+                                                           &job.sampler, job.seed, job.window_size,
+                                                           job.chans, job.length),
+            false => piston::parallel_random_crop_and_resize(job.image_paths_ptr, job.return_ptr, job.params_ptr,
+                                                             &job.sampler, job.seed, job.window_size,
+                                                             job.chans, job.length)
+        }
+    });
+}
+
+
+#[no_mangle]
+pub extern "C" fn assemble_patches_u8(crop_manager_ptr: *const c_void,
+                                      patches_ptr: *const u8,
+                                      x_ptr: *const i32,
+                                      y_ptr: *const i32,
+                                      patch_width: u32,
+                                      patch_height: u32,
+                                      chans: u32,
+                                      image_width: u32,
+                                      image_height: u32,
+                                      feather: bool,
+                                      return_ptr: *mut u8,
+                                      length: size_t)
+{
+    let cm = crop_manager(crop_manager_ptr);
+    let mode = match feather {
+        true  => stitch::BlendMode::Feather,
+        false => stitch::BlendMode::Average
+    };
+
+    stitch::parallel_assemble_patches(&cm.threadpool,
+                                      patches_ptr, x_ptr, y_ptr,
+                                      patch_width, patch_height, chans,
+                                      image_width, image_height,
+                                      mode, return_ptr, length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn assemble_patches_f32(crop_manager_ptr: *const c_void,
+                                       patches_ptr: *const f32,
+                                       x_ptr: *const i32,
+                                       y_ptr: *const i32,
+                                       patch_width: u32,
+                                       patch_height: u32,
+                                       chans: u32,
+                                       image_width: u32,
+                                       image_height: u32,
+                                       feather: bool,
+                                       return_ptr: *mut f32,
+                                       length: size_t)
+{
+    let cm = crop_manager(crop_manager_ptr);
+    let mode = match feather {
+        true  => stitch::BlendMode::Feather,
+        false => stitch::BlendMode::Average
+    };
+
+    stitch::parallel_assemble_patches(&cm.threadpool,
+                                      patches_ptr, x_ptr, y_ptr,
+                                      patch_width, patch_height, chans,
+                                      image_width, image_height,
+                                      mode, return_ptr, length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn mix_batch_u8(crop_manager_ptr: *const c_void,
+                               batch_ptr: *mut u8,
+                               perm_ptr: *mut u32,
+                               lambda_ptr: *mut f32,
+                               mode: u32,
+                               sample: bool,
+                               alpha: f32,
+                               seed: u64,
+                               window_size: u32,
+                               chans: u32,
+                               length: size_t)
+{
+    // mode is 0: MixUp, 1: CutMix
+    let cm = crop_manager(crop_manager_ptr);
+    mixing::parallel_mix_batch(&cm.threadpool,
+                               batch_ptr, perm_ptr, lambda_ptr,
+                               mixing::MixMode::from_code(mode),
+                               sample, alpha, seed,
+                               window_size, chans, length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn mix_batch_f32(crop_manager_ptr: *const c_void,
+                                batch_ptr: *mut f32,
+                                perm_ptr: *mut u32,
+                                lambda_ptr: *mut f32,
+                                mode: u32,
+                                sample: bool,
+                                alpha: f32,
+                                seed: u64,
+                                window_size: u32,
+                                chans: u32,
+                                length: size_t)
+{
+    // mode is 0: MixUp, 1: CutMix
+    let cm = crop_manager(crop_manager_ptr);
+    mixing::parallel_mix_batch(&cm.threadpool,
+                               batch_ptr, perm_ptr, lambda_ptr,
+                               mixing::MixMode::from_code(mode),
+                               sample, alpha, seed,
+                               window_size, chans, length);
+}
+
+
+fn header_transforms<F>(cm: &CropManager, image_paths_ptr: *const *const c_char, length: size_t,
+                        transform: F) -> Vec<Option<warp::Affine>>
+    where F: Fn(usize, (u32, u32)) -> warp::Affine + Sync
+{
+    // transform(idx, upright image size) of every item, reading only the image
+    // headers with the manager's backend; None for the images it can't read
+    let image_paths_vec = batch::paths(image_paths_ptr, length);
+    let probe = backend_probe(cm);
+    let mut transforms = vec![];
+    cm.threadpool.install(|| {
+        image_paths_vec.into_par_iter().enumerate()
+            .map(|(idx, path)| probe(path).map(|(width, height, _)| transform(idx, (width, height))))
+            .collect_into_vec(&mut transforms);
+    });
+    transforms
+}
+
+
+#[no_mangle]
+pub extern "C" fn transform_annotations(crop_manager_ptr: *const c_void,
+                                        image_paths_ptr: *const *const c_char,
+                                        scale_ptr: *const f32,
+                                        x_ptr: *const f32,
+                                        y_ptr: *const f32,
+                                        window_size: u32,
+                                        max_img_percent: f32,
+                                        flags_ptr: *const u8,
+                                        num_boxes_ptr: *const u32,
+                                        boxes_ptr: *mut f32,
+                                        box_valid_ptr: *mut u8,
+                                        num_points_ptr: *const u32,
+                                        points_ptr: *mut f32,
+                                        point_visible_ptr: *mut u8,
+                                        length: size_t)
+{
+    // maps source pixel boxes [sum(num_boxes), 4] and points [sum(num_points), 2]
+    // (eg: keypoints or polygon vertices) into the windows that parallel_crop_and_resize
+    // produces for the same (scale, x, y). flags_ptr holds the geometric flags of
+    // parallel_crop_and_resize_geometric and can be null when no flips were applied.
+    // only the image headers are read; the boxes and points of an image the backend
+    // can't open are all flagged invalid / hidden
+    let cm = crop_manager(crop_manager_ptr);
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let geometric: Vec<augment::GeometricAug> = match flags_ptr.is_null() {
+        true  => vec![Default::default(); length as usize],
+        false => unsafe { slice::from_raw_parts(flags_ptr, length as usize) }.iter()
+            .map(|&bits| augment::GeometricAug::from_bits(bits)).collect()
+    };
+
+    let window = (window_size, window_size);
+    let transforms = header_transforms(cm, image_paths_ptr, length, |idx, img_size| {
+        let crop = annotate::crop_transform(img_size, scale_values[idx], x_values[idx], y_values[idx],
+                                            max_img_percent, window);
+        annotate::compose(&crop, &annotate::geometric_transform(&geometric[idx], window))
+    });
+
+    annotate::parallel_transform_annotations(&cm.threadpool, &transforms, window_size,
+                                             num_boxes_ptr, boxes_ptr, box_valid_ptr,
+                                             num_points_ptr, points_ptr, point_visible_ptr,
+                                             length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn transform_annotations_affine(crop_manager_ptr: *const c_void,
+                                               matrices_ptr: *const f32,
+                                               window_size: u32,
+                                               num_boxes_ptr: *const u32,
+                                               boxes_ptr: *mut f32,
+                                               box_valid_ptr: *mut u8,
+                                               num_points_ptr: *const u32,
+                                               points_ptr: *mut f32,
+                                               point_visible_ptr: *mut u8,
+                                               length: size_t)
+{
+    // same as transform_annotations for the [N, 6] window -> source matrices
+    // given to parallel_affine_crop
+    assert!(!matrices_ptr.is_null(), "can't operate over null matrix vector");
+    let cm = crop_manager(crop_manager_ptr);
+    let transforms: Vec<Option<warp::Affine>> = unsafe { slice::from_raw_parts(matrices_ptr, 6 * length as usize) }
+        .chunks(6).map(|m| {
+            let matrix = [m[0], m[1], m[2], m[3], m[4], m[5]];
+            Some(warp::invert_affine(&matrix).expect("affine matrix is not invertible"))
+        }).collect();
+
+    annotate::parallel_transform_annotations(&cm.threadpool, &transforms, window_size,
+                                             num_boxes_ptr, boxes_ptr, box_valid_ptr,
+                                             num_points_ptr, points_ptr, point_visible_ptr,
+                                             length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn transform_annotations_boxes(crop_manager_ptr: *const c_void,
+                                              image_paths_ptr: *const *const c_char,
+                                              crop_boxes_ptr: *const f32,
+                                              normalized: bool,
+                                              padding: f32,
+                                              letterbox: bool,
+                                              window_size: u32,
+                                              num_boxes_ptr: *const u32,
+                                              boxes_ptr: *mut f32,
+                                              box_valid_ptr: *mut u8,
+                                              num_points_ptr: *const u32,
+                                              points_ptr: *mut f32,
+                                              point_visible_ptr: *mut u8,
+                                              length: size_t)
+{
+    // same as transform_annotations for the [N, 4] crop boxes, padding and letterboxing
+    // given to parallel_crop_boxes_and_resize. points in the letterbox bars stay visible
+    assert!(!crop_boxes_ptr.is_null(), "can't operate over null box vector");
+    let cm = crop_manager(crop_manager_ptr);
+    let crop_boxes: Vec<boxes::CropBox> = unsafe { slice::from_raw_parts(crop_boxes_ptr, 4 * length as usize) }
+        .chunks(4).map(boxes::CropBox::from_slice).collect();
+    let units = match normalized {
+        true  => boxes::BoxUnits::Normalized,
+        false => boxes::BoxUnits::Pixel
+    };
+    let mode = match letterbox {
+        true  => boxes::ResizeMode::Letterbox,
+        false => boxes::ResizeMode::Stretch
+    };
+
+    let transforms = header_transforms(cm, image_paths_ptr, length, |idx, img_size| {
+        annotate::box_transform(img_size, &crop_boxes[idx], units, padding, mode, (window_size, window_size))
+    });
+    annotate::parallel_transform_annotations(&cm.threadpool, &transforms, window_size,
+                                             num_boxes_ptr, boxes_ptr, box_valid_ptr,
+                                             num_points_ptr, points_ptr, point_visible_ptr,
+                                             length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn transform_annotations_random(crop_manager_ptr: *const c_void,
+                                               image_paths_ptr: *const *const c_char,
+                                               params_ptr: *const f32,
+                                               window_size: u32,
+                                               num_boxes_ptr: *const u32,
+                                               boxes_ptr: *mut f32,
+                                               box_valid_ptr: *mut u8,
+                                               num_points_ptr: *const u32,
+                                               points_ptr: *mut f32,
+                                               point_visible_ptr: *mut u8,
+                                               length: size_t)
+{
+    // same as transform_annotations for the crops of parallel_random_crop_and_resize,
+    // params_ptr being the [N, 4] normalized boxes it sampled
+    assert!(!params_ptr.is_null(), "can't operate over null params vector");
+    let cm = crop_manager(crop_manager_ptr);
+    let sampled: Vec<boxes::CropBox> = unsafe { slice::from_raw_parts(params_ptr, 4 * length as usize) }
+        .chunks(4).map(boxes::CropBox::from_slice).collect();
+
+    let transforms = header_transforms(cm, image_paths_ptr, length, |idx, img_size| {
+        annotate::box_transform(img_size, &sampled[idx], boxes::BoxUnits::Normalized, 0.0,
+                                boxes::ResizeMode::Stretch, (window_size, window_size))
+    });
+    annotate::parallel_transform_annotations(&cm.threadpool, &transforms, window_size,
+                                             num_boxes_ptr, boxes_ptr, box_valid_ptr,
+                                             num_points_ptr, points_ptr, point_visible_ptr,
+                                             length);
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_save(crop_manager_ptr: *const c_void,
+                                         image_paths_ptr: *const *const c_char,
+                                         output_template_ptr: *const c_char,
+                                         format: u32,
+                                         quality: u32,
+                                         written_ptr: *mut u8,
+                                         scale_ptr: *const f32,
+                                         x_ptr: *const f32,
+                                         y_ptr: *const f32,
+                                         window_size: u32,
+                                         max_img_percent: f32,
+                                         length: size_t) -> size_t
+{
+    // same crops as parallel_crop_and_resize, encoded to files instead of a buffer.
+    // item i is written to output_template with {index} -> i and {stem} -> the
+    // source file stem (missing directories are created and the format's
+    // extension appended when absent), as format 0: png, 1: jpeg or 2: webp with
+    // quality in [1, 100]. written_ptr (may be null) flags each written item,
+    // unreadable images included; returns the number of crops written, 0 for
+    // an unknown format
+    save_crops(crop_manager_ptr, image_paths_ptr, output_template_ptr, format, quality, written_ptr,
+               scale_ptr, x_ptr, y_ptr, window_size, max_img_percent, None, length)
+}
+
+
+#[no_mangle]
+pub extern "C" fn parallel_crop_and_save_with_config(crop_manager_ptr: *const c_void,
+                                                     config_ptr: *const CropConfig,
+                                                     image_paths_ptr: *const *const c_char,
+                                                     output_template_ptr: *const c_char,
+                                                     format: u32,
+                                                     quality: u32,
+                                                     written_ptr: *mut u8,
+                                                     scale_ptr: *const f32,
+                                                     x_ptr: *const f32,
+                                                     y_ptr: *const f32,
+                                                     length: size_t) -> size_t
+{
+    // parallel_crop_and_save with the window_size, max_img_percent and filter of a
+    // config; the files keep the channels of their source image
+    let config = config::read(config_ptr);
+    save_crops(crop_manager_ptr, image_paths_ptr, output_template_ptr, format, quality, written_ptr,
+               scale_ptr, x_ptr, y_ptr, config.window_size, config.max_img_percent,
+               Some(paired::ResizeFilter::from_code(config.filter)), length)
+}
+
+
+fn save_crops(crop_manager_ptr: *const c_void, image_paths_ptr: *const *const c_char,
+              output_template_ptr: *const c_char, format: u32, quality: u32, written_ptr: *mut u8,
+              scale_ptr: *const f32, x_ptr: *const f32, y_ptr: *const f32, window_size: u32,
+              max_img_percent: f32, filter: Option<paired::ResizeFilter>, length: size_t) -> size_t
+{
+    assert!(!output_template_ptr.is_null(), "can't operate over null output template");
+    let format = match save::SaveFormat::parse(format, quality) {
+        Some(format) => format,
+        None => {
+            if !written_ptr.is_null() {
+                unsafe { ptr::write_bytes(written_ptr, 0, length as usize) };
+            }
+            return 0;
+        }
+    };
+    let cm = crop_manager(crop_manager_ptr);
+    let template = unsafe { CStr::from_ptr(output_template_ptr) }.to_str().unwrap();
+    let job = SaveJob {
+        image_paths_ptr: image_paths_ptr,
+        scale_ptr: scale_ptr,
+        x_ptr: x_ptr,
+        y_ptr: y_ptr,
+        window_size: window_size,
+        max_img_percent: max_img_percent,
+        filter: filter,
+        template: template.to_string(),
+        format: format,
+        written_ptr: written_ptr,
+        length: length
+    };
+
+    // post to correct impl
+    cm.threadpool.install(|| {
+        match cm.use_vips {
+            true  => vips::execute_save_job(&job),
+            false => piston::execute_save_job(&job)
+        }
+    })
+}
+
+
+fn c_path<'a>(path_ptr: *const c_char) -> &'a str {
+    assert!(!path_ptr.is_null(), "can't operate over null path");
+    unsafe { CStr::from_ptr(path_ptr) }.to_str().unwrap()
+}
+
+
+#[no_mangle]
+pub extern "C" fn write_npy(path_ptr: *const c_char,
+                            data_ptr: *const c_void,
+                            dtype: u32,
+                            layout: u32,
+                            n: size_t,
+                            h: u32,
+                            w: u32,
+                            c: u32) -> bool
+{
+    // writes an [N, H, W, C] batch of dtype 0: u8 or 1: f32 (eg: the output of
+    // a crop export) to a .npy file stored as layout 0: nhwc or 1: nchw
+    assert!(!data_ptr.is_null(), "can't operate over null data");
+    let path = c_path(path_ptr);
+    let dtype = npy::Dtype::from_code(dtype);
+    let shape = [n as usize, h as usize, w as usize, c as usize];
+    let data = unsafe { slice::from_raw_parts(data_ptr as *const u8, shape.iter().product::<usize>() * dtype.size()) };
+    npy::write_npy(path, data, dtype, shape, npy::Layout::from_code(layout)).is_ok()
+}
+
+
+#[no_mangle]
+pub extern "C" fn write_npz(path_ptr: *const c_char,
+                            num_arrays: u32,
+                            names_ptr: *const *const c_char,
+                            data_ptrs: *const *const c_void,
+                            dtypes_ptr: *const u32,
+                            shapes_ptr: *const u32,
+                            layout: u32) -> bool
+{
+    // writes num_arrays named batches (eg: images and masks) to one .npz;
+    // array i has dtypes_ptr[i] and the [N, H, W, C] in shapes_ptr[4 * i..]
+    assert!(!names_ptr.is_null(), "can't operate over null names");
+    assert!(!data_ptrs.is_null(), "can't operate over null data");
+    assert!(!dtypes_ptr.is_null(), "can't operate over null dtypes");
+    assert!(!shapes_ptr.is_null(), "can't operate over null shapes");
+    let path = c_path(path_ptr);
+    let num_arrays = num_arrays as usize;
+    let layout = npy::Layout::from_code(layout);
+    let arrays: Vec<(String, Vec<u8>)> = unsafe {
+        izip!(slice::from_raw_parts(names_ptr, num_arrays),
+              slice::from_raw_parts(data_ptrs, num_arrays),
+              slice::from_raw_parts(dtypes_ptr, num_arrays),
+              slice::from_raw_parts(shapes_ptr, 4 * num_arrays).chunks(4))
+    }.map(|(&name, &data, &dtype, shape)| {
+        let dtype = npy::Dtype::from_code(dtype);
+        let shape = [shape[0] as usize, shape[1] as usize, shape[2] as usize, shape[3] as usize];
+        assert!(!data.is_null(), "can't operate over null data");
+        let data = unsafe { slice::from_raw_parts(data as *const u8, shape.iter().product::<usize>() * dtype.size()) };
+        (c_path(name).to_string(), npy::npy_bytes(data, dtype, shape, layout))
+    }).collect();
+    npy::write_npz(path, &arrays).is_ok()
+}
+
+
+#[no_mangle]
+pub extern "C" fn create_npy_memmap(path_ptr: *const c_char,
+                                    dtype: u32,
+                                    layout: u32,
+                                    n: size_t,
+                                    h: u32,
+                                    w: u32,
+                                    c: u32) -> bool
+{
+    // preallocates a zero filled [N, H, W, C] (or [N, C, H, W]) .npy for
+    // write_npy_rows. safe to call from every process of a job: an existing file
+    // holding the same array is kept, a mismatching one is an error
+    let path = c_path(path_ptr);
+    npy::create_memmap(path, npy::Dtype::from_code(dtype),
+                       [n as usize, h as usize, w as usize, c as usize],
+                       npy::Layout::from_code(layout)).is_ok()
+}
+
+
+#[no_mangle]
+pub extern "C" fn write_npy_rows(path_ptr: *const c_char,
+                                 data_ptr: *const c_void,
+                                 indices_ptr: *const u64,
+                                 dtype: u32,
+                                 layout: u32,
+                                 length: size_t,
+                                 h: u32,
+                                 w: u32,
+                                 c: u32) -> bool
+{
+    // writes the [length, H, W, C] batch into rows indices_ptr[..length] of a
+    // .npy made by create_npy_memmap. processes writing disjoint rows can
+    // share the file
+    assert!(!data_ptr.is_null(), "can't operate over null data");
+    assert!(!indices_ptr.is_null(), "can't operate over null indices");
+    let path = c_path(path_ptr);
+    let dtype = npy::Dtype::from_code(dtype);
+    let item_shape = (h as usize, w as usize, c as usize);
+    let data = unsafe { slice::from_raw_parts(data_ptr as *const u8,
+                                              length as usize * (h * w * c) as usize * dtype.size()) };
+    let indices: Vec<usize> = unsafe { slice::from_raw_parts(indices_ptr, length as usize) }.iter()
+        .map(|&i| i as usize)
+        .collect();
+    npy::write_rows(path, data, dtype, &indices, item_shape, npy::Layout::from_code(layout)).is_ok()
+}
@@ -0,0 +1,187 @@
+use std::slice;
+use rayon::ThreadPool;
+use rayon::prelude::*;
+use libc::size_t;
+use rng::Rng;
+use stitch::PatchPixel;
+
+
+// batch-level mixing (MixUp / CutMix) applied in place to a [N, h, w, chans]
+// batch after the crops have been written to return_ptr
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MixMode {
+    MixUp,  // pixel-wise convex combination with the partner item
+    CutMix  // paste a rectangle of the partner item
+}
+
+
+impl MixMode {
+    pub fn from_code(code: u32) -> MixMode {
+        match code {
+            0 => MixMode::MixUp,
+            1 => MixMode::CutMix,
+            _ => panic!("unknown mix mode {:?}", code)
+        }
+    }
+}
+
+
+// everything needed to mix a single item of the batch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MixItem {
+    pub partner: u32,
+    pub lambda: f32,
+    pub rect: (u32, u32, u32, u32)  // (x, y, width, height) pasted from the partner for CutMix
+}
+
+
+pub fn sample_permutation(length: usize, rng: &mut Rng) -> Vec<u32> {
+    // fisher-yates
+    let mut perm: Vec<u32> = (0..length as u32).collect();
+    for i in (1..length).rev() {
+        let j = rng.below(i as u32 + 1) as usize;
+        perm.swap(i, j);
+    }
+
+    perm
+}
+
+
+pub fn cutmix_rect(lambda: f32, size: (u32, u32), rng: &mut Rng) -> (u32, u32, u32, u32) {
+    // a box covering (1 - lambda) of the window around a uniform center, clipped
+    let cut = (1.0 - lambda).max(0.0).min(1.0).sqrt();
+    let (half_w, half_h) = ((size.0 as f32 * cut) as u32 / 2, (size.1 as f32 * cut) as u32 / 2);
+    let (cx, cy) = (rng.below(size.0), rng.below(size.1));
+    let (x0, y0) = (cx.saturating_sub(half_w), cy.saturating_sub(half_h));
+    let (x1, y1) = ((cx + half_w).min(size.0), (cy + half_h).min(size.1));
+    (x0, y0, x1 - x0, y1 - y0)
+}
+
+
+pub fn mix_batch<T: PatchPixel>(batch: &mut [T], items: &[MixItem], mode: MixMode,
+                                size: (u32, u32), chans: u32) -> Vec<f32>
+{
+    // mixes every item with its partner from the unmixed batch and returns
+    // the effective lambda, ie: the fraction of each output owned by the item
+    let item_len = (size.0 * size.1 * chans) as usize;
+    assert!(batch.len() == items.len() * item_len, "batch [{:?}] != {:?} items of {:?}",
+            batch.len(), items.len(), item_len);
+    for item in items {
+        assert!((item.partner as usize) < items.len(), "partner {:?} is outside the batch", item.partner);
+    }
+
+    if item_len == 0 {
+        return items.iter().map(|item| item.lambda).collect();
+    }
+
+    let original = batch.to_vec();
+    batch.par_chunks_mut(item_len).zip(items.par_iter()).map(|(dst, item)| {
+        let begin = item.partner as usize * item_len;
+        let src = &original[begin..begin + item_len];
+        match mode {
+            MixMode::MixUp => {
+                for (d, s) in dst.iter_mut().zip(src) {
+                    *d = T::from_f32(item.lambda * d.to_f32() + (1.0 - item.lambda) * s.to_f32());
+                }
+                item.lambda
+            },
+            MixMode::CutMix => {
+                let (x, y, w, h) = item.rect;
+                let row_len = (size.0 * chans) as usize;
+                for row in y..y + h {
+                    let row_begin = row as usize * row_len + (x * chans) as usize;
+                    let row_end = row_begin + (w * chans) as usize;
+                    dst[row_begin..row_end].copy_from_slice(&src[row_begin..row_end]);
+                }
+                1.0 - (w * h) as f32 / (size.0 * size.1) as f32
+            }
+        }
+    }).collect()
+}
+
+
+pub fn parallel_mix_batch<T: PatchPixel>(threadpool: &ThreadPool,
+                                         batch_ptr: *mut T,
+                                         perm_ptr: *mut u32,
+                                         lambda_ptr: *mut f32,
+                                         mode: MixMode,
+                                         sample: bool,
+                                         alpha: f32,
+                                         seed: u64,
+                                         window_size: u32,
+                                         chans: u32,
+                                         length: size_t)
+{
+    // mixes the [N, window_size, window_size, chans] batch in place. When sample is set
+    // the permutation and the per-item lambda ~ Beta(alpha, alpha) are drawn from seed
+    // and written to perm_ptr / lambda_ptr, otherwise they are read from them.
+    // lambda_ptr always receives the effective mixing coefficient of each item.
+    assert!(!batch_ptr.is_null(), "can't operate over null batch vector");
+    assert!(!perm_ptr.is_null(), "can't operate over null permutation vector");
+    assert!(!lambda_ptr.is_null(), "can't operate over null lambda vector");
+
+    let length = length as usize;
+    let item_len = (window_size * window_size * chans) as usize;
+    let batch = unsafe { slice::from_raw_parts_mut(batch_ptr, length * item_len) };
+    let perm = unsafe { slice::from_raw_parts_mut(perm_ptr, length) };
+    let lambdas = unsafe { slice::from_raw_parts_mut(lambda_ptr, length) };
+    if sample {
+        perm.copy_from_slice(&sample_permutation(length, &mut Rng::new(seed)));
+    }
+
+    let items: Vec<MixItem> = perm.iter().zip(lambdas.iter()).enumerate().map(|(idx, (&partner, &lambda))| {
+        let mut rng = Rng::for_op(::rng::MIXING, seed, idx);
+        let lambda = match sample {
+            true  => rng.beta(alpha, alpha),
+            false => lambda
+        };
+        MixItem {
+            partner: partner,
+            lambda: lambda,
+            rect: cutmix_rect(lambda, (window_size, window_size), &mut rng)
+        }
+    }).collect();
+
+    let effective = threadpool.install(|| {
+        mix_batch(batch, &items, mode, (window_size, window_size), chans)
+    });
+    lambdas.copy_from_slice(&effective);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mixup_and_cutmix() {
+        // two 2x2 gray items swapped with each other
+        let items = [MixItem { partner: 1, lambda: 0.25, rect: (0, 0, 1, 2) },
+                     MixItem { partner: 0, lambda: 0.75, rect: (1, 1, 1, 1) }];
+        let mut batch = vec![0u8, 0, 0, 0, 100, 100, 100, 100];
+        let lambdas = mix_batch(&mut batch, &items, MixMode::MixUp, (2, 2), 1);
+        assert!(batch == vec![75, 75, 75, 75, 75, 75, 75, 75], "mixup was {:?}", batch);
+        assert!(lambdas == vec![0.25, 0.75]);
+
+        let mut batch = vec![0u8, 0, 0, 0, 100, 100, 100, 100];
+        let lambdas = mix_batch(&mut batch, &items, MixMode::CutMix, (2, 2), 1);
+        assert!(batch == vec![100, 0, 100, 0, 100, 100, 100, 0], "cutmix was {:?}", batch);
+        assert!(lambdas == vec![0.5, 0.75], "lambdas were {:?}", lambdas);
+    }
+
+    #[test]
+    fn test_sampled_permutation_and_rect() {
+        let mut rng = Rng::new(3);
+        let mut perm = sample_permutation(16, &mut rng);
+        perm.sort();
+        assert!(perm == (0..16).collect::<Vec<u32>>());
+
+        for _ in 0..100 {
+            let (x, y, w, h) = cutmix_rect(rng.uniform(0.0, 1.0), (32, 24), &mut rng);
+            assert!(x + w <= 32 && y + h <= 24);
+        }
+        assert!(cutmix_rect(1.0, (32, 24), &mut rng).2 == 0);
+    }
+}
@@ -0,0 +1,403 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+// numpy .npy / .npz output for batches of crops. batches arrive as [N, H, W, C]
+// (the layout every crop export produces) and are stored as NHWC or NCHW. a
+// preallocated .npy can also be filled row by row at given indices, eg: by
+// several preprocessing processes sharing one dataset sized memmap
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dtype {
+    U8,
+    F32
+}
+
+
+impl Dtype {
+    pub fn from_code(code: u32) -> Dtype {
+        match code {
+            0 => Dtype::U8,
+            1 => Dtype::F32,
+            _ => panic!("unknown dtype {:?}, expected 0 (u8) or 1 (f32)", code)
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match *self {
+            Dtype::U8 => 1,
+            Dtype::F32 => 4
+        }
+    }
+
+    pub fn descr(&self) -> &'static str {
+        match *self {
+            Dtype::U8 => "|u1",
+            Dtype::F32 => "<f4"
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    NHWC,
+    NCHW
+}
+
+
+impl Layout {
+    pub fn from_code(code: u32) -> Layout {
+        match code {
+            0 => Layout::NHWC,
+            1 => Layout::NCHW,
+            _ => panic!("unknown layout {:?}, expected 0 (nhwc) or 1 (nchw)", code)
+        }
+    }
+
+    pub fn shape(&self, n: usize, h: usize, w: usize, c: usize) -> [usize; 4] {
+        match *self {
+            Layout::NHWC => [n, h, w, c],
+            Layout::NCHW => [n, c, h, w]
+        }
+    }
+}
+
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+pub fn header(dtype: Dtype, shape: &[usize]) -> Vec<u8> {
+    // version 1.0 header, space padded so the data starts on a 64 byte boundary
+    let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    let shape = match dims.len() {
+        1 => format!("({},)", dims[0]),
+        _ => format!("({})", dims.join(", "))
+    };
+    let mut dict = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", dtype.descr(), shape);
+    let unpadded = 10 + dict.len() + 1;
+    let padded = (unpadded + 63) / 64 * 64;
+    dict.extend(::std::iter::repeat(' ').take(padded - unpadded));
+    dict.push('\n');
+
+    let mut out = b"\x93NUMPY\x01\x00".to_vec();
+    out.extend_from_slice(&[(dict.len() & 0xff) as u8, (dict.len() >> 8) as u8]);
+    out.extend_from_slice(dict.as_bytes());
+    out
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct NpyHeader {
+    pub descr: String,
+    pub fortran_order: bool,
+    pub shape: Vec<usize>,
+    pub data_offset: u64
+}
+
+
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<NpyHeader> {
+    let mut prefix = [0u8; 8];
+    reader.read_exact(&mut prefix)?;
+    if &prefix[..6] != b"\x93NUMPY" {
+        return Err(invalid("not a .npy file".to_string()));
+    }
+
+    // versions 2.0 and 3.0 widen the header length to 4 bytes
+    let (len, offset) = match prefix[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            (len[0] as usize | (len[1] as usize) << 8, 10)
+        },
+        _ => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            (len.iter().rev().fold(0, |acc, &b| acc << 8 | b as usize), 12)
+        }
+    };
+    let mut dict = vec![0u8; len];
+    reader.read_exact(&mut dict)?;
+    let dict = String::from_utf8_lossy(&dict).into_owned();
+
+    // the dict is a python literal, pick out the three keys numpy always writes
+    let value_of = |key: &str| -> io::Result<&str> {
+        let start = dict.find(&format!("'{}':", key))
+            .ok_or_else(|| invalid(format!("npy header without {}", key)))? + key.len() + 3;
+        Ok(dict[start..].trim_start())
+    };
+    let descr = value_of("descr")?;
+    let descr = descr[1..].split(|c| c == '\'' || c == '"').next().unwrap_or("").to_string();
+    let fortran_order = value_of("fortran_order")?.starts_with("True");
+    let shape = value_of("shape")?;
+    let shape = &shape[1..shape.find(')').ok_or_else(|| invalid("npy header with bad shape".to_string()))?];
+    let shape = shape.split(',').map(|d| d.trim()).filter(|d| !d.is_empty())
+        .map(|d| d.trim_end_matches('L').parse::<usize>().map_err(|_| invalid(format!("bad npy dimension {:?}", d))))
+        .collect::<io::Result<Vec<usize>>>()?;
+
+    Ok(NpyHeader { descr: descr, fortran_order: fortran_order, shape: shape,
+                   data_offset: (offset + len) as u64 })
+}
+
+
+pub fn to_layout(data: &[u8], dtype: Dtype, item_shape: (usize, usize, usize), layout: Layout) -> Vec<u8> {
+    // reorders the [N, H, W, C] bytes of a batch into the requested layout
+    let (h, w, c) = item_shape;
+    let elem = dtype.size();
+    match layout {
+        Layout::NHWC => data.to_vec(),
+        Layout::NCHW => {
+            let item = h * w * c * elem;
+            let mut out = vec![0u8; data.len()];
+            for (src, dst) in data.chunks(item).zip(out.chunks_mut(item)) {
+                for (pixel, px) in src.chunks(c * elem).enumerate() {
+                    for (chan, value) in px.chunks(elem).enumerate() {
+                        let begin = (chan * h * w + pixel) * elem;
+                        dst[begin..begin + elem].copy_from_slice(value);
+                    }
+                }
+            }
+            out
+        }
+    }
+}
+
+
+pub fn nchw<T: Copy>(data: &[T], item_shape: (usize, usize, usize)) -> Vec<T> {
+    // moves the channels of an [N, H, W, C] batch of values first
+    let (h, w, c) = item_shape;
+    let mut out = data.to_vec();
+    for (src, dst) in data.chunks(h * w * c).zip(out.chunks_mut(h * w * c)) {
+        for (pixel, px) in src.chunks(c).enumerate() {
+            for (chan, &value) in px.iter().enumerate() {
+                dst[chan * h * w + pixel] = value;
+            }
+        }
+    }
+    out
+}
+
+
+pub fn npy_bytes(data: &[u8], dtype: Dtype, batch_shape: [usize; 4], layout: Layout) -> Vec<u8> {
+    // a complete .npy file of the [N, H, W, C] batch
+    let [n, h, w, c] = batch_shape;
+    assert!(data.len() == n * h * w * c * dtype.size(), "batch of {:?} bytes is not {:?}", data.len(), batch_shape);
+    let mut out = header(dtype, &layout.shape(n, h, w, c));
+    out.extend_from_slice(&to_layout(data, dtype, (h, w, c), layout));
+    out
+}
+
+
+pub fn write_npy(path: &str, data: &[u8], dtype: Dtype, batch_shape: [usize; 4], layout: Layout) -> io::Result<()> {
+    let mut fout = BufWriter::new(File::create(path)?);
+    fout.write_all(&npy_bytes(data, dtype, batch_shape, layout))?;
+    fout.flush()
+}
+
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0xedb8_8320,
+            _ => crc >> 1
+        })
+    })
+}
+
+
+pub fn write_npz(path: &str, arrays: &[(String, Vec<u8>)]) -> io::Result<()> {
+    // an uncompressed zip holding <name>.npy for every (name, .npy bytes),
+    // which is what numpy.savez writes
+    let mut fout = BufWriter::new(File::create(path)?);
+    let mut central = vec![];
+    let mut offset = 0usize;
+    let le16 = |v: usize| vec![v as u8, (v >> 8) as u8];
+    let le32 = |v: usize| vec![v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8];
+    for &(ref name, ref npy) in arrays {
+        if npy.len() >= 0xffff_ffff || offset >= 0xffff_ffff {
+            return Err(invalid("npz members are limited to 4GB".to_string()));
+        }
+
+        let name = format!("{}.npy", name);
+        let crc = crc32(npy) as usize;
+        // version 2.0, no flags, stored, 1980-01-01 00:00
+        let common: Vec<u8> = [le16(20), le16(0), le16(0), le16(0), le16(0x21), le32(crc),
+                               le32(npy.len()), le32(npy.len()), le16(name.len()), le16(0)].concat();
+
+        let mut local = le32(0x0403_4b50);
+        local.extend_from_slice(&common);
+        local.extend_from_slice(name.as_bytes());
+        fout.write_all(&local)?;
+        fout.write_all(npy)?;
+
+        central.extend(le32(0x0201_4b50));
+        central.extend(le16(20));
+        central.extend_from_slice(&common);
+        central.extend([le16(0), le16(0), le16(0), le32(0), le32(offset)].concat());
+        central.extend_from_slice(name.as_bytes());
+        offset += local.len() + npy.len();
+    }
+
+    fout.write_all(&central)?;
+    let end = [le32(0x0605_4b50), le16(0), le16(0), le16(arrays.len()), le16(arrays.len()),
+               le32(central.len()), le32(offset), le16(0)].concat();
+    fout.write_all(&end)?;
+    fout.flush()
+}
+
+
+fn check_header(header: &NpyHeader, dtype: Dtype, item_shape: &[usize]) -> io::Result<()> {
+    if header.descr != dtype.descr() || header.fortran_order || header.shape.len() != item_shape.len() + 1
+        || &header.shape[1..] != item_shape {
+        return Err(invalid(format!("existing npy holds {} {:?}, expected {} [N, {:?}]",
+                                   header.descr, header.shape, dtype.descr(), item_shape)));
+    }
+    Ok(())
+}
+
+
+// tells apart the temporary files of threads of the same process
+static TMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+
+fn check_memmap(path: &str, dtype: Dtype, shape: &[usize]) -> io::Result<()> {
+    let header = read_header(&mut File::open(path)?)?;
+    check_header(&header, dtype, &shape[1..])?;
+    match header.shape[0] == shape[0] {
+        true  => Ok(()),
+        false => Err(invalid(format!("existing npy has {} rows, expected {}", header.shape[0], shape[0])))
+    }
+}
+
+
+pub fn create_memmap(path: &str, dtype: Dtype, batch_shape: [usize; 4], layout: Layout) -> io::Result<()> {
+    // preallocates a zero filled .npy. an existing file is kept when it holds
+    // the same array, so every process of a job can call this first. the file is
+    // written under a name of its own and hard linked into place, which fails when
+    // another process got there first, so nobody ever reads a half written header
+    let [n, h, w, c] = batch_shape;
+    let shape = layout.shape(n, h, w, c);
+    if Path::new(path).exists() {
+        return check_memmap(path, dtype, &shape);
+    }
+
+    let tmp_path = format!("{}.{}-{}.tmp", path, process::id(), TMP_FILES.fetch_add(1, Ordering::SeqCst));
+    let linked = {
+        let mut fout = File::create(&tmp_path)?;
+        let header = header(dtype, &shape);
+        fout.write_all(&header)
+            .and_then(|_| fout.set_len((header.len() + n * h * w * c * dtype.size()) as u64))
+            .and_then(|_| fs::hard_link(&tmp_path, path))
+    };
+    let _ = fs::remove_file(&tmp_path);
+    match linked {
+        Ok(()) => Ok(()),
+        Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => check_memmap(path, dtype, &shape),
+        Err(err) => Err(err)
+    }
+}
+
+
+pub fn write_rows(path: &str, data: &[u8], dtype: Dtype, indices: &[usize],
+                  item_shape: (usize, usize, usize), layout: Layout) -> io::Result<()> {
+    // writes item i of the [len(indices), H, W, C] batch into row indices[i]
+    // of a preallocated .npy, leaving every other row untouched
+    let (h, w, c) = item_shape;
+    let row = h * w * c * dtype.size();
+    assert!(data.len() == indices.len() * row, "batch of {:?} bytes does not hold {:?} rows",
+            data.len(), indices.len());
+
+    let mut fout = OpenOptions::new().read(true).write(true).open(path)?;
+    let header = read_header(&mut fout)?;
+    check_header(&header, dtype, &layout.shape(0, h, w, c)[1..])?;
+    let rows = header.shape[0];
+    if let Some(&index) = indices.iter().find(|&&i| i >= rows) {
+        return Err(invalid(format!("row {} is out of bounds for {} rows", index, rows)));
+    }
+
+    let data = to_layout(data, dtype, item_shape, layout);
+    for (&index, values) in indices.iter().zip(data.chunks(row)) {
+        fout.seek(SeekFrom::Start(header.data_offset + (index * row) as u64))?;
+        fout.write_all(values)?;
+    }
+    fout.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_npy_layouts_and_header() {
+        // 2 items of 1x2 pixels with 3 channels
+        let data: Vec<u8> = (0..12).collect();
+        let npy = npy_bytes(&data, Dtype::U8, [2, 1, 2, 3], Layout::NCHW);
+        assert!(npy.len() % 64 == 12);
+        let header = read_header(&mut &npy[..]).unwrap();
+        assert!(header == NpyHeader { descr: "|u1".to_string(), fortran_order: false,
+                                      shape: vec![2, 3, 1, 2], data_offset: 128 });
+        assert!(&npy[128..] == &[0, 3, 1, 4, 2, 5, 6, 9, 7, 10, 8, 11]);
+        assert!(nchw(&data, (1, 2, 3)) == npy[128..].to_vec());
+
+        let floats: Vec<u8> = [1.5f32, -2.0].iter().flat_map(|v| v.to_bits().to_le_bytes().to_vec()).collect();
+        let npy = npy_bytes(&floats, Dtype::F32, [1, 1, 2, 1], Layout::NHWC);
+        assert!(read_header(&mut &npy[..]).unwrap().descr == "<f4");
+        assert!(crc32(b"123456789") == 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_memmap_rows() {
+        let path = "assets/test_memmap.npy";
+        let _ = fs::remove_file(path);
+        create_memmap(path, Dtype::U8, [4, 1, 1, 2], Layout::NHWC).unwrap();
+        // a second process opening the same array is fine, a different one is not
+        create_memmap(path, Dtype::U8, [4, 1, 1, 2], Layout::NHWC).unwrap();
+        assert!(create_memmap(path, Dtype::F32, [4, 1, 1, 2], Layout::NHWC).is_err());
+
+        write_rows(path, &[1, 2, 3, 4], Dtype::U8, &[3, 1], (1, 1, 2), Layout::NHWC).unwrap();
+        assert!(write_rows(path, &[1, 2], Dtype::U8, &[4], (1, 1, 2), Layout::NHWC).is_err());
+        let buf = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(&buf[buf.len() - 8..] == &[0, 0, 3, 4, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_npz_members() {
+        let path = "assets/test_batch.npz";
+        let first = npy_bytes(&[1, 2, 3, 4, 5, 6], Dtype::U8, [1, 1, 2, 3], Layout::NHWC);
+        let second = npy_bytes(&[7, 8, 9], Dtype::U8, [1, 1, 1, 3], Layout::NCHW);
+        write_npz(path, &[("crops".to_string(), first.clone()), ("labels".to_string(), second.clone())]).unwrap();
+        let buf = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        // walk the local file headers: name length at 26, stored size at 18, name at 30
+        let le = |at: usize, n: usize| buf[at..at + n].iter().rev().fold(0, |acc, &b| acc << 8 | b as usize);
+        let mut members = vec![];
+        let mut at = 0;
+        while le(at, 4) == 0x0403_4b50 {
+            let (size, name_len) = (le(at + 18, 4), le(at + 26, 2));
+            let name = String::from_utf8(buf[at + 30..at + 30 + name_len].to_vec()).unwrap();
+            let data = buf[at + 30 + name_len..at + 30 + name_len + size].to_vec();
+            assert!(le(at + 14, 4) == crc32(&data) as usize);
+            members.push((name, data));
+            at += 30 + name_len + size;
+        }
+        assert!(members == vec![("crops.npy".to_string(), first), ("labels.npy".to_string(), second)]);
+
+        // the central directory follows with one entry per member, then the end record
+        let end = buf.len() - 22;
+        assert!(le(end, 4) == 0x0605_4b50 && le(end + 10, 2) == 2);
+        assert!(le(end + 16, 4) == at && le(at, 4) == 0x0201_4b50);
+        let header = read_header(&mut &members[0].1[..]).unwrap();
+        assert!(header.shape == vec![1, 1, 2, 3] && &members[0].1[header.data_offset as usize..] == &[1, 2, 3, 4, 5, 6]);
+    }
+}
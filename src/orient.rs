@@ -0,0 +1,223 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use augment::GeometricAug;
+
+
+// EXIF orientation handling shared by both backends: the tag is read with a
+// minimal parser and the image is flipped / rotated upright before any crop
+// co-ordinates are interpreted
+
+
+lazy_static! {
+    // the process wide default, for threads outside a configured crop manager
+    static ref AUTO_ORIENT: AtomicBool = AtomicBool::new(true);
+}
+
+thread_local! {
+    // the setting of the crop manager whose pool runs this thread, if it has one
+    static MANAGER_ORIENT: Cell<Option<bool>> = Cell::new(None);
+}
+
+
+pub fn enabled() -> bool {
+    MANAGER_ORIENT.with(|m| m.get()).unwrap_or_else(|| AUTO_ORIENT.load(Relaxed))
+}
+
+
+pub fn set_enabled(enabled: bool) {
+    AUTO_ORIENT.store(enabled, Relaxed)
+}
+
+
+pub fn set_thread_enabled(enabled: Option<bool>) {
+    // pins the setting of the calling thread, None follows the process default
+    MANAGER_ORIENT.with(|m| m.set(enabled))
+}
+
+
+fn read_u16(buf: &[u8], offset: usize, little_endian: bool) -> Option<u16> {
+    let b = buf.get(offset..offset + 2)?;
+    Some(match little_endian {
+        true  => b[0] as u16 | (b[1] as u16) << 8,
+        false => (b[0] as u16) << 8 | b[1] as u16
+    })
+}
+
+
+fn read_u32(buf: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let lo = read_u16(buf, offset + if little_endian { 0 } else { 2 }, little_endian)? as u32;
+    let hi = read_u16(buf, offset + if little_endian { 2 } else { 0 }, little_endian)? as u32;
+    Some(hi << 16 | lo)
+}
+
+
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    // looks for the orientation tag (0x0112) in the first IFD of a tiff structure
+    let little_endian = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None
+    };
+
+    let ifd = read_u32(tiff, 4, little_endian)? as usize;
+    let num_entries = read_u16(tiff, ifd, little_endian)? as usize;
+    for i in 0..num_entries {
+        let entry = ifd + 2 + 12 * i;
+        if read_u16(tiff, entry, little_endian)? == 0x0112 {
+            return read_u16(tiff, entry + 8, little_endian);
+        }
+    }
+
+    None
+}
+
+
+fn jpeg_orientation(jpeg: &[u8]) -> Option<u16> {
+    // walks the jpeg markers up to the first scan looking for an Exif APP1 segment
+    if jpeg.get(0..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+
+    let mut pos = 2;
+    loop {
+        if *jpeg.get(pos)? != 0xFF {
+            return None;
+        }
+
+        let marker = *jpeg.get(pos + 1)?;
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+
+        let segment_len = read_u16(jpeg, pos + 2, false)? as usize;
+        if marker == 0xE1 && jpeg.get(pos + 4..pos + 10)? == b"Exif\0\0" {
+            return tiff_orientation(jpeg.get(pos + 10..pos + 2 + segment_len)?);
+        }
+        pos += 2 + segment_len;
+    }
+}
+
+
+pub fn orientation(path: &str) -> u16 {
+    // the EXIF orientation of a jpeg or tiff file, 1 (upright) when absent
+    let mut head = Vec::new();
+    let read = File::open(path).and_then(|f| f.take(1 << 16).read_to_end(&mut head));
+    if read.is_err() {
+        return 1;
+    }
+
+    match jpeg_orientation(&head).or_else(|| tiff_orientation(&head)) {
+        Some(tag) if tag >= 1 && tag <= 8 => tag,
+        _ => 1
+    }
+}
+
+
+pub fn orientation_transform(orientation: u16) -> GeometricAug {
+    // the flips and clockwise quarter turns that bring a stored image upright
+    let (hflip, rot90) = match orientation {
+        2 => (true, 0),
+        3 => (false, 2),
+        5 => (true, 3),  // transpose
+        6 => (false, 1),
+        7 => (true, 1),  // transverse
+        8 => (false, 3),
+        _ => (false, 0)
+    };
+
+    GeometricAug { hflip: hflip, vflip: orientation == 4, rot90: rot90 }
+}
+
+
+pub fn transform(path: &str) -> Option<GeometricAug> {
+    // None when the image is already upright or orientation handling is off
+    if !enabled() {
+        return None;
+    }
+
+    match orientation(path) {
+        1 => None,
+        tag => Some(orientation_transform(tag))
+    }
+}
+
+
+pub fn oriented_size(size: (u32, u32), transform: Option<GeometricAug>) -> (u32, u32) {
+    match transform {
+        Some(aug) if aug.rot90 % 2 == 1 => (size.1, size.0),
+        _ => size
+    }
+}
+
+
+pub fn orient_buffer<T: Copy>(data: &[T], size: (u32, u32), chans: u32, aug: &GeometricAug) -> (Vec<T>, (u32, u32)) {
+    // applies the flips and quarter turns to a raw [h, w, chans] buffer
+    let out_size = oriented_size(size, Some(*aug));
+    let chans = chans as usize;
+    let mut out = data.to_vec();
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            let (mut u, mut v) = (x, y);
+            if aug.hflip { u = size.0 - 1 - u; }
+            if aug.vflip { v = size.1 - 1 - v; }
+
+            // a clockwise turn of a (w, h) image sends (u, v) to (h - 1 - v, u)
+            let mut cur = size;
+            for _ in 0..aug.rot90 {
+                let turned = (cur.1 - 1 - v, u);
+                u = turned.0;
+                v = turned.1;
+                cur = (cur.1, cur.0);
+            }
+
+            let src = (y as usize * size.0 as usize + x as usize) * chans;
+            let dst = (v as usize * out_size.0 as usize + u as usize) * chans;
+            out[dst..dst + chans].copy_from_slice(&data[src..src + chans]);
+        }
+    }
+
+    (out, out_size)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exif_orientation_parsing() {
+        // a jpeg header with a big endian Exif segment holding orientation 6
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x22];
+        jpeg.extend_from_slice(b"Exif\0\0MM\0\x2a\0\0\0\x08");
+        jpeg.extend_from_slice(&[0x00, 0x01, 0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01,
+                                 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xDA]);
+        assert!(jpeg_orientation(&jpeg) == Some(6));
+        assert!(jpeg_orientation(&[0xFF, 0xD8, 0xFF, 0xDA]) == None);
+        assert!(orientation("assets/lena.png") == 1);
+    }
+
+    #[test]
+    fn test_orient_buffer() {
+        // orientation 6 rotates clockwise: a 3x2 image becomes 2x3
+        let data = [1u8, 2, 3,
+                    4, 5, 6];
+        let (rotated, size) = orient_buffer(&data, (3, 2), 1, &orientation_transform(6));
+        assert!(size == (2, 3) && rotated == vec![4, 1, 5, 2, 6, 3], "rotated was {:?}", rotated);
+
+        // orientation 5 is a transpose
+        let (transposed, _) = orient_buffer(&data, (3, 2), 1, &orientation_transform(5));
+        assert!(transposed == vec![1, 4, 2, 5, 3, 6], "transposed was {:?}", transposed);
+    }
+
+    #[test]
+    fn test_thread_setting() {
+        // a pinned thread ignores the process default until it's unpinned
+        set_thread_enabled(Some(false));
+        assert!(!enabled());
+        set_thread_enabled(None);
+        assert!(enabled() == AUTO_ORIENT.load(Relaxed));
+    }
+}
@@ -0,0 +1,108 @@
+use std::{slice, str, ptr};
+use std::ffi::CStr;
+use libc::{c_char, c_void, size_t};
+
+
+// paired crops: several aligned streams per item (eg: image, label mask and
+// depth) cropped with one set of crop parameters, each stream resized with
+// its own filter and written with its own dtype
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResizeFilter {
+    Nearest,   // label masks
+    Bilinear,
+    Bicubic,
+    Lanczos3
+}
+
+
+impl ResizeFilter {
+    pub fn from_code(code: u32) -> ResizeFilter {
+        match code {
+            0 => ResizeFilter::Nearest,
+            1 => ResizeFilter::Bilinear,
+            2 => ResizeFilter::Bicubic,
+            3 => ResizeFilter::Lanczos3,
+            _ => panic!("unknown resize filter {:?}", code)
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamDtype {
+    U8,
+    F32  // the u8 values, unscaled
+}
+
+
+impl StreamDtype {
+    pub fn from_code(code: u32) -> StreamDtype {
+        match code {
+            0 => StreamDtype::U8,
+            1 => StreamDtype::F32,
+            _ => panic!("unknown stream dtype {:?}", code)
+        }
+    }
+}
+
+
+pub struct Stream {
+    pub image_paths_ptr: *const *const c_char,
+    pub return_ptr: *mut c_void,  // [N, window_size, window_size, chans] of dtype
+    pub chans: u32,
+    pub filter: ResizeFilter,
+    pub dtype: StreamDtype
+}
+
+
+pub fn stream_paths<'a>(stream: &Stream, length: size_t) -> Vec<&'a str> {
+    assert!(!stream.image_paths_ptr.is_null(), "can't operate over null list of image paths");
+    unsafe { slice::from_raw_parts(stream.image_paths_ptr, length as usize) }.iter()
+        .map(|&p| unsafe { CStr::from_ptr(p) })
+        .map(|cs| str::from_utf8(cs.to_bytes()).unwrap())
+        .collect()
+}
+
+
+pub fn write_window(stream: &Stream, index: usize, window: &[u8], window_size: u32) {
+    // copies the u8 window of item index into the stream's return array
+    let win_size = (window_size * window_size * stream.chans) as usize;
+    assert!(window.len() == win_size, "rvec [{:?}] != window_size [{:?}]",
+            window.len(), win_size);
+    let begin = (index * win_size) as isize;
+    match stream.dtype {
+        StreamDtype::U8 => unsafe {
+            ptr::copy(window.as_ptr(), (stream.return_ptr as *mut u8).offset(begin), win_size)
+        },
+        StreamDtype::F32 => {
+            let dst = unsafe { slice::from_raw_parts_mut((stream.return_ptr as *mut f32).offset(begin), win_size) };
+            for (d, &s) in dst.iter_mut().zip(window) {
+                *d = s as f32;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_window_dtypes() {
+        let mut as_u8 = vec![0u8; 8];
+        let mut as_f32 = vec![0f32; 8];
+        let window = [1u8, 2, 3, 4];
+        for &(ret, dtype) in [(as_u8.as_mut_ptr() as *mut c_void, StreamDtype::U8),
+                              (as_f32.as_mut_ptr() as *mut c_void, StreamDtype::F32)].iter() {
+            let stream = Stream { image_paths_ptr: ptr::null(), return_ptr: ret, chans: 1,
+                                  filter: ResizeFilter::Nearest, dtype: dtype };
+            write_window(&stream, 1, &window, 2);
+        }
+
+        assert!(as_u8 == vec![0, 0, 0, 0, 1, 2, 3, 4]);
+        assert!(as_f32 == vec![0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 4.0]);
+    }
+}
@@ -7,40 +7,52 @@ use std::{slice, str, mem};
 use rayon::prelude::*;
 use libc::{size_t, c_char, c_uchar, c_void, c_double, c_longlong};
 use image::{GenericImage, ImageBuffer, imageops, FilterType, ColorType, ImageDecoder, DynamicImage};
+use boxes::{CropBox, BoxUnits, ResizeMode};
+use warp::{Affine, Homography, BorderMode};
+use sampling::CropSampler;
+use rng::Rng;
+use augment::{Augment, GeometricAug};
+use paired;
+use paired::{Stream, ResizeFilter};
+use depth;
+use depth::Output;
+use orient;
+use lazy_load;
+use tiled;
+use save;
+use batch;
+use save::SaveFormat;
 
 //use time::PreciseTime;
 
 
+pub fn open_image(path: &str) -> DynamicImage {
+    open_image_frame(path, 0)
+}
+
+
+pub fn open_image_frame(path: &str, frame: u32) -> DynamicImage {
+    // decodes the page / frame and brings it upright according to its EXIF orientation
+    let img = lazy_load::frame_to_image(path, frame).unwrap();
+    match orient::transform(path) {
+        Some(aug) => apply_geometric(img, &aug),
+        None => img
+    }
+}
+
+
 pub fn crop_and_resize(path: &str, scale: f32, x_crop: f32, y_crop: f32,
                        max_img_percent: f32, resize_width: u32, resize_height: u32) -> DynamicImage
 {
     // let start = PreciseTime::now();
 
-    assert!(x_crop >= 0f32 && x_crop <= 1f32, "x of crop not bounded in [0, 1]");
-    assert!(y_crop >= 0f32 && y_crop <= 1f32, "y of crop not bounded in [0, 1]");
-
     // read the image and grab the size TODO: read using decoder
-    let mut img = image::open(&Path::new(&path)).unwrap();
-    let img_size = img.dimensions();
-
-    // scale the x and y co-ordinates to the img_size
-    let mut x = super::scale_range(x_crop, 0f32, img_size.0 as f32) as u32;
-    let mut y = super::scale_range(y_crop, 0f32, img_size.1 as f32) as u32;
-
-    // calculate the scale of the true crop using the provided scale
-    // NOTE: this is different from the return size, i.e. window_size
-    let crop_scale = scale.min(max_img_percent);
-    let crop_size = ((img_size.0 as f32 * crop_scale).floor().max(2.0) as u32,
-                     (img_size.1 as f32 * crop_scale).floor().max(2.0) as u32);
-    let max_coords = (img_size.0 - crop_size.0,
-                      img_size.1 - crop_size.1);
-
-    // threshold the max x and y
-    x = x.min(max_coords.0);
-    y = y.min(max_coords.1);
+    let mut img = open_image(path);
+    let (x, y, width, height) = super::crop_region(img.dimensions(), scale, x_crop, y_crop,
+                                                   max_img_percent);
 
     // crop the image and resize it
-    let crp = img.crop(x, y, crop_size.0, crop_size.1).resize_exact(
+    let crp = img.crop(x, y, width, height).resize_exact(
         resize_width, resize_height, FilterType::Nearest
     );
 
@@ -124,6 +136,556 @@ pub fn parallel_crop_and_resize(image_paths_ptr: *const *const c_char,
 }
 
 
+pub fn crop_and_save(path: &str, scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                     window_size: u32, filter: Option<ResizeFilter>, out_path: &str, format: SaveFormat) -> bool
+{
+    let mut img = open_image(path);
+    let (x, y, width, height) = super::crop_region(img.dimensions(), scale, x_crop, y_crop,
+                                                   max_img_percent);
+    let filter = filter.map_or(FilterType::Nearest, filter_type);
+    let crop = img.crop(x, y, width, height).resize_exact(window_size, window_size, filter);
+    save::encode(&crop, out_path, format).is_ok()
+}
+
+
+pub fn execute_save_job(job: &super::SaveJob) -> usize {
+    save::run(job, |path, scale, x, y, out_path| {
+        crop_and_save(path, scale, x, y, job.max_img_percent, job.window_size, job.filter, out_path, job.format)
+    })
+}
+
+
+pub fn probe_image(path: &str) -> Option<(u32, u32, u32)> {
+    // the upright (width, height, chans) of an image, read from its header only
+    let (width, height, chans) = lazy_load::header(path).ok()?;
+    let size = orient::oriented_size((width, height), orient::transform(path));
+    Some((size.0, size.1, chans))
+}
+
+
+pub fn num_channels(img: &DynamicImage) -> u32 {
+    match img.color() {
+        ColorType::Gray(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) => 3,
+        _ => 4
+    }
+}
+
+
+pub fn crop_box_and_resize(path: &str, bbox: &CropBox, units: BoxUnits, padding: f32,
+                           mode: ResizeMode, resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    let mut img = open_image(path);
+    crop_image_box(&mut img, bbox, units, padding, mode, resize_width, resize_height)
+}
+
+
+pub fn crop_image_box(img: &mut DynamicImage, bbox: &CropBox, units: BoxUnits, padding: f32,
+                      mode: ResizeMode, resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // convert the box to a pixel region
+    let (x, y, width, height) = super::boxes::box_to_region(bbox, units, padding, img.dimensions());
+
+    // crop the image and resize it, letterboxing if requested
+    let crp = img.crop(x, y, width, height);
+    match mode {
+        ResizeMode::Stretch => crp.resize_exact(resize_width, resize_height, FilterType::Nearest).raw_pixels(),
+        ResizeMode::Letterbox => {
+            let size = super::boxes::letterbox_size((width, height), (resize_width, resize_height));
+            let resized = crp.resize_exact(size.0, size.1, FilterType::Nearest);
+            super::boxes::letterbox(&resized.raw_pixels(), size,
+                                    (resize_width, resize_height), num_channels(&resized))
+        }
+    }
+}
+
+
+pub fn execute_box_job(job: &super::BoxJob){
+    parallel_crop_boxes_and_resize(job.image_paths_ptr,
+                                   job.return_ptr,
+                                   job.boxes_ptr,
+                                   job.units,
+                                   job.padding,
+                                   job.mode,
+                                   job.window_size,
+                                   job.chans,
+                                   job.length)
+}
+
+
+pub fn parallel_crop_boxes_and_resize(image_paths_ptr: *const *const c_char,
+                                      return_ptr: *mut u8,
+                                      boxes_ptr: *const f32,
+                                      units: BoxUnits,
+                                      padding: f32,
+                                      mode: ResizeMode,
+                                      window_size: u32,
+                                      chans: u32,
+                                      length: size_t)
+{
+    // accepts list of image-paths (str), a [N, 4] array of (x0, y0, x1, y1) boxes
+    // and the size of the arrays (i.e. batch dim) and returns crops of all the images
+    assert!(!boxes_ptr.is_null(), "can't operate over null box vector");
+    let box_values: Vec<CropBox> = unsafe { slice::from_raw_parts(boxes_ptr, 4 * length as usize) }
+        .chunks(4).map(CropBox::from_slice).collect();
+
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        crop_box_and_resize(path, &box_values[idx], units, padding, mode, window_size, window_size)
+    });
+}
+
+
+pub fn affine_crop(path: &str, matrix: &Affine, border: BorderMode, fill: f32,
+                   resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // sample the output window straight from the source, no intermediate crop
+    let img = open_image(path);
+    super::warp::warp_affine(&img.raw_pixels(), img.dimensions(), num_channels(&img),
+                             matrix, (resize_width, resize_height), border, fill)
+}
+
+
+pub fn parallel_affine_crop(image_paths_ptr: *const *const c_char,
+                            return_ptr: *mut u8,
+                            matrices: &[Affine],
+                            border: BorderMode,
+                            fill: f32,
+                            window_size: u32,
+                            chans: u32,
+                            length: size_t)
+{
+    // accepts list of image-paths (str), one affine matrix per image
+    // and the size of the arrays (i.e. batch dim) and returns crops of all the images
+    assert!(matrices.len() == length as usize, "matrices [{:?}] != length [{:?}]",
+            matrices.len(), length);
+
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        affine_crop(path, &matrices[idx], border, fill, window_size, window_size)
+    });
+}
+
+
+pub fn perspective_crop(path: &str, homography: &Homography, border: BorderMode, fill: f32,
+                        resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // rectify the quadrilateral straight into the output window
+    let img = open_image(path);
+    super::warp::warp_perspective(&img.raw_pixels(), img.dimensions(), num_channels(&img),
+                                  homography, (resize_width, resize_height), border, fill)
+}
+
+
+pub fn parallel_perspective_crop(image_paths_ptr: *const *const c_char,
+                                 return_ptr: *mut u8,
+                                 homographies: &[Homography],
+                                 border: BorderMode,
+                                 fill: f32,
+                                 window_size: u32,
+                                 chans: u32,
+                                 length: size_t)
+{
+    // accepts list of image-paths (str), one homography per image
+    // and the size of the arrays (i.e. batch dim) and returns crops of all the images
+    assert!(homographies.len() == length as usize, "homographies [{:?}] != length [{:?}]",
+            homographies.len(), length);
+
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        perspective_crop(path, &homographies[idx], border, fill, window_size, window_size)
+    });
+}
+
+
+pub fn subpixel_crop_and_resize(path: &str, scale: f32, x_crop: f32, y_crop: f32,
+                                max_img_percent: f32, resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // continuous version of crop_and_resize, see warp::subpixel_crop
+    let img = open_image(path);
+    super::warp::subpixel_crop(&img.raw_pixels(), img.dimensions(), num_channels(&img),
+                               scale, x_crop, y_crop, max_img_percent,
+                               (resize_width, resize_height))
+}
+
+
+pub fn execute_subpixel_job(job: &super::Job){
+    parallel_subpixel_crop_and_resize(job.image_paths_ptr,
+                                      job.return_ptr,
+                                      job.scale_ptr,
+                                      job.x_ptr,
+                                      job.y_ptr,
+                                      job.window_size,
+                                      job.chans,
+                                      job.max_img_percent,
+                                      job.length)
+}
+
+
+pub fn parallel_subpixel_crop_and_resize(image_paths_ptr: *const *const c_char,
+                                         return_ptr: *mut u8,
+                                         scale_ptr: *const f32,
+                                         x_ptr: *const f32,
+                                         y_ptr: *const f32,
+                                         window_size: u32,
+                                         chans: u32,
+                                         max_img_percent: f32,
+                                         length: size_t)
+{
+    // same contract as parallel_crop_and_resize, but bilinearly sampled
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        subpixel_crop_and_resize(path, scale_values[idx], x_values[idx], y_values[idx],
+                                 max_img_percent, window_size, window_size)
+    });
+}
+
+
+pub fn random_crop_and_resize(path: &str, sampler: &CropSampler, rng: &mut Rng,
+                              resize_width: u32, resize_height: u32) -> (Vec<u8>, CropBox)
+{
+    // sample a box for this image and crop it, returning the normalized box
+    let mut img = open_image(path);
+    let img_size = img.dimensions();
+    let bbox = super::sampling::sample_box(sampler, img_size, rng);
+    let crop = crop_image_box(&mut img, &bbox, BoxUnits::Pixel, 0.0, ResizeMode::Stretch,
+                              resize_width, resize_height);
+    (crop, super::sampling::normalize_box(&bbox, img_size))
+}
+
+
+pub fn parallel_random_crop_and_resize(image_paths_ptr: *const *const c_char,
+                                       return_ptr: *mut u8,
+                                       params_ptr: *mut f32,
+                                       sampler: &CropSampler,
+                                       seed: u64,
+                                       window_size: u32,
+                                       chans: u32,
+                                       length: size_t)
+{
+    // accepts list of image-paths (str) and the size of the arrays (i.e. batch dim),
+    // samples a crop per image from (seed, index) and returns the crops along
+    // with the sampled normalized (x0, y0, x1, y1) boxes in params_ptr
+    assert!(!params_ptr.is_null(), "can't operate over null params vector");
+    let win_size = (window_size * window_size * chans) as usize;
+    let boxes = batch::run_with(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        let mut rng = Rng::for_op(::rng::RANDOM_CROP, seed, idx);
+        random_crop_and_resize(path, sampler, &mut rng, window_size, window_size)
+    });
+
+    // copy the boxes into the params
+    let params = unsafe { slice::from_raw_parts_mut(params_ptr, 4 * length as usize) };
+    for (param, bbox) in params.chunks_mut(4).zip(boxes) {
+        param.copy_from_slice(&[bbox.x0, bbox.y0, bbox.x1, bbox.y1]);
+    }
+}
+
+
+pub fn apply_geometric(img: DynamicImage, aug: &GeometricAug) -> DynamicImage {
+    // flips first, then the clockwise quarter turns
+    let img = if aug.hflip { img.fliph() } else { img };
+    let img = if aug.vflip { img.flipv() } else { img };
+    match aug.rot90 {
+        1 => img.rotate90(),
+        2 => img.rotate180(),
+        3 => img.rotate270(),
+        _ => img
+    }
+}
+
+
+pub fn crop_and_augment(path: &str, scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                        resize_width: u32, resize_height: u32, augment: &Augment) -> Vec<u8>
+{
+    // augmentations run on the resized window
+    let crp = crop_and_resize(path, scale, x_crop, y_crop, max_img_percent,
+                              resize_width, resize_height);
+    let augmented = apply_geometric(crp, &augment.geometric);
+    let chans = num_channels(&augmented);
+    let mut pixels = augmented.raw_pixels();
+    super::augment::apply_photometric(&mut pixels, augmented.dimensions(), chans,
+                                      &augment.photometric);
+    super::augment::apply_erase(&mut pixels, augmented.dimensions(), chans, &augment.erase);
+    pixels
+}
+
+
+pub fn execute_augment_job(job: &super::AugmentJob){
+    parallel_crop_and_augment(job.base.image_paths_ptr,
+                              job.base.return_ptr,
+                              job.base.scale_ptr,
+                              job.base.x_ptr,
+                              job.base.y_ptr,
+                              job.base.window_size,
+                              job.base.chans,
+                              job.base.max_img_percent,
+                              &job.augments,
+                              job.base.length)
+}
+
+
+pub fn parallel_crop_and_augment(image_paths_ptr: *const *const c_char,
+                                 return_ptr: *mut u8,
+                                 scale_ptr: *const f32,
+                                 x_ptr: *const f32,
+                                 y_ptr: *const f32,
+                                 window_size: u32,
+                                 chans: u32,
+                                 max_img_percent: f32,
+                                 augments: &[Augment],
+                                 length: size_t)
+{
+    // same contract as parallel_crop_and_resize plus one set of augmentations per image
+    assert!(augments.len() == length as usize, "augments [{:?}] != length [{:?}]",
+            augments.len(), length);
+
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        crop_and_augment(path, scale_values[idx], x_values[idx], y_values[idx],
+                         max_img_percent, window_size, window_size, &augments[idx])
+    });
+}
+
+
+fn filter_type(filter: ResizeFilter) -> FilterType {
+    match filter {
+        ResizeFilter::Nearest  => FilterType::Nearest,
+        ResizeFilter::Bilinear => FilterType::Triangle,
+        ResizeFilter::Bicubic  => FilterType::CatmullRom,
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3
+    }
+}
+
+
+pub fn paired_crop_and_resize(paths: &[&str], scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                              filters: &[ResizeFilter], resize_width: u32, resize_height: u32) -> Vec<Vec<u8>>
+{
+    // every stream is cropped with the region computed for the first one
+    let mut imgs: Vec<DynamicImage> = paths.iter().map(|path| open_image(path)).collect();
+    let img_size = imgs[0].dimensions();
+    let (x, y, width, height) = super::crop_region(img_size, scale, x_crop, y_crop, max_img_percent);
+
+    imgs.iter_mut().zip(paths).zip(filters).map(|((img, path), filter)| {
+        assert!(img.dimensions() == img_size, "{:?} is {:?} but its pair is {:?}",
+                path, img.dimensions(), img_size);
+        img.crop(x, y, width, height)
+            .resize_exact(resize_width, resize_height, filter_type(*filter))
+            .raw_pixels()
+    }).collect()
+}
+
+
+pub fn execute_paired_job(job: &super::PairedJob){
+    parallel_paired_crop_and_resize(&job.streams,
+                                    job.scale_ptr,
+                                    job.x_ptr,
+                                    job.y_ptr,
+                                    job.window_size,
+                                    job.max_img_percent,
+                                    job.length)
+}
+
+
+pub fn parallel_paired_crop_and_resize(streams: &[Stream],
+                                       scale_ptr: *const f32,
+                                       x_ptr: *const f32,
+                                       y_ptr: *const f32,
+                                       window_size: u32,
+                                       max_img_percent: f32,
+                                       length: size_t)
+{
+    // accepts one list of image-paths per stream, a vector (np) of z's shared by
+    // all the streams and writes the aligned crops into every stream's return array
+    assert!(!scale_ptr.is_null(), "can't operate over null scale vector");
+    assert!(!x_ptr.is_null(), "can't operate over null x vector");
+    assert!(!y_ptr.is_null(), "can't operate over null y vector");
+    assert!(!streams.is_empty(), "can't operate over zero streams");
+    for stream in streams {
+        assert!(!stream.return_ptr.is_null(), "can't operate over null result vector");
+    }
+
+    // gather the paths of every stream and the z into arrays of [scale, x, y]
+    let stream_paths: Vec<Vec<&str>> = streams.iter().map(|s| paired::stream_paths(s, length)).collect();
+    let filters: Vec<ResizeFilter> = streams.iter().map(|s| s.filter).collect();
+    let scale_values = unsafe { slice::from_raw_parts(scale_ptr, length as usize) };
+    let x_values = unsafe { slice::from_raw_parts(x_ptr, length as usize) };
+    let y_values = unsafe { slice::from_raw_parts(y_ptr, length as usize) };
+
+    let mut resultant_vec = vec![];
+    (0..length as usize).into_par_iter()
+        .map(|idx| {
+            let paths: Vec<&str> = stream_paths.iter().map(|p| p[idx]).collect();
+            paired_crop_and_resize(&paths,
+                                   scale_values[idx], x_values[idx], y_values[idx],
+                                   max_img_percent, &filters,
+                                   window_size, window_size)
+        }).collect_into_vec(&mut resultant_vec);
+
+    // copy every stream's window into its return array
+    for (idx, windows) in resultant_vec.iter().enumerate() {
+        for (stream, window) in streams.iter().zip(windows) {
+            paired::write_window(stream, idx, window, window_size);
+        }
+    }
+}
+
+
+pub fn crop_and_resize_depth(path: &str, scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                             resize_width: u32, resize_height: u32, output: Output) -> Vec<f32>
+{
+    // decode at the source bit depth, crop, resize and map into the output range
+    let samples = depth::decode(path).unwrap();
+    let region = super::crop_region(samples.size, scale, x_crop, y_crop, max_img_percent);
+    let mut window = depth::crop_and_resize(&samples, region, (resize_width, resize_height));
+    depth::apply_output(&mut window, output);
+    window
+}
+
+
+pub fn execute_depth_job(job: &super::DepthJob){
+    parallel_crop_and_resize_depth(job.image_paths_ptr,
+                                   job.return_ptr,
+                                   job.scale_ptr,
+                                   job.x_ptr,
+                                   job.y_ptr,
+                                   job.window_size,
+                                   job.chans,
+                                   job.max_img_percent,
+                                   job.output,
+                                   job.length)
+}
+
+
+pub fn parallel_crop_and_resize_depth(image_paths_ptr: *const *const c_char,
+                                      return_ptr: *mut c_void,
+                                      scale_ptr: *const f32,
+                                      x_ptr: *const f32,
+                                      y_ptr: *const f32,
+                                      window_size: u32,
+                                      chans: u32,
+                                      max_img_percent: f32,
+                                      output: Output,
+                                      length: size_t)
+{
+    // same contract as parallel_crop_and_resize, but return_ptr holds the dtype of output
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    depth::run(image_paths_ptr, return_ptr, output, win_size, length, |idx, path| {
+        crop_and_resize_depth(path, scale_values[idx], x_values[idx], y_values[idx],
+                              max_img_percent, window_size, window_size, output)
+    });
+}
+
+
+pub fn crop_and_resize_frame(path: &str, frame: u32, scale: f32, x_crop: f32, y_crop: f32,
+                             max_img_percent: f32, resize_width: u32, resize_height: u32) -> DynamicImage
+{
+    // crop_and_resize over a page of a multi-page tiff or a frame of an animated gif
+    let mut img = open_image_frame(path, frame);
+    let (x, y, width, height) = super::crop_region(img.dimensions(), scale, x_crop, y_crop,
+                                                   max_img_percent);
+    img.crop(x, y, width, height).resize_exact(resize_width, resize_height, FilterType::Nearest)
+}
+
+
+pub fn execute_frame_job(job: &super::FrameJob){
+    parallel_crop_and_resize_frames(job.base.image_paths_ptr,
+                                    job.base.return_ptr,
+                                    job.base.scale_ptr,
+                                    job.base.x_ptr,
+                                    job.base.y_ptr,
+                                    job.base.window_size,
+                                    job.base.chans,
+                                    job.base.max_img_percent,
+                                    &job.frames,
+                                    job.base.length)
+}
+
+
+pub fn parallel_crop_and_resize_frames(image_paths_ptr: *const *const c_char,
+                                       return_ptr: *mut u8,
+                                       scale_ptr: *const f32,
+                                       x_ptr: *const f32,
+                                       y_ptr: *const f32,
+                                       window_size: u32,
+                                       chans: u32,
+                                       max_img_percent: f32,
+                                       frames: &[u32],
+                                       length: size_t)
+{
+    // same contract as parallel_crop_and_resize plus the page / frame to read per image
+    assert!(frames.len() == length as usize, "frames [{:?}] != length [{:?}]",
+            frames.len(), length);
+
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        crop_and_resize_frame(path, frames[idx], scale_values[idx], x_values[idx], y_values[idx],
+                              max_img_percent, window_size, window_size).raw_pixels()
+    });
+}
+
+
+pub fn tiled_crop_and_resize(path: &str, scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                             resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // the crop region is computed on the full resolution level, but only read
+    // from the coarsest pyramid level that still covers the window
+    let levels = tiled::read_levels(path).unwrap();
+    let full_size = levels[0].size;
+    let region = super::crop_region(full_size, scale, x_crop, y_crop, max_img_percent);
+    let level = &levels[tiled::select_level(&levels, (region.2, region.3), (resize_width, resize_height))];
+    let (x, y, width, height) = tiled::level_region(region, full_size, level);
+    let pixels = tiled::read_region(path, level, (x, y, width, height)).unwrap();
+
+    let color = match level.chans {
+        1 => ColorType::Gray(8),
+        2 => ColorType::GrayA(8),
+        3 => ColorType::RGB(8),
+        _ => ColorType::RGBA(8)
+    };
+    lazy_load::decoded_to_image(color, image::DecodingResult::U8(pixels), (width, height)).unwrap()
+        .resize_exact(resize_width, resize_height, FilterType::Nearest)
+        .raw_pixels()
+}
+
+
+pub fn execute_tiled_job(job: &super::Job){
+    parallel_tiled_crop_and_resize(job.image_paths_ptr,
+                                   job.return_ptr,
+                                   job.scale_ptr,
+                                   job.x_ptr,
+                                   job.y_ptr,
+                                   job.window_size,
+                                   job.chans,
+                                   job.max_img_percent,
+                                   job.length)
+}
+
+
+pub fn parallel_tiled_crop_and_resize(image_paths_ptr: *const *const c_char,
+                                      return_ptr: *mut u8,
+                                      scale_ptr: *const f32,
+                                      x_ptr: *const f32,
+                                      y_ptr: *const f32,
+                                      window_size: u32,
+                                      chans: u32,
+                                      max_img_percent: f32,
+                                      length: size_t)
+{
+    // same contract as parallel_crop_and_resize for tiled pyramidal tiffs
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        tiled_crop_and_resize(path, scale_values[idx], x_values[idx], y_values[idx],
+                              max_img_percent, window_size, window_size)
+    });
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -176,4 +738,137 @@ mod tests {
         center.save(&Path::new("assets/test_lena_center.png")).unwrap();
         assert!(center.dimensions() == (32, 32), "center right was {:?}", center.dimensions());
     }
+
+    #[test]
+    fn test_box_crops() {
+        // a stretched box crop fills the window
+        let bbox = CropBox::new(0.25, 0.25, 0.75, 0.5);
+        let stretched = crop_box_and_resize("assets/lena.png", &bbox, BoxUnits::Normalized,
+                                            0.0, ResizeMode::Stretch, 32, 32);
+        assert!(stretched.len() == 32*32*3, "stretched was {:?}", stretched.len());
+
+        // a 2:1 box letterboxed into a square window leaves the top rows empty
+        let letterboxed = crop_box_and_resize("assets/lena.png", &bbox, BoxUnits::Normalized,
+                                              0.0, ResizeMode::Letterbox, 32, 32);
+        assert!(letterboxed.len() == 32*32*3, "letterboxed was {:?}", letterboxed.len());
+        assert!(letterboxed[..8*32*3].iter().all(|&p| p == 0));
+        assert!(letterboxed[8*32*3..24*32*3].iter().any(|&p| p != 0));
+    }
+
+    #[test]
+    fn test_rotated_crop() {
+        // an unrotated box matches a plain crop of the same region
+        let matrix = ::warp::rotated_box_affine((256.0, 256.0), (64.0, 64.0), 0.0, (64, 64));
+        let window = affine_crop("assets/lena_gray.png", &matrix, BorderMode::Constant, 0.0, 64, 64);
+        let mut img = image::open(&Path::new("assets/lena_gray.png")).unwrap();
+        assert!(window == img.crop(224, 224, 64, 64).raw_pixels());
+
+        // a rotated box hanging off the corner picks up the fill value
+        let matrix = ::warp::rotated_box_affine((0.0, 0.0), (64.0, 64.0), 45.0, (32, 32));
+        let window = affine_crop("assets/lena_gray.png", &matrix, BorderMode::Constant, 7.0, 32, 32);
+        assert!(window.len() == 32*32 && window[0] == 7);
+    }
+
+    #[test]
+    fn test_perspective_crop() {
+        // an axis-aligned quad matches a plain crop of the same region
+        let corners = [(100.0, 50.0), (164.0, 50.0), (164.0, 114.0), (100.0, 114.0)];
+        let homography = ::warp::quad_homography(&corners, (64, 64)).unwrap();
+        let window = perspective_crop("assets/lena.png", &homography, BorderMode::Replicate, 0.0, 64, 64);
+        let mut img = image::open(&Path::new("assets/lena.png")).unwrap();
+        assert!(window == img.crop(100, 50, 64, 64).raw_pixels());
+    }
+
+    #[test]
+    fn test_random_crop_is_seeded() {
+        let sampler = CropSampler::RandomResized { scale: (0.08, 1.0), ratio: (0.75, 4.0 / 3.0) };
+        let (first, first_box) = random_crop_and_resize("assets/lena.png", &sampler,
+                                                        &mut Rng::for_item(7, 0), 32, 32);
+        let (second, second_box) = random_crop_and_resize("assets/lena.png", &sampler,
+                                                          &mut Rng::for_item(7, 0), 32, 32);
+        assert!(first.len() == 32*32*3 && first == second && first_box == second_box);
+        assert!(first_box.x0 >= 0.0 && first_box.x1 <= 1.0 && first_box.y0 >= 0.0 && first_box.y1 <= 1.0);
+    }
+
+    #[test]
+    fn test_geometric_augment() {
+        // a 2x2 gray image flipped horizontally and turned clockwise
+        let img = DynamicImage::ImageLuma8(ImageBuffer::from_raw(2, 2, vec![1, 2, 3, 4]).unwrap());
+        let aug = GeometricAug { hflip: true, vflip: false, rot90: 1 };
+        assert!(apply_geometric(img, &aug).raw_pixels() == vec![4, 2, 3, 1]);
+
+        // the augmented crop is a permutation of the plain one
+        let plain = crop_and_resize("assets/lena.png", 0.25, 0.5, 0.5, 0.25, 32, 32).raw_pixels();
+        let augment = Augment { geometric: GeometricAug { hflip: true, vflip: true, rot90: 2 },
+                               ..Default::default() };
+        let augmented = crop_and_augment("assets/lena.png", 0.25, 0.5, 0.5, 0.25, 32, 32, &augment);
+        assert!(augmented == plain, "a double flip and a half turn should cancel");
+    }
+
+    #[test]
+    fn test_paired_crop() {
+        // an RGB image and a gray "mask" of the same size share the crop region
+        let windows = paired_crop_and_resize(&["assets/lena.png", "assets/lena_gray.png"],
+                                             0.25, 0.5, 0.5, 0.25,
+                                             &[ResizeFilter::Bilinear, ResizeFilter::Nearest], 32, 32);
+        assert!(windows[0].len() == 32*32*3 && windows[1].len() == 32*32, "lens were {:?}",
+                windows.iter().map(|w| w.len()).collect::<Vec<usize>>());
+
+        // nearest neighbour only ever copies source values
+        let mask = paired_crop_and_resize(&["assets/lena_gray.png"], 0.25, 0.5, 0.5, 0.25,
+                                          &[ResizeFilter::Nearest], 128, 128);
+        let source = crop_and_resize("assets/lena_gray.png", 0.25, 0.5, 0.5, 0.25, 128, 128).raw_pixels();
+        assert!(mask[0] == source);
+    }
+
+    #[test]
+    fn test_tiled_crop() {
+        // a pyramid written with the full level equal to lena_gray and a half level
+        let full = image::open(&Path::new("assets/lena_gray.png")).unwrap();
+        let half = full.resize_exact(256, 256, FilterType::Nearest);
+        let path = "assets/test_lena_pyramid.tif";
+        tiled::write_test_tiff(path, &[(512, 512, &full.raw_pixels()), (256, 256, &half.raw_pixels())], 64);
+
+        // a large crop reads the half level, a small one the full level
+        let coarse = tiled_crop_and_resize(path, 0.5, 0.25, 0.25, 1.0, 64, 64);
+        let expected = half.clone().crop(64, 64, 128, 128).resize_exact(64, 64, FilterType::Nearest);
+        assert!(coarse == expected.raw_pixels());
+
+        let fine = tiled_crop_and_resize(path, 0.25, 0.5, 0.5, 1.0, 128, 128);
+        assert!(fine == crop_and_resize("assets/lena_gray.png", 0.25, 0.5, 0.5, 1.0, 128, 128).raw_pixels());
+        ::std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_crop_and_save() {
+        let paths = vec![CString::new("assets/lena.png").unwrap(), CString::new("assets/lena_gray.png").unwrap(),
+                         CString::new("assets/missing.png").unwrap()];
+        let path_ptrs: Vec<*const c_char> = paths.iter().map(|p| p.as_ptr()).collect();
+        let (scale, x, y) = (vec![0.5f32, 0.25, 0.5], vec![0.1f32, 0.5, 0.5], vec![0.2f32, 0.5, 0.5]);
+        let mut written = vec![0u8; 3];
+        let job = |template: &str, format, written_ptr| super::super::SaveJob {
+            image_paths_ptr: path_ptrs.as_ptr(),
+            scale_ptr: scale.as_ptr(),
+            x_ptr: x.as_ptr(),
+            y_ptr: y.as_ptr(),
+            window_size: 32,
+            max_img_percent: 1.0,
+            filter: None,
+            template: template.to_string(),
+            format: format,
+            written_ptr: written_ptr,
+            length: 3
+        };
+
+        // png is lossless, so the files hold exactly the in-memory crops; the
+        // missing image is flagged instead of failing the batch
+        assert!(execute_save_job(&job("assets/test_saved/{stem}_{index}", SaveFormat::Png, written.as_mut_ptr())) == 2);
+        assert!(written == vec![1, 1, 0]);
+        let saved = image::open("assets/test_saved/lena_gray_1.png").unwrap();
+        assert!(saved.raw_pixels() == crop_and_resize("assets/lena_gray.png", 0.25, 0.5, 0.5, 1.0, 32, 32).raw_pixels());
+
+        assert!(execute_save_job(&job("assets/test_saved/{index}.jpeg", SaveFormat::Jpeg(90), ptr::null_mut())) == 2);
+        assert!(image::open("assets/test_saved/0.jpeg").unwrap().dimensions() == (32, 32));
+        ::std::fs::remove_dir_all("assets/test_saved").unwrap();
+    }
 }
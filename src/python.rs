@@ -0,0 +1,223 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+
+use pyo3::prelude::*;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use numpy::ndarray::Array4;
+use numpy::{IntoPyArray, PyReadonlyArray1, PyReadonlyArray2};
+
+
+// python extension (the `python` feature): a Cropper owning a crop manager
+// whose methods take lists of paths and numpy arrays, check them and return
+// numpy arrays. the crops run with the GIL released and call the backends
+// directly rather than through the C exports, so that a panic (eg: corrupt
+// pixel data the header probe can't see) raises instead of aborting python
+
+
+fn c_paths(paths: &[String]) -> PyResult<Vec<CString>> {
+    paths.iter().map(|p| CString::new(p.clone())
+                     .map_err(|_| PyValueError::new_err(format!("path {:?} contains a nul byte", p))))
+        .collect()
+}
+
+
+fn values<'a>(name: &str, array: &'a PyReadonlyArray1<f32>, length: usize) -> PyResult<&'a [f32]> {
+    let slice = array.as_slice().map_err(|_| PyValueError::new_err(format!("{} must be contiguous", name)))?;
+    match slice.len() == length {
+        true  => Ok(slice),
+        false => Err(PyValueError::new_err(format!("{} has {} values for {} paths", name, slice.len(), length)))
+    }
+}
+
+
+fn filter_code(filter: &str) -> PyResult<u32> {
+    match filter {
+        "nearest" => Ok(0),
+        "bilinear" => Ok(1),
+        "bicubic" => Ok(2),
+        "lanczos3" => Ok(3),
+        _ => Err(PyValueError::new_err(format!(
+            "unknown filter {:?}, expected nearest, bilinear, bicubic or lanczos3", filter)))
+    }
+}
+
+
+fn crop_result<F: FnOnce()>(crop: F) -> PyResult<()> {
+    panic::catch_unwind(AssertUnwindSafe(crop))
+        .map_err(|_| PyRuntimeError::new_err("cropping the batch failed, see the panic message above"))
+}
+
+
+#[pyclass]
+pub struct Cropper {
+    // the crop manager of initialize, kept as an address so the class is Send
+    crop_manager: usize,
+    #[pyo3(get)]
+    window_size: u32,
+    #[pyo3(get)]
+    chans: u32,
+    #[pyo3(get)]
+    max_img_percent: f32
+}
+
+
+impl Cropper {
+    fn check_images(&self, py: Python, paths: &[CString]) -> PyResult<()> {
+        // the crops panic on unreadable images, raise with the path instead. the probe
+        // only reads headers, so it costs little next to the crop itself
+        let cm = self.crop_manager;
+        let (ok, shapes) = py.allow_threads(|| {
+            let ptrs: Vec<*const c_char> = paths.iter().map(|p| p.as_ptr()).collect();
+            let mut ok = vec![0u8; paths.len()];
+            let mut shapes = vec![0u32; 3 * paths.len()];
+            ::probe_images(cm as *const c_void, ptrs.as_ptr(), shapes.as_mut_ptr(), ok.as_mut_ptr(), paths.len());
+            (ok, shapes)
+        });
+
+        for (i, path) in paths.iter().enumerate() {
+            if ok[i] == 0 {
+                return Err(PyValueError::new_err(format!("could not decode {:?}", path)));
+            }
+            if shapes[3 * i + 2] != self.chans {
+                return Err(PyValueError::new_err(format!("{:?} has {} channels, expected {}",
+                                                         path, shapes[3 * i + 2], self.chans)));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[pymethods]
+impl Cropper {
+    #[new]
+    #[pyo3(signature = (window_size, chans=3, num_threads=0, use_vips=false, max_img_percent=1.0))]
+    fn new(window_size: u32, chans: u32, num_threads: u64, use_vips: bool, max_img_percent: f32) -> PyResult<Self> {
+        if window_size == 0 || chans == 0 || chans > 4 {
+            return Err(PyValueError::new_err("window_size must be positive and chans in [1, 4]"));
+        }
+        Ok(Cropper {
+            crop_manager: ::initialize(num_threads, use_vips) as usize,
+            window_size: window_size,
+            chans: chans,
+            max_img_percent: max_img_percent
+        })
+    }
+
+    #[pyo3(signature = (paths, scale, x, y, filter="nearest", dtype="uint8"))]
+    fn crop(&self, py: Python, paths: Vec<String>, scale: PyReadonlyArray1<f32>, x: PyReadonlyArray1<f32>,
+            y: PyReadonlyArray1<f32>, filter: &str, dtype: &str) -> PyResult<PyObject>
+    {
+        // [N, window_size, window_size, chans] crops of the normalized (scale, x, y)
+        // of parallel_crop_and_resize, resized with filter, as uint8 or float32
+        let length = paths.len();
+        let (scale, x, y) = (values("scale", &scale, length)?, values("x", &x, length)?, values("y", &y, length)?);
+        if x.iter().chain(y).any(|&v| v < 0.0 || v > 1.0) {
+            return Err(PyValueError::new_err("x and y must be in [0, 1]"));
+        }
+        let filter = filter_code(filter)?;
+        let float = match dtype {
+            "uint8" => false,
+            "float32" => true,
+            _ => return Err(PyValueError::new_err(format!("unknown dtype {:?}, expected uint8 or float32", dtype)))
+        };
+        let c_paths = c_paths(&paths)?;
+        self.check_images(py, &c_paths)?;
+
+        let (cm, window_size, chans, max_img_percent) = (self.crop_manager, self.window_size, self.chans,
+                                                         self.max_img_percent);
+        let shape = (length, window_size as usize, window_size as usize, chans as usize);
+        let size = shape.0 * shape.1 * shape.2 * shape.3;
+        let run = |ret: *mut c_void| -> PyResult<()> {
+            let ptrs: Vec<*const c_char> = c_paths.iter().map(|p| p.as_ptr()).collect();
+            let job = ::PairedJob {
+                streams: vec![::paired::Stream {
+                    image_paths_ptr: ptrs.as_ptr(),
+                    return_ptr: ret,
+                    chans: chans,
+                    filter: ::paired::ResizeFilter::from_code(filter),
+                    dtype: ::paired::StreamDtype::from_code(float as u32)
+                }],
+                scale_ptr: scale.as_ptr(),
+                x_ptr: x.as_ptr(),
+                y_ptr: y.as_ptr(),
+                window_size: window_size,
+                max_img_percent: max_img_percent,
+                length: length
+            };
+            crop_result(|| ::run_paired_job(::crop_manager(cm as *const c_void), &job))
+        };
+
+        // the buffers are addresses inside the closure so it stays Send
+        match float {
+            true  => {
+                let mut crops = vec![0f32; size];
+                let ret = crops.as_mut_ptr() as usize;
+                py.allow_threads(|| run(ret as *mut c_void))?;
+                Ok(Array4::from_shape_vec(shape, crops).unwrap().into_pyarray(py).to_object(py))
+            },
+            false => {
+                let mut crops = vec![0u8; size];
+                let ret = crops.as_mut_ptr() as usize;
+                py.allow_threads(|| run(ret as *mut c_void))?;
+                Ok(Array4::from_shape_vec(shape, crops).unwrap().into_pyarray(py).to_object(py))
+            }
+        }
+    }
+
+    #[pyo3(signature = (paths, boxes, normalized=false, padding=0.0, letterbox=false))]
+    fn crop_boxes(&self, py: Python, paths: Vec<String>, boxes: PyReadonlyArray2<f32>, normalized: bool,
+                  padding: f32, letterbox: bool) -> PyResult<PyObject>
+    {
+        // [N, window_size, window_size, chans] uint8 crops of the [N, 4] (x0, y0, x1, y1) boxes
+        let length = paths.len();
+        if boxes.shape() != [length, 4] {
+            return Err(PyValueError::new_err(format!("boxes has shape {:?}, expected [{}, 4]", boxes.shape(), length)));
+        }
+        let boxes = boxes.as_slice().map_err(|_| PyValueError::new_err("boxes must be contiguous"))?;
+        let c_paths = c_paths(&paths)?;
+        self.check_images(py, &c_paths)?;
+
+        let (cm, window_size, chans) = (self.crop_manager, self.window_size, self.chans);
+        let shape = (length, window_size as usize, window_size as usize, chans as usize);
+        let mut crops = vec![0u8; shape.0 * shape.1 * shape.2 * shape.3];
+        let ret = crops.as_mut_ptr() as usize;
+        py.allow_threads(|| {
+            let ptrs: Vec<*const c_char> = c_paths.iter().map(|p| p.as_ptr()).collect();
+            let job = ::BoxJob {
+                image_paths_ptr: ptrs.as_ptr(),
+                return_ptr: ret as *mut u8,
+                boxes_ptr: boxes.as_ptr(),
+                units: match normalized {
+                    true  => ::boxes::BoxUnits::Normalized,
+                    false => ::boxes::BoxUnits::Pixel
+                },
+                padding: padding,
+                mode: match letterbox {
+                    true  => ::boxes::ResizeMode::Letterbox,
+                    false => ::boxes::ResizeMode::Stretch
+                },
+                window_size: window_size,
+                chans: chans,
+                length: length
+            };
+            crop_result(|| ::run_box_job(::crop_manager(cm as *const c_void), &job))
+        })?;
+        Ok(Array4::from_shape_vec(shape, crops).unwrap().into_pyarray(py).to_object(py))
+    }
+}
+
+
+impl Drop for Cropper {
+    fn drop(&mut self) {
+        ::destroy(self.crop_manager as *mut c_void);
+    }
+}
+
+
+#[pymodule]
+fn parallel_image_crop(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Cropper>()?;
+    Ok(())
+}
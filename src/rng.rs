@@ -0,0 +1,129 @@
+// small deterministic generator for in-crate augmentation sampling.
+//
+// every batch item gets its own stream derived from (seed, index) so the
+// sampled values do not depend on how rayon schedules the items. every
+// sampler salts the seed with its own constant, otherwise with one seed per
+// batch their first draws coincide, eg: the flip decision would also decide
+// whether the brightness lands low
+
+
+pub const GEOMETRIC: u64 = 0x5A17_C0DE_0000_0001;
+pub const PHOTOMETRIC: u64 = 0x5A17_C0DE_0000_0002;
+pub const ERASE: u64 = 0x5A17_C0DE_0000_0003;
+pub const RANDOM_CROP: u64 = 0x5A17_C0DE_0000_0004;
+pub const MIXING: u64 = 0x5A17_C0DE_0000_0005;
+
+
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64
+}
+
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn for_item(seed: u64, index: usize) -> Rng {
+        // decorrelate neighbouring items by mixing the index through one step
+        let mut base = Rng::new(seed ^ (index as u64).wrapping_mul(0xD6E8FEB86659FD93));
+        Rng::new(base.next_u64())
+    }
+
+    pub fn for_op(salt: u64, seed: u64, index: usize) -> Rng {
+        // the stream of one sampler, salt is one of the constants above
+        Rng::for_item(seed ^ salt, index)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        // splitmix64
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        // uniform in [0, 1) from the top 24 bits
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn uniform(&mut self, low: f32, high: f32) -> f32 {
+        low + (high - low) * self.next_f32()
+    }
+
+    pub fn below(&mut self, bound: u32) -> u32 {
+        // uniform integer in [0, bound)
+        assert!(bound > 0, "can't sample below zero");
+        ((self.next_u64() >> 32) * bound as u64 >> 32) as u32
+    }
+
+    pub fn chance(&mut self, prob: f32) -> bool {
+        self.next_f32() < prob
+    }
+
+    pub fn normal(&mut self) -> f32 {
+        // box-muller, using (0, 1] for the log argument
+        let u1 = 1.0 - self.next_f32();
+        let u2 = self.next_f32();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * ::std::f32::consts::PI * u2).cos()
+    }
+
+    pub fn gamma(&mut self, shape: f32) -> f32 {
+        // marsaglia-tsang, boosting shapes below one
+        assert!(shape > 0.0, "gamma shape must be positive");
+        if shape < 1.0 {
+            let u = 1.0 - self.next_f32();
+            return self.gamma(shape + 1.0) * u.powf(1.0 / shape);
+        }
+
+        let d = shape - 1.0 / 3.0;
+        let c = 1.0 / (9.0 * d).sqrt();
+        loop {
+            let x = self.normal();
+            let v = (1.0 + c * x).powi(3);
+            if v <= 0.0 {
+                continue;
+            }
+
+            let u = 1.0 - self.next_f32();
+            if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+                return d * v;
+            }
+        }
+    }
+
+    pub fn beta(&mut self, a: f32, b: f32) -> f32 {
+        let x = self.gamma(a);
+        let y = self.gamma(b);
+        x / (x + y)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streams_are_reproducible() {
+        let a: Vec<u64> = (0..4).map(|_| Rng::for_item(42, 3).next_u64()).collect();
+        assert!(a.iter().all(|&v| v == a[0]));
+        assert!(Rng::for_item(42, 3).next_u64() != Rng::for_item(42, 4).next_u64());
+        assert!(Rng::for_item(42, 3).next_u64() != Rng::for_item(43, 3).next_u64());
+
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            let f = rng.uniform(2.0, 3.0);
+            assert!(f >= 2.0 && f < 3.0);
+            assert!(rng.below(5) < 5);
+        }
+
+        // beta(a, a) is symmetric around a half
+        let mean = (0..4000).map(|_| rng.beta(0.4, 0.4)).sum::<f32>() / 4000.0;
+        assert!((mean - 0.5).abs() < 0.03, "mean was {:?}", mean);
+        assert!((0..100).map(|_| rng.beta(0.2, 0.2)).all(|v| v >= 0.0 && v <= 1.0));
+    }
+}
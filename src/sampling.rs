@@ -0,0 +1,113 @@
+use boxes::CropBox;
+use rng::Rng;
+
+
+// crop parameter sampling done inside the crate so that augmentation is
+// reproducible from a single seed per call
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropSampler {
+    // torchvision's RandomResizedCrop: area fraction in scale, aspect ratio (w / h) in ratio
+    RandomResized { scale: (f32, f32), ratio: (f32, f32) },
+
+    // square-relative crop of side scale * image size (the parallel_crop_and_resize
+    // convention) with the side uniform in scale and a uniform position
+    RandomPosition { scale: (f32, f32) },
+
+    // deterministic centered crop of side scale * image size, for evaluation
+    Center { scale: f32 }
+}
+
+
+fn center_box(img_size: (u32, u32), width: f32, height: f32) -> CropBox {
+    let x0 = ((img_size.0 as f32 - width) / 2.0).round();
+    let y0 = ((img_size.1 as f32 - height) / 2.0).round();
+    CropBox::new(x0, y0, x0 + width, y0 + height)
+}
+
+
+fn random_resized_box(img_size: (u32, u32), scale: (f32, f32), ratio: (f32, f32), rng: &mut Rng) -> CropBox {
+    let (width, height) = (img_size.0 as f32, img_size.1 as f32);
+    let area = width * height;
+    let log_ratio = (ratio.0.ln(), ratio.1.ln());
+    for _ in 0..10 {
+        let target_area = area * rng.uniform(scale.0, scale.1);
+        let aspect = rng.uniform(log_ratio.0, log_ratio.1).exp();
+        let w = (target_area * aspect).sqrt().round();
+        let h = (target_area / aspect).sqrt().round();
+        if w > 0.0 && w <= width && h > 0.0 && h <= height {
+            let x0 = rng.below((width - w) as u32 + 1) as f32;
+            let y0 = rng.below((height - h) as u32 + 1) as f32;
+            return CropBox::new(x0, y0, x0 + w, y0 + h);
+        }
+    }
+
+    // fallback to a central crop clamped to the ratio range
+    let in_ratio = width / height;
+    if in_ratio < ratio.0 {
+        center_box(img_size, width, (width / ratio.0).round())
+    } else if in_ratio > ratio.1 {
+        center_box(img_size, (height * ratio.1).round(), height)
+    } else {
+        center_box(img_size, width, height)
+    }
+}
+
+
+pub fn sample_box(sampler: &CropSampler, img_size: (u32, u32), rng: &mut Rng) -> CropBox
+{
+    // returns a box in pixel co-ordinates of the image
+    match *sampler {
+        CropSampler::RandomResized { scale, ratio } => random_resized_box(img_size, scale, ratio, rng),
+        CropSampler::RandomPosition { scale } => {
+            let side = rng.uniform(scale.0, scale.1);
+            let (w, h) = ((img_size.0 as f32 * side).floor().max(1.0),
+                          (img_size.1 as f32 * side).floor().max(1.0));
+            let x0 = rng.uniform(0.0, img_size.0 as f32 - w).floor();
+            let y0 = rng.uniform(0.0, img_size.1 as f32 - h).floor();
+            CropBox::new(x0, y0, x0 + w, y0 + h)
+        },
+        CropSampler::Center { scale } => {
+            center_box(img_size, (img_size.0 as f32 * scale).round().max(1.0).min(img_size.0 as f32),
+                       (img_size.1 as f32 * scale).round().max(1.0).min(img_size.1 as f32))
+        }
+    }
+}
+
+
+pub fn normalize_box(bbox: &CropBox, img_size: (u32, u32)) -> CropBox {
+    let (w, h) = (img_size.0 as f32, img_size.1 as f32);
+    CropBox::new(bbox.x0 / w, bbox.y0 / h, bbox.x1 / w, bbox.y1 / h)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_resized_box() {
+        let sampler = CropSampler::RandomResized { scale: (0.08, 1.0), ratio: (3.0 / 4.0, 4.0 / 3.0) };
+        for i in 0..100 {
+            let bbox = sample_box(&sampler, (640, 480), &mut Rng::for_item(0, i));
+            assert!(bbox.x0 >= 0.0 && bbox.y0 >= 0.0 && bbox.x1 <= 640.0 && bbox.y1 <= 480.0, "{:?}", bbox);
+            let area = (bbox.x1 - bbox.x0) * (bbox.y1 - bbox.y0) / (640.0 * 480.0);
+            assert!(area > 0.07 && area <= 1.0, "area was {:?}", area);
+
+            // the same seed and index give the same box
+            assert!(bbox == sample_box(&sampler, (640, 480), &mut Rng::for_item(0, i)));
+        }
+    }
+
+    #[test]
+    fn test_center_and_position_boxes() {
+        let mut rng = Rng::new(1);
+        let center = sample_box(&CropSampler::Center { scale: 0.5 }, (100, 50), &mut rng);
+        assert!(center == CropBox::new(25.0, 13.0, 75.0, 38.0), "center was {:?}", center);
+
+        let bbox = sample_box(&CropSampler::RandomPosition { scale: (0.25, 0.25) }, (100, 40), &mut rng);
+        assert!(bbox.x1 - bbox.x0 == 25.0 && bbox.y1 - bbox.y0 == 10.0);
+        assert!(bbox.x1 <= 100.0 && bbox.y1 <= 40.0);
+    }
+}
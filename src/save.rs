@@ -0,0 +1,156 @@
+use std::{slice, str};
+use std::ffi::CStr;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use rayon::prelude::*;
+
+use image::{ColorType, DynamicImage, GenericImage, ImageResult};
+use image::jpeg::JPEGEncoder;
+use image::png::PNGEncoder;
+
+use codecs;
+
+
+// crops written to disk instead of into a return buffer, eg: for dataset
+// preprocessing. the output path of item i comes from a template where
+// {index} is i and {stem} the source file name without its extension
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SaveFormat {
+    Png,
+    Jpeg(u8),  // quality in [1, 100]
+    WebP(u8)
+}
+
+
+impl SaveFormat {
+    pub fn parse(code: u32, quality: u32) -> Option<SaveFormat> {
+        let quality = quality.max(1).min(100) as u8;
+        match code {
+            0 => Some(SaveFormat::Png),
+            1 => Some(SaveFormat::Jpeg(quality)),
+            2 => Some(SaveFormat::WebP(quality)),
+            _ => None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            SaveFormat::Png => "png",
+            SaveFormat::Jpeg(_) => "jpg",
+            SaveFormat::WebP(_) => "webp"
+        }
+    }
+
+    pub fn vips_options(&self) -> String {
+        // libvips reads saver options from the file name, eg: "out.jpg[Q=90]"
+        match *self {
+            SaveFormat::Png => "".to_string(),
+            SaveFormat::Jpeg(q) | SaveFormat::WebP(q) => format!("[Q={}]", q)
+        }
+    }
+}
+
+
+pub fn output_path(template: &str, source: &str, index: usize, format: SaveFormat) -> String {
+    // fills in the template and makes sure the extension names the format,
+    // which is what libvips picks its saver from
+    let stem = Path::new(source).file_stem().map_or("".to_string(), |s| s.to_string_lossy().into_owned());
+    let path = template.replace("{index}", &index.to_string()).replace("{stem}", &stem);
+    let ext = Path::new(&path).extension().map(|s| s.to_string_lossy().to_ascii_lowercase());
+    match (ext.as_ref().map(|s| &s[..]), format) {
+        (Some("jpeg"), SaveFormat::Jpeg(_)) => path,
+        (Some(ext), _) if ext == format.extension() => path,
+        _ => format!("{}.{}", path, format.extension())
+    }
+}
+
+
+pub fn encode(img: &DynamicImage, path: &str, format: SaveFormat) -> ImageResult<()> {
+    let (width, height) = img.dimensions();
+    match format {
+        SaveFormat::Png => {
+            let fout = BufWriter::new(File::create(path)?);
+            PNGEncoder::new(fout).encode(&img.raw_pixels(), width, height, img.color())?;
+        },
+        SaveFormat::Jpeg(quality) => {
+            // jpeg has no alpha channel
+            let img = match img.color() {
+                ColorType::Gray(8) | ColorType::RGB(8) => img.clone(),
+                _ => DynamicImage::ImageRgb8(img.to_rgb())
+            };
+            let mut fout = BufWriter::new(File::create(path)?);
+            JPEGEncoder::new_with_quality(&mut fout, quality).encode(&img.raw_pixels(), width, height, img.color())?;
+        },
+        SaveFormat::WebP(quality) => {
+            let buf = codecs::encode_webp(img, quality)?;
+            fs::write(path, buf)?;
+        }
+    };
+    Ok(())
+}
+
+
+pub fn run<F>(job: &super::SaveJob, save_one: F) -> usize
+    where F: Fn(&str, f32, f32, f32, &str) -> bool + Sync
+{
+    // crops and saves every item with save_one(path, scale, x, y, out_path),
+    // flags the written items in written_ptr (when given) and counts them. an
+    // item whose crop panics, eg: an unreadable image, is flagged as not written
+    assert!(!job.scale_ptr.is_null(), "can't operate over null scale vector");
+    assert!(!job.x_ptr.is_null(), "can't operate over null x vector");
+    assert!(!job.y_ptr.is_null(), "can't operate over null y vector");
+    assert!(!job.image_paths_ptr.is_null(), "can't operate over null list of image paths");
+
+    let image_paths_vec: Vec<&str> = unsafe { slice::from_raw_parts(job.image_paths_ptr, job.length as usize) }.iter()
+        .map(|&p| unsafe { CStr::from_ptr(p) })
+        .map(|cs| str::from_utf8(cs.to_bytes()).unwrap())
+        .collect();
+    let scale_values = unsafe { slice::from_raw_parts(job.scale_ptr, job.length as usize) };
+    let x_values = unsafe { slice::from_raw_parts(job.x_ptr, job.length as usize) };
+    let y_values = unsafe { slice::from_raw_parts(job.y_ptr, job.length as usize) };
+
+    let mut written = vec![];
+    image_paths_vec.into_par_iter().enumerate().zip(scale_values)
+        .zip(x_values).zip(y_values)
+        .map(|((((index, path), scale), x), y)| {
+            let out_path = output_path(&job.template, path, index, job.format);
+            if let Some(parent) = Path::new(&out_path).parent() {
+                if fs::create_dir_all(parent).is_err() {
+                    return false;
+                }
+            }
+            panic::catch_unwind(AssertUnwindSafe(|| save_one(path, *scale, *x, *y, &out_path)))
+                .unwrap_or(false)
+        }).collect_into_vec(&mut written);
+
+    if !job.written_ptr.is_null() {
+        let flags = unsafe { slice::from_raw_parts_mut(job.written_ptr, job.length as usize) };
+        for (flag, ok) in flags.iter_mut().zip(&written) {
+            *flag = *ok as u8;
+        }
+    }
+    written.iter().filter(|&&ok| ok).count()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_path() {
+        let png = SaveFormat::parse(0, 90).unwrap();
+        let jpeg = SaveFormat::parse(1, 250).unwrap();
+        assert!(jpeg == SaveFormat::Jpeg(100));
+        assert!(output_path("out/{stem}_{index}", "data/cat.JPG", 3, png) == "out/cat_3.png");
+        assert!(output_path("out/{index}.jpeg", "a.png", 0, jpeg) == "out/0.jpeg");
+        assert!(output_path("out/{index}.png", "a.png", 1, jpeg) == "out/1.png.jpg");
+        assert!(jpeg.vips_options() == "[Q=100]" && png.vips_options() == "");
+        assert!(SaveFormat::parse(3, 90).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use image;
+use image::ImageFormat;
+
+use codecs::NativeFormat;
+
+
+// where a file is decoded: the image crate or one of the native codecs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Image(ImageFormat),
+    Native(NativeFormat)
+}
+
+
+#[derive(Debug)]
+pub enum FormatError {
+    Io(io::Error),
+    // neither the leading bytes nor the extension name a supported format
+    Unrecognized { path: String, extension: String }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::Io(ref err) => write!(f, "could not read image header: {}", err),
+            FormatError::Unrecognized { ref path, ref extension } => write!(
+                f, "{:?} matches no supported image format (extension {:?})", path, extension)
+        }
+    }
+}
+
+impl Error for FormatError {
+    fn description(&self) -> &str {
+        match *self {
+            FormatError::Io(_) => "could not read image header",
+            FormatError::Unrecognized { .. } => "unrecognized image format"
+        }
+    }
+}
+
+impl From<io::Error> for FormatError {
+    fn from(err: io::Error) -> FormatError {
+        FormatError::Io(err)
+    }
+}
+
+impl From<FormatError> for image::ImageError {
+    fn from(err: FormatError) -> image::ImageError {
+        match err {
+            FormatError::Io(err) => image::ImageError::IoError(err),
+            err => image::ImageError::UnsupportedError(err.to_string())
+        }
+    }
+}
+
+
+pub fn sniff(head: &[u8]) -> Option<Format> {
+    // the format named by a file's leading bytes; tga has no signature
+    let starts = |magic: &[u8]| head.starts_with(magic);
+    let image = |format| Some(Format::Image(format));
+    if starts(b"\xff\xd8\xff") {
+        image(ImageFormat::JPEG)
+    } else if starts(b"\x89PNG\r\n\x1a\n") {
+        image(ImageFormat::PNG)
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        image(ImageFormat::GIF)
+    } else if starts(b"II*\0") || starts(b"MM\0*") {
+        image(ImageFormat::TIFF)
+    } else if starts(b"BM") {
+        image(ImageFormat::BMP)
+    } else if starts(b"\0\0\x01\0") {
+        image(ImageFormat::ICO)
+    } else if starts(b"#?RADIANCE") || starts(b"#?RGBE") {
+        image(ImageFormat::HDR)
+    } else if head.len() >= 3 && head[0] == b'P' && head[1] >= b'1' && head[1] <= b'7'
+        && (head[2] as char).is_whitespace() {
+        image(ImageFormat::PNM)
+    } else if head.len() >= 12 && starts(b"RIFF") && &head[8..12] == b"WEBP" {
+        Some(Format::Native(NativeFormat::WebP))
+    } else if head.len() >= 12 && &head[4..8] == b"ftyp" && (&head[8..12] == b"avif" || &head[8..12] == b"avis") {
+        Some(Format::Native(NativeFormat::Avif))
+    } else {
+        None
+    }
+}
+
+
+pub fn extension_format(path: &Path) -> Option<Format> {
+    let ext = path.extension().and_then(|s| s.to_str())
+                  .map_or("".to_string(), |s| s.to_ascii_lowercase());
+
+    let image = |format| Some(Format::Image(format));
+    match &ext[..] {
+        "jpg" |
+        "jpeg" => image(ImageFormat::JPEG),
+        "png"  => image(ImageFormat::PNG),
+        "gif"  => image(ImageFormat::GIF),
+        "webp" => Some(Format::Native(NativeFormat::WebP)),
+        "avif" => Some(Format::Native(NativeFormat::Avif)),
+        "tif" |
+        "tiff" => image(ImageFormat::TIFF),
+        "tga" =>  image(ImageFormat::TGA),
+        "bmp" =>  image(ImageFormat::BMP),
+        "ico" =>  image(ImageFormat::ICO),
+        "hdr" =>  image(ImageFormat::HDR),
+        "pbm" |
+        "pam" |
+        "ppm" |
+        "pgm" =>  image(ImageFormat::PNM),
+        _ => None
+    }
+}
+
+
+pub fn detect_format(path: &Path) -> Result<Format, FormatError> {
+    // trust the content over the name: scraped data is often mislabelled
+    let mut head = Vec::with_capacity(32);
+    File::open(path)?.take(32).read_to_end(&mut head)?;
+    sniff(&head).or_else(|| extension_format(path)).ok_or_else(|| FormatError::Unrecognized {
+        path: path.to_string_lossy().into_owned(),
+        extension: path.extension().map_or("".to_string(), |s| s.to_string_lossy().into_owned())
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_detect_format() {
+        assert!(sniff(b"\x89PNG\r\n\x1a\n....") == Some(Format::Image(ImageFormat::PNG)));
+        assert!(sniff(b"P5\n512 512\n255\n") == Some(Format::Image(ImageFormat::PNM)));
+        assert!(sniff(b"Plain text") == None);
+        assert!(detect_format(Path::new("assets/solid.avif")).unwrap() == Format::Native(NativeFormat::Avif));
+
+        // content wins over a wrong extension and covers a missing one
+        fs::copy("assets/lena_gray.png", "assets/test_mislabelled.jpg").unwrap();
+        fs::copy("assets/lena_gray.png", "assets/test_no_extension").unwrap();
+        assert!(detect_format(Path::new("assets/test_mislabelled.jpg")).unwrap() == Format::Image(ImageFormat::PNG));
+        assert!(detect_format(Path::new("assets/test_no_extension")).unwrap() == Format::Image(ImageFormat::PNG));
+        fs::remove_file("assets/test_mislabelled.jpg").unwrap();
+        fs::remove_file("assets/test_no_extension").unwrap();
+
+        // tga has no signature so its extension is the fallback
+        fs::write("assets/test_fallback.tga", b"\0\0\x02").unwrap();
+        fs::write("assets/test_unknown.dat", b"not an image").unwrap();
+        assert!(detect_format(Path::new("assets/test_fallback.tga")).unwrap() == Format::Image(ImageFormat::TGA));
+        match detect_format(Path::new("assets/test_unknown.dat")) {
+            Err(FormatError::Unrecognized { extension, .. }) => assert!(extension == "dat"),
+            other => panic!("expected an unrecognized format, got {:?}", other)
+        }
+        fs::remove_file("assets/test_fallback.tga").unwrap();
+        fs::remove_file("assets/test_unknown.dat").unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use std::{slice, ptr};
+use rayon::ThreadPool;
+use rayon::prelude::*;
+use libc::size_t;
+
+
+// reassembles per-patch outputs (eg: segmentation logits) into a full image,
+// blending the regions where several patches overlap
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Average,  // every covering patch contributes equally
+    Feather   // weight falls off linearly towards the patch border
+}
+
+
+// the pixel types a patch buffer can hold
+pub trait PatchPixel: Copy + Send + Sync {
+    fn to_f32(self) -> f32;
+    fn from_f32(val: f32) -> Self;
+}
+
+impl PatchPixel for u8 {
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f32(val: f32) -> u8 { val.round().max(0.0).min(255.0) as u8 }
+}
+
+impl PatchPixel for f32 {
+    fn to_f32(self) -> f32 { self }
+    fn from_f32(val: f32) -> f32 { val }
+}
+
+
+fn feather_weight(pos: u32, size: u32) -> f32 {
+    // linear ramp that is 1 at the border pixel and peaks at the patch center
+    (pos + 1).min(size - pos) as f32
+}
+
+
+fn blend_weight(px: u32, py: u32, patch_size: (u32, u32), mode: BlendMode) -> f32 {
+    match mode {
+        BlendMode::Average => 1.0,
+        BlendMode::Feather => feather_weight(px, patch_size.0) * feather_weight(py, patch_size.1)
+    }
+}
+
+
+pub fn assemble_patches<T: PatchPixel>(patches: &[&[T]], origins: &[(i32, i32)],
+                                       patch_size: (u32, u32), image_size: (u32, u32),
+                                       chans: u32, mode: BlendMode) -> Vec<T>
+{
+    // accepts a list of [patch_h, patch_w, chans] buffers and their top-left origin
+    // in the full image; origins can lie partially outside the image, in which
+    // case the patch is clipped. Pixels not covered by any patch are zero.
+    assert!(patches.len() == origins.len(), "number of patches [{:?}] != number of origins [{:?}]",
+            patches.len(), origins.len());
+    assert!(patch_size.0 > 0 && patch_size.1 > 0, "patch size must be non-zero");
+    let patch_len = (patch_size.0 * patch_size.1 * chans) as usize;
+    for patch in patches {
+        assert!(patch.len() == patch_len, "patch [{:?}] != patch_size [{:?}]",
+                patch.len(), patch_len);
+    }
+
+    // each output row is blended independently, so rows are spread over the pool
+    let row_len = (image_size.0 * chans) as usize;
+    let mut assembled = vec![T::from_f32(0.0); row_len * image_size.1 as usize];
+    if row_len == 0 {
+        return assembled;
+    }
+
+    assembled.par_chunks_mut(row_len).enumerate().for_each(|(y, row)| {
+        let y = y as i64;
+        let mut acc = vec![0f32; row_len];
+        let mut weights = vec![0f32; image_size.0 as usize];
+        for (patch, &(ox, oy)) in patches.iter().zip(origins) {
+            // skip the patches that do not cover this row
+            let py = y - oy as i64;
+            if py < 0 || py >= patch_size.1 as i64 {
+                continue;
+            }
+
+            // clip the patch columns to the image
+            let x_begin = (ox as i64).max(0);
+            let x_end = (ox as i64 + patch_size.0 as i64).min(image_size.0 as i64);
+            for x in x_begin..x_end {
+                let px = (x - ox as i64) as u32;
+                let weight = blend_weight(px, py as u32, patch_size, mode);
+                let src = ((py as u32 * patch_size.0 + px) * chans) as usize;
+                let dst = x as usize * chans as usize;
+                for c in 0..chans as usize {
+                    acc[dst + c] += weight * patch[src + c].to_f32();
+                }
+                weights[x as usize] += weight;
+            }
+        }
+
+        // normalize by the accumulated weight per pixel
+        for (x, &weight) in weights.iter().enumerate() {
+            if weight > 0.0 {
+                for c in 0..chans as usize {
+                    let idx = x * chans as usize + c;
+                    row[idx] = T::from_f32(acc[idx] / weight);
+                }
+            }
+        }
+    });
+
+    assembled
+}
+
+
+pub fn parallel_assemble_patches<T: PatchPixel>(threadpool: &ThreadPool,
+                                                patches_ptr: *const T,
+                                                x_ptr: *const i32,
+                                                y_ptr: *const i32,
+                                                patch_width: u32,
+                                                patch_height: u32,
+                                                chans: u32,
+                                                image_width: u32,
+                                                image_height: u32,
+                                                mode: BlendMode,
+                                                return_ptr: *mut T,
+                                                length: size_t)
+{
+    // accepts a contiguous [N, patch_h, patch_w, chans] array of patches, their
+    // (x, y) origins and writes the [image_h, image_w, chans] result to return_ptr
+    assert!(!patches_ptr.is_null(), "can't operate over null patch vector");
+    assert!(!x_ptr.is_null(), "can't operate over null x vector");
+    assert!(!y_ptr.is_null(), "can't operate over null y vector");
+    assert!(!return_ptr.is_null(), "can't operate over null result vector");
+
+    let patch_len = (patch_width * patch_height * chans) as usize;
+    let patch_values = unsafe { slice::from_raw_parts(patches_ptr, length as usize * patch_len) };
+    let x_values = unsafe { slice::from_raw_parts(x_ptr, length as usize) };
+    let y_values = unsafe { slice::from_raw_parts(y_ptr, length as usize) };
+
+    let patches: Vec<&[T]> = if patch_len == 0 { vec![&[]; length as usize] }
+                             else { patch_values.chunks(patch_len).collect() };
+    let origins: Vec<(i32, i32)> = x_values.iter().cloned().zip(y_values.iter().cloned()).collect();
+    let assembled = threadpool.install(|| {
+        assemble_patches(&patches, &origins,
+                         (patch_width, patch_height),
+                         (image_width, image_height),
+                         chans, mode)
+    });
+
+    // copy the buffer into the return array
+    unsafe { ptr::copy(assembled.as_ptr(), return_ptr, assembled.len()) };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_non_overlapping_patches_roundtrip() {
+        // four 2x2 gray patches tiling a 4x4 image should come back unchanged
+        let patches: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i * 10; 4]).collect();
+        let patch_refs: Vec<&[u8]> = patches.iter().map(|p| &p[..]).collect();
+        let origins = [(0, 0), (2, 0), (0, 2), (2, 2)];
+        let img = assemble_patches(&patch_refs, &origins, (2, 2), (4, 4), 1, BlendMode::Feather);
+        assert!(img == vec![0, 0, 10, 10,
+                            0, 0, 10, 10,
+                            20, 20, 30, 30,
+                            20, 20, 30, 30], "assembled was {:?}", img);
+    }
+
+    #[test]
+    fn test_overlapping_patches_blend() {
+        // two 3x1 patches overlapping in the middle column
+        let left = [0f32, 0.0, 0.0];
+        let right = [1f32, 1.0, 1.0];
+        let patches: Vec<&[f32]> = vec![&left, &right];
+        let origins = [(0, 0), (1, 0)];
+
+        let avg = assemble_patches(&patches, &origins, (3, 1), (4, 1), 1, BlendMode::Average);
+        assert!(avg == vec![0.0, 0.5, 0.5, 1.0], "average was {:?}", avg);
+
+        // feathering favours the patch whose center is closer
+        let feather = assemble_patches(&patches, &origins, (3, 1), (4, 1), 1, BlendMode::Feather);
+        assert!(feather[1] < 0.5 && feather[2] > 0.5, "feather was {:?}", feather);
+    }
+
+    #[test]
+    fn test_clipped_and_uncovered_patches() {
+        // a patch hanging off the top-left corner and an uncovered remainder
+        let patch = [5u8, 6, 7, 8];
+        let img = assemble_patches(&[&patch[..]], &[(-1, -1)], (2, 2), (2, 2), 1, BlendMode::Average);
+        assert!(img == vec![8, 0, 0, 0], "assembled was {:?}", img);
+    }
+}
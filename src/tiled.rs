@@ -0,0 +1,388 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::fs::File;
+use std::cmp;
+use std::collections::HashSet;
+use inflate;
+use lzw;
+use image::{ImageDecoder, DecodingResult};
+use image::jpeg::JPEGDecoder;
+
+
+// reader for tiled (pyramidal) tiff files such as whole-slide images: only
+// the IFD directories and the tiles intersecting a crop are ever read.
+//
+// classic (non Big) tiff with 8-bit samples and no, LZW, deflate or JPEG
+// compression is supported
+
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_PREDICTOR: u16 = 317;
+const TAG_TILE_WIDTH: u16 = 322;
+const TAG_TILE_LENGTH: u16 = 323;
+const TAG_TILE_OFFSETS: u16 = 324;
+const TAG_TILE_BYTE_COUNTS: u16 = 325;
+const TAG_JPEG_TABLES: u16 = 347;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiffLevel {
+    pub page: u32,             // index of the IFD in the file, ie: the vips page
+    pub size: (u32, u32),
+    pub tile_size: (u32, u32),
+    pub chans: u32,
+    pub compression: u16,
+    pub predictor: u16,
+    pub tile_offsets: Vec<u32>,
+    pub tile_byte_counts: Vec<u32>,
+    pub jpeg_tables: Option<Vec<u8>>
+}
+
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+
+struct TiffReader {
+    file: File,
+    little_endian: bool
+}
+
+
+impl TiffReader {
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u16_at(&self, buf: &[u8], offset: usize) -> u16 {
+        match self.little_endian {
+            true  => buf[offset] as u16 | (buf[offset + 1] as u16) << 8,
+            false => (buf[offset] as u16) << 8 | buf[offset + 1] as u16
+        }
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        let (a, b) = (self.u16_at(buf, offset) as u32, self.u16_at(buf, offset + 2) as u32);
+        match self.little_endian {
+            true  => b << 16 | a,
+            false => a << 16 | b
+        }
+    }
+
+    fn entry_values(&mut self, entry: &[u8]) -> io::Result<Vec<u32>> {
+        // values of a BYTE / UNDEFINED, SHORT or LONG entry, inline or at an offset
+        let (kind, count) = (self.u16_at(entry, 2), self.u32_at(entry, 4) as usize);
+        let width = match kind {
+            1 | 7 => 1,
+            3 => 2,
+            4 => 4,
+            _ => return Ok(vec![])
+        };
+
+        let data = match width * count {
+            len if len <= 4 => entry[8..8 + len].to_vec(),
+            len => {
+                let offset = self.u32_at(entry, 8) as u64;
+                self.read_at(offset, len)?
+            }
+        };
+
+        Ok((0..count).map(|i| match width {
+            1 => data[i] as u32,
+            2 => self.u16_at(&data, 2 * i) as u32,
+            _ => self.u32_at(&data, 4 * i)
+        }).collect())
+    }
+}
+
+
+pub fn read_levels(path: &str) -> io::Result<Vec<TiffLevel>> {
+    // walks the IFD chain and returns the tiled directories, largest first
+    let mut reader = TiffReader { file: File::open(path)?, little_endian: true };
+    let header = reader.read_at(0, 8)?;
+    reader.little_endian = match &header[0..2] {
+        b"II" => true,
+        b"MM" => false,
+        _ => return Err(invalid("not a tiff file"))
+    };
+    if reader.u16_at(&header, 2) != 42 {
+        return Err(invalid("only classic tiff files are supported"));
+    }
+
+    let mut levels = vec![];
+    let mut ifd = reader.u32_at(&header, 4) as u64;
+    let mut page = 0;
+    let mut visited = HashSet::new();
+    while ifd != 0 {
+        // a next IFD offset pointing back into the chain would loop forever
+        if !visited.insert(ifd) {
+            return Err(invalid("tiff directory chain loops"));
+        }
+        let count_buf = reader.read_at(ifd, 2)?;
+        let num_entries = reader.u16_at(&count_buf, 0) as usize;
+        let entries = reader.read_at(ifd + 2, 12 * num_entries + 4)?;
+
+        let mut level = TiffLevel { page: page, size: (0, 0), tile_size: (0, 0), chans: 1,
+                                    compression: 1, predictor: 1, tile_offsets: vec![],
+                                    tile_byte_counts: vec![], jpeg_tables: None };
+        let mut bits = 8;
+        for i in 0..num_entries {
+            let entry = &entries[12 * i..12 * (i + 1)];
+            let tag = reader.u16_at(entry, 0);
+            let values = reader.entry_values(entry)?;
+            let first = values.get(0).cloned().unwrap_or(0);
+            match tag {
+                TAG_IMAGE_WIDTH => level.size.0 = first,
+                TAG_IMAGE_LENGTH => level.size.1 = first,
+                TAG_BITS_PER_SAMPLE => bits = first,
+                TAG_COMPRESSION => level.compression = first as u16,
+                TAG_SAMPLES_PER_PIXEL => level.chans = first,
+                TAG_PREDICTOR => level.predictor = first as u16,
+                TAG_TILE_WIDTH => level.tile_size.0 = first,
+                TAG_TILE_LENGTH => level.tile_size.1 = first,
+                TAG_TILE_OFFSETS => level.tile_offsets = values,
+                TAG_TILE_BYTE_COUNTS => level.tile_byte_counts = values,
+                TAG_JPEG_TABLES => level.jpeg_tables = Some(values.iter().map(|&v| v as u8).collect()),
+                _ => {}
+            }
+        }
+
+        // strip based directories (eg: slide labels) are skipped
+        if level.tile_size.0 > 0 && level.tile_size.1 > 0 && bits == 8 {
+            if level.size.0 == 0 || level.size.1 == 0 {
+                return Err(invalid("tiled directory with a zero size"));
+            }
+            levels.push(level);
+        }
+
+        ifd = reader.u32_at(&entries, 12 * num_entries) as u64;
+        page += 1;
+    }
+
+    if levels.is_empty() {
+        return Err(invalid("no tiled 8-bit directory found"));
+    }
+    levels.sort_by(|a, b| b.size.0.cmp(&a.size.0));
+    Ok(levels)
+}
+
+
+pub fn select_level(levels: &[TiffLevel], region_size: (u32, u32), window_size: (u32, u32)) -> usize {
+    // the coarsest level on which the full resolution region still spans the window
+    let full = levels[0].size;
+    for (idx, level) in levels.iter().enumerate().rev() {
+        let level_w = region_size.0 as u64 * level.size.0 as u64 / full.0 as u64;
+        let level_h = region_size.1 as u64 * level.size.1 as u64 / full.1 as u64;
+        if level_w >= window_size.0 as u64 && level_h >= window_size.1 as u64 {
+            return idx;
+        }
+    }
+
+    0
+}
+
+
+pub fn level_region(region: (u32, u32, u32, u32), full_size: (u32, u32), level: &TiffLevel) -> (u32, u32, u32, u32) {
+    // maps a full resolution (x, y, width, height) region onto the level
+    let (sx, sy) = (level.size.0 as f64 / full_size.0 as f64, level.size.1 as f64 / full_size.1 as f64);
+    let x = cmp::min((region.0 as f64 * sx) as u32, level.size.0 - 1);
+    let y = cmp::min((region.1 as f64 * sy) as u32, level.size.1 - 1);
+    let w = cmp::min(cmp::max((region.2 as f64 * sx).round() as u32, 1), level.size.0 - x);
+    let h = cmp::min(cmp::max((region.3 as f64 * sy).round() as u32, 1), level.size.1 - y);
+    (x, y, w, h)
+}
+
+
+fn decode_tile(file: &mut File, level: &TiffLevel, index: usize) -> io::Result<Vec<u8>> {
+    let offset = *level.tile_offsets.get(index).ok_or_else(|| invalid("tile index out of range"))?;
+    let byte_count = *level.tile_byte_counts.get(index).ok_or_else(|| invalid("tile index out of range"))?;
+    let mut compressed = vec![0u8; byte_count as usize];
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.read_exact(&mut compressed)?;
+
+    let tile_len = (level.tile_size.0 * level.tile_size.1 * level.chans) as usize;
+    let mut tile = match level.compression {
+        1 => compressed,
+        5 => {
+            let mut decoder = lzw::DecoderEarlyChange::new(lzw::MsbReader::new(), 8);
+            let mut tile = Vec::with_capacity(tile_len);
+            let mut bytes_read = 0;
+            while bytes_read < compressed.len() && tile.len() < tile_len {
+                let (len, bytes) = decoder.decode_bytes(&compressed[bytes_read..])?;
+                if len == 0 {
+                    break;
+                }
+                bytes_read += len;
+                tile.extend_from_slice(bytes);
+            }
+            tile
+        },
+        8 | 32946 => inflate::inflate_bytes_zlib(&compressed).map_err(|e| invalid(&e))?,
+        7 => {
+            // abbreviated streams share the tables stored once in the directory
+            let stream = match level.jpeg_tables {
+                Some(ref tables) if tables.len() > 4 => {
+                    let mut stream = tables[..tables.len() - 2].to_vec();
+                    stream.extend_from_slice(&compressed[2..]);
+                    stream
+                },
+                _ => compressed
+            };
+            match JPEGDecoder::new(io::Cursor::new(stream)).read_image() {
+                Ok(DecodingResult::U8(buf)) => buf,
+                _ => return Err(invalid("failed to decode jpeg tile"))
+            }
+        },
+        other => return Err(invalid(&format!("unsupported tile compression {:?}", other)))
+    };
+
+    // undo horizontal differencing
+    if level.predictor == 2 {
+        let (row_len, chans) = ((level.tile_size.0 * level.chans) as usize, level.chans as usize);
+        for row in tile.chunks_mut(row_len) {
+            for i in chans..row.len() {
+                row[i] = row[i].wrapping_add(row[i - chans]);
+            }
+        }
+    }
+
+    tile.resize(tile_len, 0);
+    Ok(tile)
+}
+
+
+pub fn read_region(path: &str, level: &TiffLevel, region: (u32, u32, u32, u32)) -> io::Result<Vec<u8>> {
+    // decodes the tiles intersecting the (x, y, width, height) level region
+    // and returns it as a [height, width, chans] buffer
+    let mut file = File::open(path)?;
+    let (x, y, width, height) = region;
+    let (tw, th) = level.tile_size;
+    let tiles_across = (level.size.0 + tw - 1) / tw;
+    let chans = level.chans as usize;
+    let mut out = vec![0u8; (width * height) as usize * chans];
+
+    for tile_y in y / th..(y + height - 1) / th + 1 {
+        for tile_x in x / tw..(x + width - 1) / tw + 1 {
+            let tile = decode_tile(&mut file, level, (tile_y * tiles_across + tile_x) as usize)?;
+
+            // copy the intersection of the tile and the region row by row
+            let (x0, x1) = (cmp::max(x, tile_x * tw), cmp::min(x + width, (tile_x + 1) * tw));
+            let (y0, y1) = (cmp::max(y, tile_y * th), cmp::min(y + height, (tile_y + 1) * th));
+            let row_len = (x1 - x0) as usize * chans;
+            for row in y0..y1 {
+                let src = (((row - tile_y * th) * tw + (x0 - tile_x * tw)) as usize) * chans;
+                let dst = (((row - y) * width + (x0 - x)) as usize) * chans;
+                out[dst..dst + row_len].copy_from_slice(&tile[src..src + row_len]);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+
+#[cfg(test)]
+pub fn write_test_tiff(path: &str, levels: &[(u32, u32, &[u8])], tile: u32) {
+    // little endian, uncompressed, 8-bit gray, one tiled IFD per level
+    use std::io::Write;
+    let mut data = b"II\x2a\0".to_vec();
+    data.extend_from_slice(&[0; 4]);
+    let mut prev_next = 4;
+    for &(w, h, pixels) in levels {
+        let (tiles_across, tiles_down) = ((w + tile - 1) / tile, (h + tile - 1) / tile);
+        let mut offsets = vec![];
+        for ty in 0..tiles_down {
+            for tx in 0..tiles_across {
+                offsets.push(data.len() as u32);
+                for y in ty * tile..(ty + 1) * tile {
+                    for x in tx * tile..(tx + 1) * tile {
+                        data.push(if x < w && y < h { pixels[(y * w + x) as usize] } else { 0 });
+                    }
+                }
+            }
+        }
+
+        let num_tiles = offsets.len() as u32;
+        let offsets_at = data.len() as u32;
+        for o in &offsets { data.extend_from_slice(&o.to_le_bytes()); }
+        let counts_at = data.len() as u32;
+        for _ in 0..num_tiles { data.extend_from_slice(&(tile * tile).to_le_bytes()); }
+
+        let ifd = data.len() as u32;
+        data[prev_next..prev_next + 4].copy_from_slice(&ifd.to_le_bytes());
+        let single = num_tiles == 1;
+        let entries: Vec<(u16, u16, u32, u32)> = vec![
+            (TAG_IMAGE_WIDTH, 4, 1, w), (TAG_IMAGE_LENGTH, 4, 1, h), (TAG_BITS_PER_SAMPLE, 3, 1, 8),
+            (TAG_COMPRESSION, 3, 1, 1), (TAG_SAMPLES_PER_PIXEL, 3, 1, 1),
+            (TAG_TILE_WIDTH, 3, 1, tile), (TAG_TILE_LENGTH, 3, 1, tile),
+            (TAG_TILE_OFFSETS, 4, num_tiles, if single { offsets[0] } else { offsets_at }),
+            (TAG_TILE_BYTE_COUNTS, 4, num_tiles, if single { tile * tile } else { counts_at })];
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, kind, count, value) in entries {
+            // SHORT values sit left justified in the value field
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&kind.to_le_bytes());
+            data.extend_from_slice(&count.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        prev_next = data.len();
+        data.extend_from_slice(&[0; 4]);
+    }
+
+    File::create(path).unwrap().write_all(&data).unwrap();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiled_levels_and_regions() {
+        // pixel values (x + y) of each level so that region reads can be checked
+        let ramp = |w: u32, h: u32| -> Vec<u8> { (0..h).flat_map(|y| (0..w).map(move |x| (x + y) as u8)).collect() };
+        let (full, half) = (ramp(64, 48), ramp(32, 24));
+        let path = "assets/test_tiled.tif";
+        write_test_tiff(path, &[(64, 48, &full), (32, 24, &half)], 16);
+        let levels = read_levels(path).unwrap();
+        assert!(levels.len() == 2 && levels[0].size == (64, 48) && levels[1].page == 1);
+
+        // a region that spans four tiles of the full resolution level
+        let region = read_region(path, &levels[0], (14, 15, 4, 3)).unwrap();
+        ::std::fs::remove_file(path).unwrap();
+        let expected: Vec<u8> = (15..18).flat_map(|y| (14..18).map(move |x| (x + y) as u8)).collect();
+        assert!(region == expected, "region was {:?}", region);
+
+        // large crops prefer the coarse level, small ones need full resolution
+        assert!(select_level(&levels, (64, 48), (32, 24)) == 1);
+        assert!(select_level(&levels, (32, 24), (32, 24)) == 0);
+        assert!(level_region((32, 24, 32, 24), (64, 48), &levels[1]) == (16, 12, 16, 12));
+    }
+
+    #[test]
+    fn test_malformed_tiff() {
+        use std::io::Write;
+        let path = "assets/test_malformed.tif";
+        let pixels = vec![7u8; 16 * 16];
+
+        // the last directory links back to the first one
+        write_test_tiff(path, &[(16, 16, &pixels)], 16);
+        let mut data = std::fs::read(path).unwrap();
+        let (first, end) = (data[4..8].to_vec(), data.len());
+        data[end - 4..].copy_from_slice(&first);
+        File::create(path).unwrap().write_all(&data).unwrap();
+        assert!(read_levels(path).is_err());
+
+        write_test_tiff(path, &[(0, 16, &[])], 16);
+        assert!(read_levels(path).is_err());
+        ::std::fs::remove_file(path).unwrap();
+    }
+}
@@ -6,45 +6,102 @@ use std::marker::PhantomData;
 use std::error::Error;
 use libc::{size_t, c_char, c_uchar, c_void, c_double, c_longlong};
 use vips_ffi::{VipsInstance, VipsImage};
-use vips_sys::VipsAccess;
+use vips_sys::{VipsAccess, VipsExtend, VipsDirection, VipsAngle, VipsKernel, VipsBandFormat};
+use boxes::{CropBox, BoxUnits, ResizeMode};
+use warp::{Affine, Homography, BorderMode};
+use sampling::CropSampler;
+use rng::Rng;
+use augment::{Augment, GeometricAug};
+use paired;
+use paired::{Stream, ResizeFilter};
+use depth;
+use depth::Output;
+use orient;
+use sniff;
+use save;
+use batch;
+use save::SaveFormat;
+use tiled;
 
 
-pub fn vips_crop_and_resize(path: &str, scale: f32, x_crop: f32, y_crop: f32,
-                            max_img_percent: f32, resize_width: u32, resize_height: u32) -> Vec<u8>
-{
-    assert!(x_crop >= 0f32 && x_crop <= 1f32, "x of crop not bounded in [0, 1]");
-    assert!(y_crop >= 0f32 && y_crop <= 1f32, "y of crop not bounded in [0, 1]");
-    let null_ptr: *const i32 = ptr::null();
+pub fn vips_open_image<'a>(path: &str, access: VipsAccess) -> VipsImage<'a> {
+    vips_open_image_page(path, access, 0)
+}
 
-    // load the image and grab the image dimensions
-    let mut img = VipsImage::from_file(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL).unwrap();
-    let img_size = (img.width(), img.height());
 
-    // scale the x and y co-ordinates to the img_size
-    let mut x = super::scale_range(x_crop, 0f32, img_size.0 as f32) as u32;
-    let mut y = super::scale_range(y_crop, 0f32, img_size.1 as f32) as u32;
+pub fn vips_open_image_page<'a>(path: &str, access: VipsAccess, page: u32) -> VipsImage<'a> {
+    // loads the page upright according to its EXIF orientation; rotating
+    // needs random access so the requested access only holds for upright files
+    let transform = orient::transform(path);
+    let access = match transform {
+        Some(_) => VipsAccess::VIPS_ACCESS_RANDOM,
+        None => access
+    };
+
+    // only the multi-page loaders know the page option; libvips picks its
+    // loader from the content, so a failure on unknown content reports the
+    // same format error as the image backend
+    let loaded = match page {
+        0 => VipsImage::from_file(path, access),
+        _ => VipsImage::from_file_page(path, access, page)
+    };
+    let img = loaded.unwrap_or_else(|err| match sniff::detect_format(Path::new(path)) {
+        Err(format_err) => panic!("{}", format_err),
+        Ok(_) => panic!("{}", err)
+    });
+    match transform {
+        Some(aug) => vips_apply_geometric(img, &aug),
+        None => img
+    }
+}
+
 
-    // calculate the scale of the true crop using the provided scale
-    // NOTE: this is different from the return size, i.e. window_size
-    let crop_scale = scale.min(max_img_percent);
-    let crop_size = ((img_size.0 as f32 * crop_scale).floor().max(2.0) as u32,
-                     (img_size.1 as f32 * crop_scale).floor().max(2.0) as u32);
-    let max_coords = (img_size.0 - crop_size.0,
-                      img_size.1 - crop_size.1);
+pub fn vips_probe_image(path: &str) -> Option<(u32, u32, u32)> {
+    // the upright (width, height, bands) of an image libvips can open. opening is
+    // lazy, only the header is read until pixels are accessed
+    let img = VipsImage::from_file(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL).ok()?;
+    let size = orient::oriented_size((img.width(), img.height()), orient::transform(path));
+    Some((size.0, size.1, img.bands()))
+}
 
-    // threshold the max x and y
-    x = x.min(max_coords.0);
-    y = y.min(max_coords.1);
-    // println!("x= {} | y= {} | w = {} | h = {} | rw = {} | rh = {} " , x as i32, y as i32,
-    //          crop_size.0 as i32, crop_size.1 as i32, resize_width, resize_height);
+
+pub fn vips_crop_and_resize(path: &str, scale: f32, x_crop: f32, y_crop: f32,
+                            max_img_percent: f32, resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // load the image and grab the crop region
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL);
+    let (x, y, width, height) = super::crop_region((img.width(), img.height()), scale, x_crop, y_crop,
+                                                   max_img_percent);
 
     // truncate the image, resize it and return a Vec<u8>
-    let crop = img.crop(x as i32, y as i32, crop_size.0 as i32, crop_size.1 as i32).unwrap();
+    let crop = img.crop(x as i32, y as i32, width as i32, height as i32).unwrap();
     let resized = crop.resize_to_size(resize_width, Some(resize_height), None).unwrap();
     resized.to_vec()
 }
 
 
+pub fn vips_crop_and_save(path: &str, scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                          window_size: u32, filter: Option<ResizeFilter>, out_path: &str,
+                          format: SaveFormat) -> bool
+{
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL);
+    let (x, y, width, height) = super::crop_region((img.width(), img.height()), scale, x_crop, y_crop,
+                                                   max_img_percent);
+
+    let crop = img.crop(x as i32, y as i32, width as i32, height as i32).unwrap();
+    let resized = crop.resize_to_size(window_size, Some(window_size), filter.map(vips_kernel)).unwrap();
+    resized.write_to_file(format!("{}{}", out_path, format.vips_options())).is_ok()
+}
+
+
+pub fn execute_save_job(job: &super::SaveJob) -> usize {
+    save::run(job, |path, scale, x, y, out_path| {
+        vips_crop_and_save(path, scale, x, y, job.max_img_percent, job.window_size, job.filter, out_path,
+                           job.format)
+    })
+}
+
+
 pub fn execute_job(job: &super::Job){
     parallel_crop_and_resize(job.image_paths_ptr,
                              job.return_ptr,
@@ -128,6 +185,549 @@ pub fn parallel_crop_and_resize(image_paths_ptr: *const *const c_char,
 }
 
 
+pub fn vips_crop_box_and_resize(path: &str, bbox: &CropBox, units: BoxUnits, padding: f32,
+                                mode: ResizeMode, resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL);
+    vips_crop_image_box(&img, bbox, units, padding, mode, resize_width, resize_height)
+}
+
+
+pub fn vips_crop_image_box(img: &VipsImage, bbox: &CropBox, units: BoxUnits, padding: f32,
+                           mode: ResizeMode, resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // convert the box to a pixel region
+    let (x, y, width, height) = super::boxes::box_to_region(bbox, units, padding,
+                                                            (img.width(), img.height()));
+
+    // truncate the image, resize it and return a Vec<u8>, letterboxing if requested
+    let crop = img.crop(x as i32, y as i32, width as i32, height as i32).unwrap();
+    match mode {
+        ResizeMode::Stretch => crop.resize_to_size(resize_width, Some(resize_height), None).unwrap().to_vec(),
+        ResizeMode::Letterbox => {
+            let size = super::boxes::letterbox_size((width, height), (resize_width, resize_height));
+            let resized = crop.resize_to_size(size.0, Some(size.1), None).unwrap();
+            super::boxes::letterbox(&resized.to_vec(), size,
+                                    (resize_width, resize_height), resized.bands())
+        }
+    }
+}
+
+
+pub fn execute_box_job(job: &super::BoxJob){
+    parallel_crop_boxes_and_resize(job.image_paths_ptr,
+                                   job.return_ptr,
+                                   job.boxes_ptr,
+                                   job.units,
+                                   job.padding,
+                                   job.mode,
+                                   job.window_size,
+                                   job.chans,
+                                   job.length)
+}
+
+pub fn parallel_crop_boxes_and_resize(image_paths_ptr: *const *const c_char,
+                                      return_ptr: *mut u8,
+                                      boxes_ptr: *const f32,
+                                      units: BoxUnits,
+                                      padding: f32,
+                                      mode: ResizeMode,
+                                      window_size: u32,
+                                      chans: u32,
+                                      length: size_t)
+{
+    // accepts list of image-paths (str), a [N, 4] array of (x0, y0, x1, y1) boxes
+    // and the size of the arrays (i.e. batch dim) and returns crops of all the images
+    assert!(!boxes_ptr.is_null(), "can't operate over null box vector");
+    let box_values: Vec<CropBox> = unsafe { slice::from_raw_parts(boxes_ptr, 4 * length as usize) }
+        .chunks(4).map(CropBox::from_slice).collect();
+
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        vips_crop_box_and_resize(path, &box_values[idx], units, padding, mode, window_size, window_size)
+    });
+}
+
+
+pub fn vips_affine_crop(path: &str, matrix: &Affine, border: BorderMode, fill: f32,
+                        resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // matrix maps continuous output co-ordinates to the source (see warp);
+    // vips_affine wants the forward transform between pixel indices, so shift
+    // by half a pixel on both sides and invert it
+    let index_matrix = [matrix[0], matrix[1], matrix[2] + 0.5 * (matrix[0] + matrix[1]) - 0.5,
+                        matrix[3], matrix[4], matrix[5] + 0.5 * (matrix[3] + matrix[4]) - 0.5];
+    let forward = super::warp::invert_affine(&index_matrix).expect("affine matrix is not invertible");
+
+    // NOTE: vips mirrors including the edge pixel, unlike warp's reflect101
+    let extend = match border {
+        BorderMode::Constant  => VipsExtend::VIPS_EXTEND_BACKGROUND,
+        BorderMode::Replicate => VipsExtend::VIPS_EXTEND_COPY,
+        BorderMode::Reflect   => VipsExtend::VIPS_EXTEND_MIRROR
+    };
+
+    // affine needs random access to the source
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_RANDOM);
+    let warped = img.affine([forward[0] as f64, forward[1] as f64, forward[3] as f64, forward[4] as f64],
+                            (forward[2] as f64, forward[5] as f64),
+                            resize_width, resize_height, extend, fill as f64).unwrap();
+    warped.to_vec()
+}
+
+
+pub fn parallel_affine_crop(image_paths_ptr: *const *const c_char,
+                            return_ptr: *mut u8,
+                            matrices: &[Affine],
+                            border: BorderMode,
+                            fill: f32,
+                            window_size: u32,
+                            chans: u32,
+                            length: size_t)
+{
+    // accepts list of image-paths (str), one affine matrix per image
+    // and the size of the arrays (i.e. batch dim) and returns crops of all the images
+    assert!(matrices.len() == length as usize, "matrices [{:?}] != length [{:?}]",
+            matrices.len(), length);
+
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        vips_affine_crop(path, &matrices[idx], border, fill, window_size, window_size)
+    });
+}
+
+
+pub fn vips_perspective_crop(path: &str, homography: &Homography, border: BorderMode, fill: f32,
+                             resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // vips decodes, the sampling is shared with piston so both backends
+    // rectify (and fill the border) identically
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL);
+    let img_size = (img.width(), img.height());
+    super::warp::warp_perspective(&img.to_vec(), img_size, img.bands(),
+                                  homography, (resize_width, resize_height), border, fill)
+}
+
+
+pub fn parallel_perspective_crop(image_paths_ptr: *const *const c_char,
+                                 return_ptr: *mut u8,
+                                 homographies: &[Homography],
+                                 border: BorderMode,
+                                 fill: f32,
+                                 window_size: u32,
+                                 chans: u32,
+                                 length: size_t)
+{
+    // accepts list of image-paths (str), one homography per image
+    // and the size of the arrays (i.e. batch dim) and returns crops of all the images
+    assert!(homographies.len() == length as usize, "homographies [{:?}] != length [{:?}]",
+            homographies.len(), length);
+
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        vips_perspective_crop(path, &homographies[idx], border, fill, window_size, window_size)
+    });
+}
+
+
+pub fn vips_subpixel_crop_and_resize(path: &str, scale: f32, x_crop: f32, y_crop: f32,
+                                     max_img_percent: f32, resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // continuous version of vips_crop_and_resize, see warp::subpixel_crop
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL);
+    let img_size = (img.width(), img.height());
+    super::warp::subpixel_crop(&img.to_vec(), img_size, img.bands(),
+                               scale, x_crop, y_crop, max_img_percent,
+                               (resize_width, resize_height))
+}
+
+
+pub fn execute_subpixel_job(job: &super::Job){
+    parallel_subpixel_crop_and_resize(job.image_paths_ptr,
+                                      job.return_ptr,
+                                      job.scale_ptr,
+                                      job.x_ptr,
+                                      job.y_ptr,
+                                      job.window_size,
+                                      job.chans,
+                                      job.max_img_percent,
+                                      job.length)
+}
+
+
+pub fn parallel_subpixel_crop_and_resize(image_paths_ptr: *const *const c_char,
+                                         return_ptr: *mut u8,
+                                         scale_ptr: *const f32,
+                                         x_ptr: *const f32,
+                                         y_ptr: *const f32,
+                                         window_size: u32,
+                                         chans: u32,
+                                         max_img_percent: f32,
+                                         length: size_t)
+{
+    // same contract as parallel_crop_and_resize, but bilinearly sampled
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        vips_subpixel_crop_and_resize(path, scale_values[idx], x_values[idx], y_values[idx],
+                                      max_img_percent, window_size, window_size)
+    });
+}
+
+
+pub fn vips_random_crop_and_resize(path: &str, sampler: &CropSampler, rng: &mut Rng,
+                                   resize_width: u32, resize_height: u32) -> (Vec<u8>, CropBox)
+{
+    // sample a box for this image and crop it, returning the normalized box
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL);
+    let img_size = (img.width(), img.height());
+    let bbox = super::sampling::sample_box(sampler, img_size, rng);
+    let crop = vips_crop_image_box(&img, &bbox, BoxUnits::Pixel, 0.0, ResizeMode::Stretch,
+                                   resize_width, resize_height);
+    (crop, super::sampling::normalize_box(&bbox, img_size))
+}
+
+
+pub fn parallel_random_crop_and_resize(image_paths_ptr: *const *const c_char,
+                                       return_ptr: *mut u8,
+                                       params_ptr: *mut f32,
+                                       sampler: &CropSampler,
+                                       seed: u64,
+                                       window_size: u32,
+                                       chans: u32,
+                                       length: size_t)
+{
+    // accepts list of image-paths (str) and the size of the arrays (i.e. batch dim),
+    // samples a crop per image from (seed, index) and returns the crops along
+    // with the sampled normalized (x0, y0, x1, y1) boxes in params_ptr
+    assert!(!params_ptr.is_null(), "can't operate over null params vector");
+    let win_size = (window_size * window_size * chans) as usize;
+    let boxes = batch::run_with(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        let mut rng = Rng::for_op(::rng::RANDOM_CROP, seed, idx);
+        vips_random_crop_and_resize(path, sampler, &mut rng, window_size, window_size)
+    });
+
+    // copy the boxes into the params
+    let params = unsafe { slice::from_raw_parts_mut(params_ptr, 4 * length as usize) };
+    for (param, bbox) in params.chunks_mut(4).zip(boxes) {
+        param.copy_from_slice(&[bbox.x0, bbox.y0, bbox.x1, bbox.y1]);
+    }
+}
+
+
+pub fn vips_apply_geometric<'a>(img: VipsImage<'a>, aug: &GeometricAug) -> VipsImage<'a> {
+    // flips first, then the clockwise quarter turns
+    let img = if aug.hflip { img.flip(VipsDirection::VIPS_DIRECTION_HORIZONTAL).unwrap() } else { img };
+    let img = if aug.vflip { img.flip(VipsDirection::VIPS_DIRECTION_VERTICAL).unwrap() } else { img };
+    match aug.rot90 {
+        1 => img.rot(VipsAngle::VIPS_ANGLE_D90).unwrap(),
+        2 => img.rot(VipsAngle::VIPS_ANGLE_D180).unwrap(),
+        3 => img.rot(VipsAngle::VIPS_ANGLE_D270).unwrap(),
+        _ => img
+    }
+}
+
+
+pub fn vips_crop_and_augment(path: &str, scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                             resize_width: u32, resize_height: u32, augment: &Augment) -> Vec<u8>
+{
+    // load the image and grab the crop region
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL);
+    let (x, y, width, height) = super::crop_region((img.width(), img.height()), scale, x_crop, y_crop,
+                                                   max_img_percent);
+
+    // truncate the image, resize it and augment the resized window
+    let crop = img.crop(x as i32, y as i32, width as i32, height as i32).unwrap();
+    let resized = crop.resize_to_size(resize_width, Some(resize_height), None).unwrap();
+    let augmented = vips_apply_geometric(resized, &augment.geometric);
+    let mut pixels = augmented.to_vec();
+    super::augment::apply_photometric(&mut pixels, (augmented.width(), augmented.height()),
+                                      augmented.bands(), &augment.photometric);
+    super::augment::apply_erase(&mut pixels, (augmented.width(), augmented.height()),
+                                augmented.bands(), &augment.erase);
+    pixels
+}
+
+
+pub fn execute_augment_job(job: &super::AugmentJob){
+    parallel_crop_and_augment(job.base.image_paths_ptr,
+                              job.base.return_ptr,
+                              job.base.scale_ptr,
+                              job.base.x_ptr,
+                              job.base.y_ptr,
+                              job.base.window_size,
+                              job.base.chans,
+                              job.base.max_img_percent,
+                              &job.augments,
+                              job.base.length)
+}
+
+
+pub fn parallel_crop_and_augment(image_paths_ptr: *const *const c_char,
+                                 return_ptr: *mut u8,
+                                 scale_ptr: *const f32,
+                                 x_ptr: *const f32,
+                                 y_ptr: *const f32,
+                                 window_size: u32,
+                                 chans: u32,
+                                 max_img_percent: f32,
+                                 augments: &[Augment],
+                                 length: size_t)
+{
+    // same contract as parallel_crop_and_resize plus one set of augmentations per image
+    assert!(augments.len() == length as usize, "augments [{:?}] != length [{:?}]",
+            augments.len(), length);
+
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        vips_crop_and_augment(path, scale_values[idx], x_values[idx], y_values[idx],
+                              max_img_percent, window_size, window_size, &augments[idx])
+    });
+}
+
+
+
+fn vips_kernel(filter: ResizeFilter) -> VipsKernel {
+    match filter {
+        ResizeFilter::Nearest  => VipsKernel::VIPS_KERNEL_NEAREST,
+        ResizeFilter::Bilinear => VipsKernel::VIPS_KERNEL_LINEAR,
+        ResizeFilter::Bicubic  => VipsKernel::VIPS_KERNEL_CUBIC,
+        ResizeFilter::Lanczos3 => VipsKernel::VIPS_KERNEL_LANCZOS3
+    }
+}
+
+
+pub fn vips_paired_crop_and_resize(paths: &[&str], scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                                   filters: &[ResizeFilter], resize_width: u32, resize_height: u32) -> Vec<Vec<u8>>
+{
+    // every stream is cropped with the region computed for the first one
+    let imgs: Vec<VipsImage> = paths.iter()
+        .map(|path| vips_open_image(*path, VipsAccess::VIPS_ACCESS_SEQUENTIAL))
+        .collect();
+    let img_size = (imgs[0].width(), imgs[0].height());
+    let (x, y, width, height) = super::crop_region(img_size, scale, x_crop, y_crop, max_img_percent);
+
+    imgs.iter().zip(paths).zip(filters).map(|((img, path), filter)| {
+        assert!((img.width(), img.height()) == img_size, "{:?} is {:?} but its pair is {:?}",
+                path, (img.width(), img.height()), img_size);
+        let crop = img.crop(x as i32, y as i32, width as i32, height as i32).unwrap();
+        let resized = crop.resize_to_size(resize_width, Some(resize_height), Some(vips_kernel(*filter))).unwrap();
+        resized.to_vec()
+    }).collect()
+}
+
+
+pub fn execute_paired_job(job: &super::PairedJob){
+    parallel_paired_crop_and_resize(&job.streams,
+                                    job.scale_ptr,
+                                    job.x_ptr,
+                                    job.y_ptr,
+                                    job.window_size,
+                                    job.max_img_percent,
+                                    job.length)
+}
+
+
+pub fn parallel_paired_crop_and_resize(streams: &[Stream],
+                                       scale_ptr: *const f32,
+                                       x_ptr: *const f32,
+                                       y_ptr: *const f32,
+                                       window_size: u32,
+                                       max_img_percent: f32,
+                                       length: size_t)
+{
+    // accepts one list of image-paths per stream, a vector (np) of z's shared by
+    // all the streams and writes the aligned crops into every stream's return array
+    assert!(!scale_ptr.is_null(), "can't operate over null scale vector");
+    assert!(!x_ptr.is_null(), "can't operate over null x vector");
+    assert!(!y_ptr.is_null(), "can't operate over null y vector");
+    assert!(!streams.is_empty(), "can't operate over zero streams");
+    for stream in streams {
+        assert!(!stream.return_ptr.is_null(), "can't operate over null result vector");
+    }
+
+    // gather the paths of every stream and the z into arrays of [scale, x, y]
+    let stream_paths: Vec<Vec<&str>> = streams.iter().map(|s| paired::stream_paths(s, length)).collect();
+    let filters: Vec<ResizeFilter> = streams.iter().map(|s| s.filter).collect();
+    let scale_values = unsafe { slice::from_raw_parts(scale_ptr, length as usize) };
+    let x_values = unsafe { slice::from_raw_parts(x_ptr, length as usize) };
+    let y_values = unsafe { slice::from_raw_parts(y_ptr, length as usize) };
+
+    let mut resultant_vec = vec![];
+    (0..length as usize).into_par_iter()
+        .map(|idx| {
+            let paths: Vec<&str> = stream_paths.iter().map(|p| p[idx]).collect();
+            vips_paired_crop_and_resize(&paths,
+                                        scale_values[idx], x_values[idx], y_values[idx],
+                                        max_img_percent, &filters,
+                                        window_size, window_size)
+        }).collect_into_vec(&mut resultant_vec);
+
+    // copy every stream's window into its return array
+    for (idx, windows) in resultant_vec.iter().enumerate() {
+        for (stream, window) in streams.iter().zip(windows) {
+            paired::write_window(stream, idx, window, window_size);
+        }
+    }
+}
+
+
+pub fn vips_crop_and_resize_depth(path: &str, scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                                  resize_width: u32, resize_height: u32, output: Output) -> Vec<f32>
+{
+    // load the image and grab the crop region
+    let img = vips_open_image(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL);
+    let (x, y, width, height) = super::crop_region((img.width(), img.height()), scale, x_crop, y_crop,
+                                                   max_img_percent);
+
+    // resize in the source format and only then cast to float
+    let crop = img.crop(x as i32, y as i32, width as i32, height as i32).unwrap();
+    let resized = crop.resize_to_size(resize_width, Some(resize_height), None).unwrap();
+    let mut window = resized.cast(VipsBandFormat::VIPS_FORMAT_FLOAT).unwrap().to_vec_f32();
+    depth::apply_output(&mut window, output);
+    window
+}
+
+
+pub fn execute_depth_job(job: &super::DepthJob){
+    parallel_crop_and_resize_depth(job.image_paths_ptr,
+                                   job.return_ptr,
+                                   job.scale_ptr,
+                                   job.x_ptr,
+                                   job.y_ptr,
+                                   job.window_size,
+                                   job.chans,
+                                   job.max_img_percent,
+                                   job.output,
+                                   job.length)
+}
+
+
+pub fn parallel_crop_and_resize_depth(image_paths_ptr: *const *const c_char,
+                                      return_ptr: *mut c_void,
+                                      scale_ptr: *const f32,
+                                      x_ptr: *const f32,
+                                      y_ptr: *const f32,
+                                      window_size: u32,
+                                      chans: u32,
+                                      max_img_percent: f32,
+                                      output: Output,
+                                      length: size_t)
+{
+    // same contract as parallel_crop_and_resize, but return_ptr holds the dtype of output
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    depth::run(image_paths_ptr, return_ptr, output, win_size, length, |idx, path| {
+        vips_crop_and_resize_depth(path, scale_values[idx], x_values[idx], y_values[idx],
+                                   max_img_percent, window_size, window_size, output)
+    });
+}
+
+
+pub fn vips_crop_and_resize_frame(path: &str, frame: u32, scale: f32, x_crop: f32, y_crop: f32,
+                                  max_img_percent: f32, resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // vips_crop_and_resize over a page of a multi-page tiff or a frame of an animated gif
+    let img = vips_open_image_page(path, VipsAccess::VIPS_ACCESS_SEQUENTIAL, frame);
+    let (x, y, width, height) = super::crop_region((img.width(), img.height()), scale, x_crop, y_crop,
+                                                   max_img_percent);
+    let crop = img.crop(x as i32, y as i32, width as i32, height as i32).unwrap();
+    let resized = crop.resize_to_size(resize_width, Some(resize_height), None).unwrap();
+    resized.to_vec()
+}
+
+
+pub fn execute_frame_job(job: &super::FrameJob){
+    parallel_crop_and_resize_frames(job.base.image_paths_ptr,
+                                    job.base.return_ptr,
+                                    job.base.scale_ptr,
+                                    job.base.x_ptr,
+                                    job.base.y_ptr,
+                                    job.base.window_size,
+                                    job.base.chans,
+                                    job.base.max_img_percent,
+                                    &job.frames,
+                                    job.base.length)
+}
+
+
+pub fn parallel_crop_and_resize_frames(image_paths_ptr: *const *const c_char,
+                                       return_ptr: *mut u8,
+                                       scale_ptr: *const f32,
+                                       x_ptr: *const f32,
+                                       y_ptr: *const f32,
+                                       window_size: u32,
+                                       chans: u32,
+                                       max_img_percent: f32,
+                                       frames: &[u32],
+                                       length: size_t)
+{
+    // same contract as parallel_crop_and_resize plus the page / frame to read per image
+    assert!(frames.len() == length as usize, "frames [{:?}] != length [{:?}]",
+            frames.len(), length);
+
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        vips_crop_and_resize_frame(path, frames[idx], scale_values[idx], x_values[idx], y_values[idx],
+                                   max_img_percent, window_size, window_size)
+    });
+}
+
+
+pub fn vips_tiled_crop_and_resize(path: &str, scale: f32, x_crop: f32, y_crop: f32, max_img_percent: f32,
+                                  resize_width: u32, resize_height: u32) -> Vec<u8>
+{
+    // the level is picked from the tiff directories, vips then only reads
+    // the tiles of that page that the crop touches
+    let levels = tiled::read_levels(path).unwrap();
+    let full_size = levels[0].size;
+    let region = super::crop_region(full_size, scale, x_crop, y_crop, max_img_percent);
+    let level = &levels[tiled::select_level(&levels, (region.2, region.3), (resize_width, resize_height))];
+    let (x, y, width, height) = tiled::level_region(region, full_size, level);
+
+    let img = match level.page {
+        0 => VipsImage::from_file(path, VipsAccess::VIPS_ACCESS_RANDOM).unwrap(),
+        page => VipsImage::from_file_page(path, VipsAccess::VIPS_ACCESS_RANDOM, page).unwrap()
+    };
+    let crop = img.crop(x as i32, y as i32, width as i32, height as i32).unwrap();
+    let resized = crop.resize_to_size(resize_width, Some(resize_height), None).unwrap();
+    resized.to_vec()
+}
+
+
+pub fn execute_tiled_job(job: &super::Job){
+    parallel_tiled_crop_and_resize(job.image_paths_ptr,
+                                   job.return_ptr,
+                                   job.scale_ptr,
+                                   job.x_ptr,
+                                   job.y_ptr,
+                                   job.window_size,
+                                   job.chans,
+                                   job.max_img_percent,
+                                   job.length)
+}
+
+
+pub fn parallel_tiled_crop_and_resize(image_paths_ptr: *const *const c_char,
+                                      return_ptr: *mut u8,
+                                      scale_ptr: *const f32,
+                                      x_ptr: *const f32,
+                                      y_ptr: *const f32,
+                                      window_size: u32,
+                                      chans: u32,
+                                      max_img_percent: f32,
+                                      length: size_t)
+{
+    // same contract as parallel_crop_and_resize for tiled pyramidal tiffs
+    let (scale_values, x_values, y_values) = batch::coords(scale_ptr, x_ptr, y_ptr, length);
+    let win_size = (window_size * window_size * chans) as usize;
+    batch::run(image_paths_ptr, return_ptr, win_size, length, |idx, path| {
+        vips_tiled_crop_and_resize(path, scale_values[idx], x_values[idx], y_values[idx],
+                                   max_img_percent, window_size, window_size)
+    });
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -143,6 +743,15 @@ mod tests {
         // destroy_vips();
     }
 
+    #[test]
+    fn test_vips_bw_box_crop() {
+        // a letterboxed 2:1 box keeps the single gray band
+        let bbox = CropBox::new(0.25, 0.25, 0.75, 0.5);
+        let letterboxed = vips_crop_box_and_resize("assets/lena_gray.png", &bbox, BoxUnits::Normalized,
+                                                   0.0, ResizeMode::Letterbox, 32, 32);
+        assert!(letterboxed.len() == 32*32);
+    }
+
     // #[test]
     // fn test_vips_image_crops() {
     //     initialize_vips();
@@ -7,7 +7,7 @@ use std::marker::PhantomData;
 use std::error::Error;
 use std::os::raw::{c_void, c_int, c_char};
 use vips_sys;
-use vips_sys::{VipsSize, VipsKernel, VipsBandFormat, VipsCombineMode, VipsDirection};
+use vips_sys::{VipsSize, VipsKernel, VipsBandFormat, VipsCombineMode, VipsDirection, VipsExtend, VipsAngle};
 
 
 // most of these are straight up copy-pasta from vips-rs
@@ -85,6 +85,21 @@ impl<'a> VipsImage<'a> {
         result(c)
     }
 
+    pub fn from_file_page<S: Into<Vec<u8>>>(path: S, access: vips_sys::VipsAccess,
+                                            page: u32) -> Result<VipsImage<'a>, Box<Error>> {
+        // page of a multi-page tiff or frame of an animated gif
+        let path = CString::new(path)?;
+        let access_str = CString::new("access")?;
+        let page_str = CString::new("page")?;
+        let c = unsafe { vips_sys::vips_image_new_from_file(path.as_ptr(),
+                                                            access_str.as_ptr(),
+                                                            access,
+                                                            page_str.as_ptr(),
+                                                            page as c_int,
+                                                            ptr::null() as *const c_char) };
+        result(c)
+    }
+
     pub fn from_memory(buf: Vec<u8>, width: u32, height: u32,
                        bands: u8, format: VipsBandFormat) -> Result<VipsImage<'a>, Box<Error>> {
         let b:Box<[_]> = buf.into_boxed_slice();
@@ -178,6 +193,92 @@ impl<'a> VipsImage<'a> {
         result_with_ret(out_ptr, ret)
     }
 
+    // forward transform: out = [a b; c d] * in + (odx, ody), sampled bilinearly
+    // into the [0, 0, width, height] output area
+    pub fn affine(&self, matrix: [f64; 4], offset: (f64, f64), width: u32, height: u32,
+                  extend: VipsExtend, background: f64) -> Result<VipsImage, Box<Error>> {
+        let mut out_ptr: *mut vips_sys::VipsImage = ptr::null_mut();
+        let interpolate_name = CString::new("bilinear")?;
+        let oarea = [0, 0, width as c_int, height as c_int];
+        let background = vec![background; self.bands() as usize];
+        let ret = unsafe {
+            let interpolate = vips_sys::vips_interpolate_new(interpolate_name.as_ptr());
+            let oarea = vips_sys::vips_array_int_new(oarea.as_ptr(), oarea.len() as c_int);
+            let background = vips_sys::vips_array_double_new(background.as_ptr(), background.len() as c_int);
+            let ret = vips_sys::vips_affine(self.c as *mut vips_sys::VipsImage,
+                                            &mut out_ptr,
+                                            matrix[0], matrix[1], matrix[2], matrix[3],
+                                            "interpolate\0".as_ptr(), interpolate,
+                                            "oarea\0".as_ptr(), oarea,
+                                            "odx\0".as_ptr(), offset.0,
+                                            "ody\0".as_ptr(), offset.1,
+                                            "extend\0".as_ptr(), extend,
+                                            "background\0".as_ptr(), background,
+                                            ptr::null() as *const c_char);
+
+            // the operation holds its own references
+            vips_sys::g_object_unref(interpolate as *mut c_void);
+            vips_sys::vips_area_unref(oarea as *mut vips_sys::VipsArea);
+            vips_sys::vips_area_unref(background as *mut vips_sys::VipsArea);
+            ret
+        };
+
+        result_with_ret(out_ptr, ret)
+    }
+
+    // the output holds its own reference to the input, hence the 'a lifetime
+    pub fn flip(&self, direction: VipsDirection) -> Result<VipsImage<'a>, Box<Error>> {
+        let mut out_ptr: *mut vips_sys::VipsImage = ptr::null_mut();
+        let ret = unsafe {
+            vips_sys::vips_flip(self.c as *mut vips_sys::VipsImage,
+                                &mut out_ptr,
+                                direction,
+                                ptr::null() as *const c_char)
+        };
+
+        result_with_ret(out_ptr, ret)
+    }
+
+    // clockwise rotation by a multiple of 90 degrees
+    pub fn rot(&self, angle: VipsAngle) -> Result<VipsImage<'a>, Box<Error>> {
+        let mut out_ptr: *mut vips_sys::VipsImage = ptr::null_mut();
+        let ret = unsafe {
+            vips_sys::vips_rot(self.c as *mut vips_sys::VipsImage,
+                               &mut out_ptr,
+                               angle,
+                               ptr::null() as *const c_char)
+        };
+
+        result_with_ret(out_ptr, ret)
+    }
+
+    pub fn cast(&self, format: VipsBandFormat) -> Result<VipsImage<'a>, Box<Error>> {
+        let mut out_ptr: *mut vips_sys::VipsImage = ptr::null_mut();
+        let ret = unsafe {
+            vips_sys::vips_cast(self.c as *mut vips_sys::VipsImage,
+                                &mut out_ptr,
+                                format,
+                                ptr::null() as *const c_char)
+        };
+
+        result_with_ret(out_ptr, ret)
+    }
+
+    pub fn write_to_file<S: Into<Vec<u8>>>(&self, path: S) -> Result<(), Box<Error>> {
+        // the saver and its options come from the name, eg: "out.jpg[Q=90]"
+        let path = CString::new(path)?;
+        let ret = unsafe {
+            vips_sys::vips_image_write_to_file(self.c as *mut vips_sys::VipsImage,
+                                               path.as_ptr(),
+                                               ptr::null() as *const c_char)
+        };
+
+        match ret {
+            0 => Ok(()),
+            _ => Err(current_error().into())
+        }
+    }
+
     pub fn width(&self) -> u32 {
         unsafe { (*self.c).Xsize as u32 }
     }
@@ -186,6 +287,10 @@ impl<'a> VipsImage<'a> {
         unsafe { (*self.c).Ysize as u32 }
     }
 
+    pub fn bands(&self) -> u32 {
+        unsafe { (*self.c).Bands as u32 }
+    }
+
     pub fn to_vec(&self) -> Vec<u8> {
         unsafe {
             let mut result_size: usize = 0;
@@ -197,6 +302,13 @@ impl<'a> VipsImage<'a> {
             vec
         }
     }
+
+    // only meaningful for VIPS_FORMAT_FLOAT images, see cast
+    pub fn to_vec_f32(&self) -> Vec<f32> {
+        self.to_vec().chunks(4)
+            .map(|b| unsafe { ptr::read_unaligned(b.as_ptr() as *const f32) })
+            .collect()
+    }
 }
 
 
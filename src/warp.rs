@@ -0,0 +1,315 @@
+// resampling helpers for crops that are not axis-aligned rectangles.
+//
+// all transforms here map *output* window co-ordinates back to *source*
+// co-ordinates (an inverse map), using continuous co-ordinates where pixel
+// i spans [i, i + 1) and its center sits at i + 0.5
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BorderMode {
+    Constant,   // samples outside the image read the fill value
+    Replicate,  // clamp to the nearest edge pixel
+    Reflect     // mirror the image at its edges
+}
+
+
+impl BorderMode {
+    pub fn from_code(code: u32) -> BorderMode {
+        match code {
+            0 => BorderMode::Constant,
+            1 => BorderMode::Replicate,
+            2 => BorderMode::Reflect,
+            _ => panic!("unknown border mode {:?}", code)
+        }
+    }
+}
+
+
+// [a, b, c, d, e, f] such that src_x = a*u + b*v + c and src_y = d*u + e*v + f
+pub type Affine = [f32; 6];
+
+// row-major 3x3 matrix such that (src_x, src_y, w) = H * (u, v, 1) up to the scale w
+pub type Homography = [f32; 9];
+
+
+fn resolve_border(idx: i64, size: u32, border: BorderMode) -> Option<u32> {
+    // maps a possibly out of bounds index into the image or None for the fill value
+    let size = size as i64;
+    if idx >= 0 && idx < size {
+        return Some(idx as u32);
+    }
+
+    match border {
+        BorderMode::Constant => None,
+        BorderMode::Replicate => Some(idx.max(0).min(size - 1) as u32),
+        BorderMode::Reflect => {
+            // reflect101, eg: 2 1 | 0 1 2 | 1 0
+            if size == 1 {
+                return Some(0);
+            }
+            let period = 2 * (size - 1);
+            let idx = ((idx % period) + period) % period;
+            Some(if idx < size { idx } else { period - idx } as u32)
+        }
+    }
+}
+
+
+pub fn sample_bilinear(src: &[u8], src_size: (u32, u32), chans: u32,
+                       x: f32, y: f32, border: BorderMode, fill: f32, out: &mut [u8])
+{
+    // bilinearly samples the [h, w, chans] image at the continuous co-ordinate
+    // (x, y) and writes the chans values into out
+    let (xs, ys) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (xs.floor(), ys.floor());
+    let (fx, fy) = (xs - x0, ys - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let xi = [resolve_border(x0, src_size.0, border), resolve_border(x0 + 1, src_size.0, border)];
+    let yi = [resolve_border(y0, src_size.1, border), resolve_border(y0 + 1, src_size.1, border)];
+    let weights = [(1.0 - fx) * (1.0 - fy), fx * (1.0 - fy), (1.0 - fx) * fy, fx * fy];
+    for c in 0..chans as usize {
+        let mut acc = 0f32;
+        for (i, weight) in weights.iter().enumerate() {
+            let val = match (xi[i % 2], yi[i / 2]) {
+                (Some(px), Some(py)) => src[((py * src_size.0 + px) * chans) as usize + c] as f32,
+                _ => fill
+            };
+            acc += weight * val;
+        }
+        out[c] = acc.round().max(0.0).min(255.0) as u8;
+    }
+}
+
+
+pub fn warp<F>(src: &[u8], src_size: (u32, u32), chans: u32, out_size: (u32, u32),
+               border: BorderMode, fill: f32, map: F) -> Vec<u8>
+    where F: Fn(f32, f32) -> (f32, f32)
+{
+    // samples every output pixel center through the inverse map
+    assert!(src.len() == (src_size.0 * src_size.1 * chans) as usize,
+            "src [{:?}] != src_size [{:?}]", src.len(), src_size);
+    let mut window = vec![0u8; (out_size.0 * out_size.1 * chans) as usize];
+    for v in 0..out_size.1 {
+        for u in 0..out_size.0 {
+            let (x, y) = map(u as f32 + 0.5, v as f32 + 0.5);
+            let begin = ((v * out_size.0 + u) * chans) as usize;
+            sample_bilinear(src, src_size, chans, x, y, border, fill,
+                            &mut window[begin..begin + chans as usize]);
+        }
+    }
+
+    window
+}
+
+
+pub fn warp_affine(src: &[u8], src_size: (u32, u32), chans: u32, matrix: &Affine,
+                   out_size: (u32, u32), border: BorderMode, fill: f32) -> Vec<u8>
+{
+    warp(src, src_size, chans, out_size, border, fill, |u, v| {
+        (matrix[0] * u + matrix[1] * v + matrix[2],
+         matrix[3] * u + matrix[4] * v + matrix[5])
+    })
+}
+
+
+pub fn invert_affine(matrix: &Affine) -> Option<Affine> {
+    let det = matrix[0] * matrix[4] - matrix[1] * matrix[3];
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let (a, b, d, e) = (matrix[4] / det, -matrix[1] / det, -matrix[3] / det, matrix[0] / det);
+    Some([a, b, -(a * matrix[2] + b * matrix[5]),
+          d, e, -(d * matrix[2] + e * matrix[5])])
+}
+
+
+pub fn rotated_box_affine(center: (f32, f32), size: (f32, f32), angle: f32,
+                          out_size: (u32, u32)) -> Affine
+{
+    // maps the output window onto a box of the given size (in source pixels)
+    // centered at center and rotated by angle degrees; with y pointing down a
+    // positive angle rotates the box clockwise on screen
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (sx, sy) = (size.0 / out_size.0 as f32, size.1 / out_size.1 as f32);
+    [cos * sx, -sin * sy, center.0 - cos * size.0 / 2.0 + sin * size.1 / 2.0,
+     sin * sx, cos * sy, center.1 - sin * size.0 / 2.0 - cos * size.1 / 2.0]
+}
+
+
+pub fn warp_perspective(src: &[u8], src_size: (u32, u32), chans: u32, homography: &Homography,
+                        out_size: (u32, u32), border: BorderMode, fill: f32) -> Vec<u8>
+{
+    let h = homography;
+    warp(src, src_size, chans, out_size, border, fill, |u, v| {
+        let w = h[6] * u + h[7] * v + h[8];
+        ((h[0] * u + h[1] * v + h[2]) / w,
+         (h[3] * u + h[4] * v + h[5]) / w)
+    })
+}
+
+
+fn solve_linear(mut a: Vec<[f64; 9]>) -> Option<[f64; 8]> {
+    // gaussian elimination with partial pivoting over an 8x8 augmented system
+    let n = a.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+            .unwrap();
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+
+        for row in 0..n {
+            if row != col {
+                let factor = a[row][col] / a[col][col];
+                for k in col..n + 1 {
+                    a[row][k] -= factor * a[col][k];
+                }
+            }
+        }
+    }
+
+    let mut solution = [0f64; 8];
+    for i in 0..n {
+        solution[i] = a[i][n] / a[i][i];
+    }
+    Some(solution)
+}
+
+
+pub fn quad_homography(corners: &[(f32, f32); 4], out_size: (u32, u32)) -> Option<Homography>
+{
+    // maps the output window corners onto the source quadrilateral given as
+    // (top-left, top-right, bottom-right, bottom-left); None for degenerate quads
+    let (w, h) = (out_size.0 as f64, out_size.1 as f64);
+    let window = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)];
+    let mut system = Vec::with_capacity(8);
+    for (&(u, v), &(x, y)) in window.iter().zip(corners.iter()) {
+        let (x, y) = (x as f64, y as f64);
+        system.push([u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x]);
+        system.push([0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y]);
+    }
+
+    solve_linear(system).map(|m| {
+        [m[0] as f32, m[1] as f32, m[2] as f32,
+         m[3] as f32, m[4] as f32, m[5] as f32,
+         m[6] as f32, m[7] as f32, 1.0]
+    })
+}
+
+
+pub fn subpixel_crop_affine(img_size: (u32, u32), scale: f32, x_crop: f32, y_crop: f32,
+                            max_img_percent: f32, out_size: (u32, u32)) -> Affine
+{
+    // same window as crop_and_resize (origin at (x, y) * img_size, side of
+    // min(scale, max_img_percent) * img_size, kept inside the image) but
+    // without truncating the origin or flooring the size
+    assert!(x_crop >= 0f32 && x_crop <= 1f32, "x of crop not bounded in [0, 1]");
+    assert!(y_crop >= 0f32 && y_crop <= 1f32, "y of crop not bounded in [0, 1]");
+    let crop_scale = scale.min(max_img_percent);
+    let crop_size = ((img_size.0 as f32 * crop_scale).max(2.0).min(img_size.0 as f32),
+                     (img_size.1 as f32 * crop_scale).max(2.0).min(img_size.1 as f32));
+    let x = super::scale_range(x_crop, 0f32, img_size.0 as f32).min(img_size.0 as f32 - crop_size.0);
+    let y = super::scale_range(y_crop, 0f32, img_size.1 as f32).min(img_size.1 as f32 - crop_size.1);
+    [crop_size.0 / out_size.0 as f32, 0.0, x,
+     0.0, crop_size.1 / out_size.1 as f32, y]
+}
+
+
+pub fn subpixel_crop(src: &[u8], src_size: (u32, u32), chans: u32, scale: f32, x_crop: f32, y_crop: f32,
+                     max_img_percent: f32, out_size: (u32, u32)) -> Vec<u8>
+{
+    // bilinear sampling of the continuous window with zero padding, ie: the
+    // semantics of grid_sample(mode='bilinear', padding_mode='zeros', align_corners=False)
+    let matrix = subpixel_crop_affine(src_size, scale, x_crop, y_crop, max_img_percent, out_size);
+    warp_affine(src, src_size, chans, &matrix, out_size, BorderMode::Constant, 0.0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_border_modes() {
+        assert!(resolve_border(-1, 4, BorderMode::Constant) == None);
+        assert!(resolve_border(-2, 4, BorderMode::Replicate) == Some(0));
+        assert!(resolve_border(5, 4, BorderMode::Replicate) == Some(3));
+        assert!(resolve_border(-2, 4, BorderMode::Reflect) == Some(2));
+        assert!(resolve_border(4, 4, BorderMode::Reflect) == Some(2));
+    }
+
+    #[test]
+    fn test_identity_and_rotation() {
+        // a 2x2 gray image: the identity keeps it while rotating the box
+        // clockwise turns the content anti-clockwise
+        let src = [10u8, 20, 30, 40];
+        let identity = [1f32, 0.0, 0.0, 0.0, 1.0, 0.0];
+        assert!(warp_affine(&src, (2, 2), 1, &identity, (2, 2), BorderMode::Constant, 0.0) == src.to_vec());
+
+        let rotated = rotated_box_affine((1.0, 1.0), (2.0, 2.0), 90.0, (2, 2));
+        let out = warp_affine(&src, (2, 2), 1, &rotated, (2, 2), BorderMode::Constant, 0.0);
+        assert!(out == vec![20, 40, 10, 30], "rotated was {:?}", out);
+
+        // and the inverse maps back onto the identity
+        let inv = invert_affine(&rotated_box_affine((4.0, 3.0), (2.0, 6.0), 30.0, (8, 8))).unwrap();
+        let fwd = rotated_box_affine((4.0, 3.0), (2.0, 6.0), 30.0, (8, 8));
+        let (u, v) = (3.0, 5.0);
+        let (x, y) = (fwd[0] * u + fwd[1] * v + fwd[2], fwd[3] * u + fwd[4] * v + fwd[5]);
+        assert!((inv[0] * x + inv[1] * y + inv[2] - u).abs() < 1e-4);
+        assert!((inv[3] * x + inv[4] * y + inv[5] - v).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_bilinear_midpoint_and_fill() {
+        let src = [0u8, 100];
+        let mut out = [0u8];
+        sample_bilinear(&src, (2, 1), 1, 1.0, 0.5, BorderMode::Replicate, 0.0, &mut out);
+        assert!(out[0] == 50);
+
+        // half way into the constant border blends with the fill
+        sample_bilinear(&src, (2, 1), 1, 2.5, 0.5, BorderMode::Constant, 200.0, &mut out);
+        assert!(out[0] == 200);
+        sample_bilinear(&src, (2, 1), 1, 2.0, 0.5, BorderMode::Constant, 200.0, &mut out);
+        assert!(out[0] == 150);
+    }
+
+    #[test]
+    fn test_quad_homography() {
+        // the corners of the window land on the corners of the quad
+        let corners = [(10.0, 5.0), (50.0, 0.0), (60.0, 40.0), (0.0, 30.0)];
+        let h = quad_homography(&corners, (16, 8)).unwrap();
+        for (&(u, v), &(x, y)) in [(0f32, 0f32), (16.0, 0.0), (16.0, 8.0), (0.0, 8.0)].iter().zip(corners.iter()) {
+            let w = h[6] * u + h[7] * v + h[8];
+            assert!(((h[0] * u + h[1] * v + h[2]) / w - x).abs() < 1e-3);
+            assert!(((h[3] * u + h[4] * v + h[5]) / w - y).abs() < 1e-3);
+        }
+
+        // an axis-aligned quad is a plain crop and collapsed quads are rejected
+        let src: Vec<u8> = (0..16).collect();
+        let h = quad_homography(&[(1.0, 1.0), (3.0, 1.0), (3.0, 3.0), (1.0, 3.0)], (2, 2)).unwrap();
+        let out = warp_perspective(&src, (4, 4), 1, &h, (2, 2), BorderMode::Constant, 0.0);
+        assert!(out == vec![5, 6, 9, 10], "perspective was {:?}", out);
+        assert!(quad_homography(&[(1.0, 1.0); 4], (2, 2)).is_none());
+    }
+
+    #[test]
+    fn test_subpixel_crop_matches_grid_sample() {
+        // a 4x4 ramp of 40*row + 4*col, bilinear sampling of which is exact
+        let src: Vec<u8> = (0..16u8).map(|i| 40 * (i / 4) + 4 * (i % 4)).collect();
+
+        // a 2x2 window at (0.5, 1.0) upsampled to 4x4; the reference values are
+        // grid_sample(src, grid, align_corners=False) with the grid spanning the window
+        let out = subpixel_crop(&src, (4, 4), 1, 0.5, 0.125, 0.25, 1.0, (4, 4));
+        assert!(out == vec![31, 33, 35, 37,
+                            51, 53, 55, 57,
+                            71, 73, 75, 77,
+                            91, 93, 95, 97], "subpixel was {:?}", out);
+
+        // unlike the integer path, a quarter pixel shift of x moves the window continuously
+        let shifted = subpixel_crop(&src, (4, 4), 1, 0.5, 0.125 + 0.25 / 4.0, 0.25, 1.0, (4, 4));
+        assert!(shifted.iter().zip(&out).all(|(s, o)| *s == *o + 1), "shifted was {:?}", shifted);
+    }
+}